@@ -1,36 +1,398 @@
+mod client;
 mod common;
+mod tui;
 pub mod actions {
     tonic::include_proto!("actions");
 }
 
 use actions::{
-    controller_client::ControllerClient, AuthAction, AuthRequest, BackupRequest, CommandRequest,
-    DownloadRequest, LaunchRequest, StopRequest,
+    controller_client::ControllerClient, AcceptEulaRequest, AddEntryRequest, AuthAction,
+    BackupFile, BackupRequest, BackupsRequest, CommandRequest, ConsoleRequest, ErrorCode,
+    GetPropertiesRequest, InstallModRequest, InstallVersionRequest, InstalledModsRequest,
+    JavaRuntimesRequest, LaunchRequest, ListEntriesRequest, MetricsRequest, MetricsSample,
+    PlayerEventsRequest, PlayerList, PlayersRequest, RegisterJavaRequest, ReloadConfigRequest,
+    RemoveEntryRequest, RemoveModRequest, RestartRequest, SearchModsRequest, ServerPhase,
+    SetPropertiesRequest, StatusRequest, StatusResponce, StopRequest, UpdateModsRequest,
+    VersionsRequest, WatchMetricsRequest,
 };
+use clap::{Parser, Subcommand};
+use client::Config;
 use common::ran_letters;
 use lazy_regex::regex_is_match;
-use magic_crypt::{new_magic_crypt, MagicCryptTrait};
-use serde_derive::Deserialize;
-use std::{fs, io::Write};
+use serde_json::json;
+use std::{
+    fs,
+    io::{IsTerminal, Write},
+    path::PathBuf,
+};
 use tonic::transport::Channel;
 
-use crate::actions::OpResult;
+use crate::actions::{OpResponce, OpResult};
+
+/// Exit codes besides 0 for success, clap exits with 2 on bad arguments
+const EXIT_FAIL: i32 = 1;
+const EXIT_DENIED: i32 = 3;
+const EXIT_UNREACHABLE: i32 = 4;
+
+/// Control a minecraft server run by mcsc-server
+#[derive(Parser, Debug)]
+#[command(name = "mcsc-cli", version)]
+struct Args {
+    /// Print the server's reply as a line of json
+    #[arg(long, global = true)]
+    json: bool,
+    /// Server from mcsc_client.toml to talk to, defaults to its default profile
+    #[arg(long, short, global = true, env = "MCSC_CLIENT_PROFILE")]
+    profile: Option<String>,
+    /// Config file to use instead of looking for mcsc_client.toml
+    #[arg(long, short, global = true, env = "MCSC_CLIENT_CONFIG")]
+    config: Option<PathBuf>,
+    /// Leave out to use the interactive menu
+    #[command(subcommand)]
+    command: Option<Action>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Action {
+    /// Launch the server and wait until it accepts players
+    Launch {
+        /// Return as soon as the server process has started
+        #[arg(long)]
+        no_wait: bool,
+    },
+    /// Stop the server
+    Stop {
+        /// Stop even when players are online
+        #[arg(long)]
+        force: bool,
+    },
+    /// Stop the server and launch it again
+    Restart {
+        /// Restart even when players are online
+        #[arg(long)]
+        force: bool,
+    },
+    /// Back up the world
+    Backup,
+    /// Run a console command, eg. mcsc-cli command say hi
+    Command {
+        #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+        command: Vec<String>,
+    },
+    /// Download a backup
+    Download {
+        /// Where to save it, - for stdout. Defaults to a new file in the working directory
+        #[arg(long, short)]
+        out: Option<PathBuf>,
+        /// Which backup, as listed by the backups subcommand. Defaults to the newest
+        #[arg(long)]
+        name: Option<String>,
+    },
+    /// List the backups that can be downloaded
+    Backups,
+    /// Show whether the server is accepting players
+    Status,
+    /// Make mcsc-server read its config again, admins only
+    ReloadConfig,
+    /// Full screen console with keys for launch, stop and backup
+    Tui,
+    /// Save a server from a connection string printed by mcsc-server init as a profile, named
+    /// by --profile or after its host
+    Import { connection: String },
+    /// The interactive menu
+    Shell,
+}
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+async fn main() {
+    let args = Args::parse();
+    if let Some(Action::Import { connection }) = &args.command {
+        std::process::exit(import(&args, connection));
+    }
+    let config = match Config::load(args.config.as_deref(), args.profile.as_deref()) {
+        Ok(config) => config,
+        Err(error) => {
+            eprintln!("{error}");
+            std::process::exit(EXIT_FAIL);
+        }
+    };
+    let action = match args.command {
+        Some(Action::Shell) | None => shell(&config).await,
+        Some(action) => action,
+    };
+    let code = match run(action, &config, args.json).await {
+        Ok(code) => code,
+        Err(status) => {
+            let error = client::error_of(&status);
+            let error_code = ErrorCode::from_i32(error.code).unwrap_or(ErrorCode::InternalError);
+            let (result, code) = match error_code {
+                ErrorCode::InvalidToken | ErrorCode::UnknownUser | ErrorCode::NotPermitted => {
+                    (OpResult::Denied, EXIT_DENIED)
+                }
+                ErrorCode::Unreachable => (OpResult::Fail, EXIT_UNREACHABLE),
+                _ => (OpResult::Fail, EXIT_FAIL),
+            };
+            if args.json {
+                println!(
+                    "{}",
+                    json!({
+                        "result": result.as_str_name(),
+                        "comment": error.message,
+                        "error": error_code.as_str_name(),
+                        "details": error.details,
+                    })
+                );
+            } else {
+                eprintln!("{}", error.message);
+            }
+            code
+        }
+    };
+    std::process::exit(code);
+}
+
+async fn shell(config: &Config) -> ! {
     println!(
         "Welcome to mcsc, NOTE: these operations take time to complete so be patent Enter a command: either by name or the number next to it"
     );
 
-    let config: Config = {
-        let bytes = std::fs::read("mcsc_client.toml").expect("No config file!");
-        let text = std::str::from_utf8(&bytes).expect("Config file encoding error");
-        toml::from_str(text).expect("No config file!")
-    };
-
     loop {
-        let _ = procces_request(&config).await;
+        if let Err(error) = procces_request(config).await {
+            match error.downcast_ref::<tonic::Status>() {
+                Some(status) => println!("Failed! {}", client::error_of(status).message),
+                None => println!("Failed! {error}"),
+            }
+        }
+    }
+}
+
+/// Add the server a connection string describes to the client config. Gives the exit code
+fn import(args: &Args, connection: &str) -> i32 {
+    let added = common::parse_connection_string(connection).and_then(|(address, key, user)| {
+        let name = args.profile.clone().unwrap_or_else(|| {
+            address
+                .rsplit_once(':')
+                .map_or(address.as_str(), |(host, _)| host)
+                .to_string()
+        });
+        let config = Config {
+            ip: format!("http://{address}"),
+            key,
+            user,
+        };
+        let path = client::add_profile(args.config.as_deref(), &name, &config)?;
+        Ok((name, path))
+    });
+    match added {
+        Ok((name, path)) => {
+            let comment = format!("Added profile {name} to {}", path.display());
+            let fields = json!({ "profile": name, "path": path });
+            finish(
+                args.json,
+                OpResult::Success as i32,
+                &comment,
+                fields,
+                Vec::new(),
+            )
+        }
+        Err(error) => finish(
+            args.json,
+            OpResult::Fail as i32,
+            &error,
+            json!({}),
+            Vec::new(),
+        ),
+    }
+}
+
+/// Run one subcommand, printing the outcome. Gives the exit code
+async fn run(action: Action, config: &Config, json: bool) -> Result<i32, tonic::Status> {
+    let mut client = client::connect(config).await.map_err(|error| {
+        tonic::Status::unavailable(format!("Couldn't connect to {}: {error}", config.ip))
+    })?;
+    let responce = match action {
+        Action::Launch { no_wait } => {
+            let token = client::auth(&mut client, AuthAction::Launch, config).await?;
+            let wait = !no_wait;
+            client.launch(LaunchRequest { token, wait }).await?
+        }
+        Action::Stop { force } => {
+            let token = client::auth(&mut client, AuthAction::Stop, config).await?;
+            client.stop(StopRequest { token, force }).await?
+        }
+        Action::Restart { force } => {
+            let token = client::auth(&mut client, AuthAction::Restart, config).await?;
+            client.restart(RestartRequest { token, force }).await?
+        }
+        Action::Backup => {
+            let token = client::auth(&mut client, AuthAction::Backup, config).await?;
+            client.backup(BackupRequest { token }).await?
+        }
+        Action::Command { command } => {
+            let token = client::auth(&mut client, AuthAction::Command, config).await?;
+            let command = command.join(" ");
+            client.command(CommandRequest { token, command }).await?
+        }
+        Action::Download { out, name } => {
+            return download(&mut client, config, out, name, json).await;
+        }
+        Action::Backups => {
+            let token = client::auth(&mut client, AuthAction::Backups, config).await?;
+            let backups = client.backups(BackupsRequest { token }).await?.into_inner();
+            let fields = json!({ "backups": backups.backups.iter().map(|backup| json!({
+                "name": backup.name,
+                "size": backup.size,
+                "created": backup.created,
+            })).collect::<Vec<_>>() });
+            let details = backups.backups.iter().map(describe_backup).collect();
+            return Ok(finish(
+                json,
+                backups.result,
+                &backups.comment,
+                fields,
+                details,
+            ));
+        }
+        Action::Status => {
+            let token = client::auth(&mut client, AuthAction::Status, config).await?;
+            let status = client.status(StatusRequest { token }).await?.into_inner();
+            let fields = json!({
+                "phase": ServerPhase::from_i32(status.phase).unwrap_or_default().as_str_name(),
+                "responding": status.responding,
+                "version": status.version,
+                "motd": status.motd,
+                "online": status.online,
+                "max": status.max,
+                "latency_ms": status.latency_ms,
+                "players": status.players,
+                "plugins": status.plugins,
+                "eula_accepted": status.eula_accepted,
+            });
+            return Ok(finish(
+                json,
+                status.result,
+                &status.comment,
+                fields,
+                describe_status(&status),
+            ));
+        }
+        Action::ReloadConfig => {
+            let token = client::auth(&mut client, AuthAction::ReloadConfig, config).await?;
+            let reload = client
+                .reload_config(ReloadConfigRequest { token })
+                .await?
+                .into_inner();
+            let fields = json!({
+                "changed": reload.changed,
+                "needs_restart": reload.needs_restart,
+                "problems": reload.problems,
+            });
+            return Ok(finish(
+                json,
+                reload.result,
+                &reload.comment,
+                fields,
+                reload.problems,
+            ));
+        }
+        Action::Tui => return tui::run(client, config).await.map(|_| 0),
+        Action::Shell => unreachable!("The shell doesn't return"),
+        Action::Import { .. } => unreachable!("Imports happen before the config is loaded"),
+    }
+    .into_inner();
+    Ok(finish(
+        json,
+        responce.result,
+        &responce.comment,
+        json!({}),
+        Vec::new(),
+    ))
+}
+
+/// Print a reply as json or as its comment followed by `details`, gives the exit code for it
+fn finish(
+    json: bool,
+    result: i32,
+    comment: &str,
+    fields: serde_json::Value,
+    details: Vec<String>,
+) -> i32 {
+    let result = OpResult::from_i32(result).unwrap_or(OpResult::Fail);
+    if json {
+        let mut reply = json!({ "result": result.as_str_name(), "comment": comment });
+        if let (Some(reply), serde_json::Value::Object(fields)) = (reply.as_object_mut(), fields) {
+            reply.extend(fields);
+        }
+        println!("{reply}");
+    } else {
+        println!("{comment}");
+        for line in details {
+            println!("  {line}");
+        }
+    }
+    match result {
+        OpResult::Success => 0,
+        OpResult::Fail => EXIT_FAIL,
+        OpResult::Denied => EXIT_DENIED,
+    }
+}
+
+async fn download(
+    client: &mut ControllerClient<Channel>,
+    config: &Config,
+    out: Option<PathBuf>,
+    name: Option<String>,
+    json: bool,
+) -> Result<i32, tonic::Status> {
+    let name = name.unwrap_or_default();
+    // Only draw progress for people, not for whatever reads the output
+    let show_progress = !json && std::io::stderr().is_terminal();
+    let progress = |received: u64, size: u64| {
+        if show_progress && size > 0 {
+            eprint!("\rDownloading {}%", received * 100 / size);
+        }
+    };
+    let (result, path) = match out {
+        Some(path) if path.as_os_str() == "-" => {
+            let mut stdout = std::io::stdout().lock();
+            let result = client::download(client, config, &name, &mut stdout, progress).await;
+            let _ = stdout.flush();
+            (result, None)
+        }
+        out => {
+            let path =
+                out.unwrap_or_else(|| format!("worldbackup-[{}].tar.gz", ran_letters(32)).into());
+            let mut file = fs::File::create(&path).map_err(|error| {
+                tonic::Status::internal(format!("Couldn't create {}: {error}", path.display()))
+            })?;
+            let result = client::download(client, config, &name, &mut file, progress).await;
+            if result.is_err() {
+                // Throw away redundant file to avoid confusion
+                let _ = fs::remove_file(&path);
+            }
+            (result, Some(path))
+        }
+    };
+    if show_progress {
+        eprintln!();
+    }
+    if let Err(error) = result {
+        return Err(match error.downcast::<tonic::Status>() {
+            Ok(status) => *status,
+            Err(error) => tonic::Status::unknown(format!("Download failed: {error}")),
+        });
     }
+    // A backup written to stdout is the output, keep anything else off it
+    Ok(match path {
+        Some(path) => finish(
+            json,
+            OpResult::Success as i32,
+            &format!("Saved as {}", path.display()),
+            json!({ "path": path }),
+            Vec::new(),
+        ),
+        None => 0,
+    })
 }
 
 async fn procces_request(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
@@ -41,36 +403,68 @@ async fn procces_request(config: &Config) -> Result<(), Box<dyn std::error::Erro
 2 | \'Backup\'   to create a backup or 
 3 | \'Command\'  to run a command
 4 | \'Download\' to download the latest backup
+5 | \'Runtimes\' to list the java runtimes on the server
+6 | \'RegisterJava\' to register a java runtime on the server
+7 | \'Versions\' to list the available minecraft versions
+8 | \'Upgrade\'  to back up the world and switch minecraft version
+9 | \'Search\'   to search modrinth for mods or plugins
+10 | \'Mods\'    to list installed mods
+11 | \'Install\' to install a mod and its dependencies
+12 | \'Update\'  to update installed mods
+13 | \'Remove\'  to remove a mod
+14 | \'Properties\' to show server.properties
+15 | \'Set\'     to change a server.properties value
+16 | \'Lists\'   to show the whitelist, ops or bans
+17 | \'AddEntry\' to whitelist, op or ban someone
+18 | \'RemoveEntry\' to take someone off the whitelist, ops or bans
+19 | \'Players\' to see who is online
+20 | \'Events\'  to follow joins, leaves, deaths, chat and advancements
+21 | \'Restart\' to stop the server and launch it again
+22 | \'Status\'  to check whether the server is accepting players
+23 | \'AcceptEula\' to agree to the minecraft EULA for the server
+24 | \'Metrics\' to show cpu, memory, disk use and tick rate
+25 | \'WatchMetrics\' to follow cpu, memory, disk use and tick rate
+26 | \'Console\' to follow the server console
+27 | \'Backups\' to list the backups that can be downloaded
+28 | \'ReloadConfig\' to make the server read its config again
 => "
     );
     let input = read_input();
     // Don't await the client as we won't need the connection if the input is invailid
-    let connection = ControllerClient::connect(config.ip.to_owned());
+    let connection = client::connect(config);
 
     // Launch the server
-    let response = if regex_is_match!(r"((?i)Launch(?-i)|0)", &input) {
+    let response = if regex_is_match!(r"^\s*((?i)Launch(?-i)|0)\s*$", &input) {
         let mut client = connection.await?;
-        let token = auth(&mut client, AuthAction::Launch, &config).await?;
-        client.launch(LaunchRequest { token }).await?
+        let token = auth(&mut client, AuthAction::Launch, config).await?;
+        println!("[Waiting for the server to accept players...]");
+        client.launch(LaunchRequest { token, wait: true }).await?
 
     // Stop the server
-    } else if regex_is_match!(r"((?i)Stop(?-i)|1)", &input) {
+    } else if regex_is_match!(r"^\s*((?i)Stop(?-i)|1)\s*$", &input) {
         let mut client = connection.await?;
-        let token = auth(&mut client, AuthAction::Stop, &config).await?;
-        client.stop(StopRequest { token }).await?
+        let force = match confirm_kick(&mut client, config, "Stop anyway?").await {
+            Some(force) => force,
+            None => {
+                println!("Not stopped");
+                return Ok(());
+            }
+        };
+        let token = auth(&mut client, AuthAction::Stop, config).await?;
+        client.stop(StopRequest { token, force }).await?
 
     // Take backup
-    } else if regex_is_match!(r"((?i)Backup(?-i)|2)", &input) {
+    } else if regex_is_match!(r"^\s*((?i)Backup(?-i)|2)\s*$", &input) {
         let mut client = connection.await?;
-        let token = auth(&mut client, AuthAction::Backup, &config).await?;
+        let token = auth(&mut client, AuthAction::Backup, config).await?;
         client.backup(BackupRequest { token }).await?
 
     // Run Command
-    } else if regex_is_match!(r"((?i)Command(?-i)|3)", &input) {
+    } else if regex_is_match!(r"^\s*((?i)Command(?-i)|3)\s*$", &input) {
         let mut client = connection.await?;
         print!("Enter command \n=> ");
         let command = read_input();
-        let token = auth(&mut client, AuthAction::Command, &config).await?;
+        let token = auth(&mut client, AuthAction::Command, config).await?;
         let request = CommandRequest {
             token,
             command: command.to_owned(),
@@ -78,9 +472,425 @@ async fn procces_request(config: &Config) -> Result<(), Box<dyn std::error::Erro
         client.command(request).await?
 
     // Download latest backup
-    } else if regex_is_match!(r"((?i)Download(?-i)|4)", &input) {
-        let mut client = ControllerClient::connect(config.ip.to_owned()).await?;
-        recive_world_download(&mut client, &config).await?;
+    } else if regex_is_match!(r"^\s*((?i)Download(?-i)|4)\s*$", &input) {
+        let mut client = client::connect(config).await?;
+        recive_world_download(&mut client, config).await?;
+        return Ok(());
+
+    // List java runtimes
+    } else if regex_is_match!(r"^\s*((?i)Runtimes(?-i)|5)\s*$", &input) {
+        let mut client = connection.await?;
+        let token = auth(&mut client, AuthAction::JavaRuntimes, config).await?;
+        let runtimes = client
+            .java_runtimes(JavaRuntimesRequest { token })
+            .await?
+            .into_inner();
+        println!("{}", runtimes.comment);
+        for runtime in runtimes.runtimes {
+            let marker = if runtime.major == runtimes.required {
+                "*"
+            } else {
+                " "
+            };
+            println!(
+                "{marker} java {:<3} {:<16} {:<10} {}",
+                runtime.major, runtime.version, runtime.source, runtime.path
+            );
+        }
+        if runtimes.required != 0 {
+            println!("The server requires java {}", runtimes.required);
+        }
+        return Ok(());
+
+    // Register a java runtime
+    } else if regex_is_match!(r"^\s*((?i)RegisterJava(?-i)|6)\s*$", &input) {
+        let mut client = connection.await?;
+        print!("Enter the path of a java home or jdk archive on the server \n=> ");
+        let path = read_input().trim().to_owned();
+        let token = auth(&mut client, AuthAction::RegisterJava, config).await?;
+        client
+            .register_java(RegisterJavaRequest { token, path })
+            .await?
+
+    // List minecraft versions
+    } else if regex_is_match!(r"^\s*((?i)Versions(?-i)|7)\s*$", &input) {
+        let mut client = connection.await?;
+        print!("Include snapshots? [y/N] \n=> ");
+        let snapshots = regex_is_match!(r"^\s*(?i)y", &read_input());
+        let token = auth(&mut client, AuthAction::Versions, config).await?;
+        let versions = client
+            .versions(VersionsRequest { token, snapshots })
+            .await?
+            .into_inner();
+        println!("{}", versions.comment);
+        // The manifest lists newest first
+        for version in versions.versions.iter().take(20) {
+            println!(
+                "  {:<24} {:<10} {}",
+                version.id, version.kind, version.release_time
+            );
+        }
+        if versions.versions.len() > 20 {
+            println!("  ... and {} older", versions.versions.len() - 20);
+        }
+        println!(
+            "Latest release: {}, latest snapshot: {}, installed: {}",
+            versions.latest_release,
+            versions.latest_snapshot,
+            if versions.installed.is_empty() {
+                "unknown"
+            } else {
+                &versions.installed
+            }
+        );
+        return Ok(());
+
+    // Switch minecraft version
+    } else if regex_is_match!(r"^\s*((?i)Upgrade(?-i)|8)\s*$", &input) {
+        let mut client = connection.await?;
+        print!("Enter the version to install, or \'latest\' \n=> ");
+        let version = read_input().trim().to_owned();
+        let token = auth(&mut client, AuthAction::InstallVersion, config).await?;
+        client
+            .install_version(InstallVersionRequest { token, version })
+            .await?
+
+    // Search modrinth
+    } else if regex_is_match!(r"^\s*((?i)Search(?-i)|9)\s*$", &input) {
+        let mut client = connection.await?;
+        print!("Enter search terms \n=> ");
+        let query = read_input().trim().to_owned();
+        let token = auth(&mut client, AuthAction::SearchMods, config).await?;
+        let mods = client
+            .search_mods(SearchModsRequest { token, query })
+            .await?
+            .into_inner();
+        println!("{} for {} {}", mods.comment, mods.loader, mods.game_version);
+        for found in mods.mods {
+            println!("  {:<24} {}", found.slug, found.title);
+            println!("  {:<24} {}", "", found.description);
+        }
+        return Ok(());
+
+    // List installed mods
+    } else if regex_is_match!(r"^\s*((?i)Mods(?-i)|10)\s*$", &input) {
+        let mut client = connection.await?;
+        let token = auth(&mut client, AuthAction::InstalledMods, config).await?;
+        let mods = client
+            .installed_mods(InstalledModsRequest { token })
+            .await?
+            .into_inner();
+        println!("{}", mods.comment);
+        for installed in mods.mods {
+            println!(
+                "  {:<24} {:<16} {}",
+                installed.slug, installed.version, installed.filename
+            );
+        }
+        return Ok(());
+
+    // Install a mod
+    } else if regex_is_match!(r"^\s*((?i)Install(?-i)|11)\s*$", &input) {
+        let mut client = connection.await?;
+        print!("Enter the modrinth slug or project id \n=> ");
+        let project = read_input().trim().to_owned();
+        let token = auth(&mut client, AuthAction::InstallMod, config).await?;
+        client
+            .install_mod(InstallModRequest { token, project })
+            .await?
+
+    // Update mods
+    } else if regex_is_match!(r"^\s*((?i)Update(?-i)|12)\s*$", &input) {
+        let mut client = connection.await?;
+        print!("Enter the modrinth slug or project id, leave empty to update everything \n=> ");
+        let project = read_input().trim().to_owned();
+        let token = auth(&mut client, AuthAction::UpdateMods, config).await?;
+        client
+            .update_mods(UpdateModsRequest { token, project })
+            .await?
+
+    // Remove a mod
+    } else if regex_is_match!(r"^\s*((?i)Remove(?-i)|13)\s*$", &input) {
+        let mut client = connection.await?;
+        print!("Enter the modrinth slug or project id \n=> ");
+        let project = read_input().trim().to_owned();
+        let token = auth(&mut client, AuthAction::RemoveMod, config).await?;
+        client
+            .remove_mod(RemoveModRequest { token, project })
+            .await?
+
+    // Show server.properties
+    } else if regex_is_match!(r"^\s*((?i)Properties(?-i)|14)\s*$", &input) {
+        let mut client = connection.await?;
+        let token = auth(&mut client, AuthAction::GetProperties, config).await?;
+        let properties = client
+            .get_properties(GetPropertiesRequest { token })
+            .await?
+            .into_inner();
+        println!("{} (* = you may change it)", properties.comment);
+        for property in properties.properties {
+            let marker = if property.editable { "*" } else { " " };
+            println!(
+                "{marker} {:<36} {:<8} {}",
+                property.key, property.kind, property.value
+            );
+        }
+        return Ok(());
+
+    // Change a server.properties value
+    } else if regex_is_match!(r"^\s*((?i)Set(?-i)|15)\s*$", &input) {
+        let mut client = connection.await?;
+        print!("Enter the key to change, eg. motd \n=> ");
+        let key = read_input().trim().to_owned();
+        print!("Enter the new value \n=> ");
+        let value = read_input().trim_end_matches(['\r', '\n']).to_owned();
+        let token = auth(&mut client, AuthAction::SetProperties, config).await?;
+        let responce = client
+            .set_properties(SetPropertiesRequest {
+                token,
+                properties: [(key, value)].into_iter().collect(),
+            })
+            .await?
+            .into_inner();
+        tonic::Response::new(OpResponce {
+            result: responce.result,
+            comment: responce.comment,
+        })
+
+    // Show a player list
+    } else if regex_is_match!(r"^\s*((?i)Lists(?-i)|16)\s*$", &input) {
+        let mut client = connection.await?;
+        let list = read_player_list();
+        let token = auth(&mut client, AuthAction::ListEntries, config).await?;
+        let entries = client
+            .list_entries(ListEntriesRequest {
+                token,
+                list: list.into(),
+            })
+            .await?
+            .into_inner();
+        println!("{}", entries.comment);
+        for entry in entries.entries {
+            match list {
+                PlayerList::Whitelist => println!("  {:<16} {}", entry.name, entry.uuid),
+                PlayerList::Ops => {
+                    println!("  {:<16} level {} {}", entry.name, entry.level, entry.uuid)
+                }
+                PlayerList::BannedPlayers => println!(
+                    "  {:<16} {} by {}: {}",
+                    entry.name, entry.created, entry.source, entry.reason
+                ),
+                PlayerList::BannedIps => println!(
+                    "  {:<16} {} by {}: {}",
+                    entry.ip, entry.created, entry.source, entry.reason
+                ),
+            }
+        }
+        return Ok(());
+
+    // Add to a player list
+    } else if regex_is_match!(r"^\s*((?i)AddEntry(?-i)|17)\s*$", &input) {
+        let mut client = connection.await?;
+        let list = read_player_list();
+        print!("Enter the player name or ip \n=> ");
+        let name = read_input().trim().to_owned();
+        let reason = if matches!(list, PlayerList::BannedPlayers | PlayerList::BannedIps) {
+            print!("Enter the ban reason \n=> ");
+            read_input().trim().to_owned()
+        } else {
+            String::new()
+        };
+        let token = auth(&mut client, AuthAction::AddEntry, config).await?;
+        client
+            .add_entry(AddEntryRequest {
+                token,
+                list: list.into(),
+                name,
+                reason,
+            })
+            .await?
+
+    // Remove from a player list
+    } else if regex_is_match!(r"^\s*((?i)RemoveEntry(?-i)|18)\s*$", &input) {
+        let mut client = connection.await?;
+        let list = read_player_list();
+        print!("Enter the player name or ip \n=> ");
+        let name = read_input().trim().to_owned();
+        let token = auth(&mut client, AuthAction::RemoveEntry, config).await?;
+        client
+            .remove_entry(RemoveEntryRequest {
+                token,
+                list: list.into(),
+                name,
+            })
+            .await?
+
+    // Who is online
+    } else if regex_is_match!(r"^\s*((?i)Players(?-i)|19)\s*$", &input) {
+        let mut client = connection.await?;
+        let token = auth(&mut client, AuthAction::Players, config).await?;
+        let players = client.players(PlayersRequest { token }).await?.into_inner();
+        println!("{}", players.comment);
+        for player in players.online {
+            println!(
+                "  {:<16} online for {}",
+                player.name,
+                describe_seconds(player.online_for)
+            );
+        }
+        if !players.sessions.is_empty() {
+            println!("Recent sessions:");
+            for session in players.sessions.iter().take(10) {
+                println!(
+                    "  {:<16} played for {}",
+                    session.name,
+                    describe_seconds(session.duration)
+                );
+            }
+        }
+        return Ok(());
+
+    // Follow player events
+    } else if regex_is_match!(r"^\s*((?i)Events(?-i)|20)\s*$", &input) {
+        let mut client = connection.await?;
+        let token = auth(&mut client, AuthAction::PlayerEvents, config).await?;
+        let mut events = client
+            .player_events(PlayerEventsRequest { token })
+            .await?
+            .into_inner();
+        println!("Following player events, press Ctrl-C to stop");
+        while let Some(event) = events.message().await? {
+            println!("{}", event.comment);
+        }
+        return Ok(());
+
+    // Restart the server
+    } else if regex_is_match!(r"^\s*((?i)Restart(?-i)|21)\s*$", &input) {
+        let mut client = connection.await?;
+        let force = match confirm_kick(&mut client, config, "Restart anyway?").await {
+            Some(force) => force,
+            None => {
+                println!("Not restarted");
+                return Ok(());
+            }
+        };
+        let token = auth(&mut client, AuthAction::Restart, config).await?;
+        client.restart(RestartRequest { token, force }).await?
+
+    // Show whether the server is up
+    } else if regex_is_match!(r"^\s*((?i)Status(?-i)|22)\s*$", &input) {
+        let mut client = connection.await?;
+        let token = auth(&mut client, AuthAction::Status, config).await?;
+        let status = client.status(StatusRequest { token }).await?.into_inner();
+        println!("{}", status.comment);
+        for line in describe_status(&status) {
+            println!("  {line}");
+        }
+        return Ok(());
+
+    // Agree to the EULA so the server can launch
+    } else if regex_is_match!(r"^\s*((?i)AcceptEula(?-i)|23)\s*$", &input) {
+        print!(
+            "The minecraft EULA is at https://aka.ms/MinecraftEULA \nDo you agree to it? [y/N] \n=> "
+        );
+        if !regex_is_match!(r"^\s*(?i)y", &read_input()) {
+            println!("EULA not accepted");
+            return Ok(());
+        }
+        let mut client = connection.await?;
+        let token = auth(&mut client, AuthAction::AcceptEula, config).await?;
+        client.accept_eula(AcceptEulaRequest { token }).await?
+
+    // Show the latest resource sample and how the kept ones compare
+    } else if regex_is_match!(r"^\s*((?i)Metrics(?-i)|24)\s*$", &input) {
+        let mut client = connection.await?;
+        let token = auth(&mut client, AuthAction::Metrics, config).await?;
+        let metrics = client.metrics(MetricsRequest { token }).await?.into_inner();
+        let latest = match metrics.samples.last() {
+            Some(latest) => latest,
+            None => {
+                println!("No samples yet, {}", metrics.comment);
+                return Ok(());
+            }
+        };
+        println!("{}", describe_sample(latest));
+        let count = metrics.samples.len() as f64;
+        let ticking: Vec<f64> = metrics
+            .samples
+            .iter()
+            .map(|sample| sample.tps)
+            .filter(|tps| *tps > 0.0)
+            .collect();
+        println!(
+            "Over the last {}: {:.0}% cpu on average, {} memory at most{}",
+            describe_seconds(metrics.samples.len() as u64 * metrics.interval),
+            metrics.samples.iter().map(|s| s.cpu_percent).sum::<f64>() / count,
+            describe_bytes(
+                metrics
+                    .samples
+                    .iter()
+                    .map(|s| s.memory_bytes)
+                    .max()
+                    .unwrap_or(0)
+            ),
+            match ticking.iter().cloned().reduce(f64::min) {
+                Some(tps) => format!(", {tps:.1} tps at worst"),
+                None => String::new(),
+            }
+        );
+        return Ok(());
+
+    // Follow resource samples as they're taken
+    } else if regex_is_match!(r"^\s*((?i)WatchMetrics(?-i)|25)\s*$", &input) {
+        let mut client = connection.await?;
+        let token = auth(&mut client, AuthAction::WatchMetrics, config).await?;
+        let mut samples = client
+            .watch_metrics(WatchMetricsRequest { token })
+            .await?
+            .into_inner();
+        println!("Following metrics, press Ctrl-C to stop");
+        while let Some(sample) = samples.message().await? {
+            println!("{}", describe_sample(&sample));
+        }
+        return Ok(());
+
+    // Follow the console
+    } else if regex_is_match!(r"^\s*((?i)Console(?-i)|26)\s*$", &input) {
+        let mut client = connection.await?;
+        let token = auth(&mut client, AuthAction::Console, config).await?;
+        let mut lines = client
+            .console(ConsoleRequest { token, history: 20 })
+            .await?
+            .into_inner();
+        println!("Following the console, press Ctrl-C to stop");
+        while let Some(line) = lines.message().await? {
+            println!("{}", line.line);
+        }
+        return Ok(());
+
+    // List backups
+    } else if regex_is_match!(r"^\s*((?i)Backups(?-i)|27)\s*$", &input) {
+        let mut client = connection.await?;
+        let token = auth(&mut client, AuthAction::Backups, config).await?;
+        let backups = client.backups(BackupsRequest { token }).await?.into_inner();
+        println!("{}", backups.comment);
+        for backup in &backups.backups {
+            println!("  {}", describe_backup(backup));
+        }
+        return Ok(());
+
+    // Read the server config again
+    } else if regex_is_match!(r"^\s*((?i)ReloadConfig(?-i)|28)\s*$", &input) {
+        let mut client = connection.await?;
+        let token = auth(&mut client, AuthAction::ReloadConfig, config).await?;
+        let reload = client
+            .reload_config(ReloadConfigRequest { token })
+            .await?
+            .into_inner();
+        println!("{}", reload.comment);
+        for problem in reload.problems {
+            println!("  {problem}");
+        }
         return Ok(());
     }
     // No action recognised
@@ -101,42 +911,155 @@ async fn procces_request(config: &Config) -> Result<(), Box<dyn std::error::Erro
     Ok(())
 }
 
-#[derive(Deserialize, Debug)]
-struct Config {
-    ip: String,
-    key: String,
-}
-
-fn decrypt(data: &Vec<u8>, key: &str) -> Result<Vec<u8>, magic_crypt::MagicCryptError> {
-    let key = new_magic_crypt!(key, 256);
-    Ok(key.decrypt_bytes_to_bytes(data)?)
-}
-
 async fn auth(
     client: &mut ControllerClient<Channel>,
     action: AuthAction,
     config: &Config,
 ) -> Result<Vec<u8>, tonic::Status> {
     println!("[Awaiting server response...]");
-    let key = client
-        .auth(AuthRequest {
-            action: action.into(),
-        })
-        .await?
-        .into_inner();
-    println!("[Server connection status: {}]", key.comment);
-    Ok(decrypt(&key.key, &config.key).expect("Client side auth error occurred"))
+    let token = client::auth(client, action, config).await;
+    println!(
+        "[Server connection status: {}]",
+        match &token {
+            Ok(_) => "Success",
+            Err(status) => status.message(),
+        }
+    );
+    token
 }
 
 fn read_input() -> String {
     let mut input = String::new();
     let _ = std::io::Write::flush(&mut std::io::stdout());
-    std::io::stdin()
+    let read = std::io::stdin()
         .read_line(&mut input)
         .expect("Could not read input");
+    // Nothing more will come, otherwise the shell would keep failing on empty input
+    if read == 0 {
+        std::process::exit(0);
+    }
     input
 }
 
+/// Names of the players online, fails if the user may not see them
+async fn players_online(
+    client: &mut ControllerClient<Channel>,
+    config: &Config,
+) -> Result<Vec<String>, tonic::Status> {
+    let token = auth(client, AuthAction::Players, config).await?;
+    let players = client.players(PlayersRequest { token }).await?.into_inner();
+    Ok(players
+        .online
+        .into_iter()
+        .map(|player| player.name)
+        .collect())
+}
+
+/// Warn before kicking anyone, None if the user changed their mind. Otherwise whether to force
+/// the request past the server's online_stop_policy, only when the user has seen who is online
+async fn confirm_kick(
+    client: &mut ControllerClient<Channel>,
+    config: &Config,
+    question: &str,
+) -> Option<bool> {
+    let online = match players_online(client, config).await {
+        Ok(online) if !online.is_empty() => online,
+        _ => return Some(false),
+    };
+    print!(
+        "{} online: {} \n{question} [y/N] \n=> ",
+        describe_count(online.len()),
+        online.join(", ")
+    );
+    if regex_is_match!(r"^\s*(?i)y", &read_input()) {
+        Some(true)
+    } else {
+        None
+    }
+}
+
+/// "1 player", "3 players"
+fn describe_count(count: usize) -> String {
+    match count {
+        1 => "1 player".to_string(),
+        count => format!("{count} players"),
+    }
+}
+
+/// "1h 5m", "12m", "40s"
+fn describe_seconds(seconds: u64) -> String {
+    match (seconds / 3600, seconds / 60 % 60) {
+        (0, 0) => format!("{seconds}s"),
+        (0, minutes) => format!("{minutes}m"),
+        (hours, minutes) => format!("{hours}h {minutes}m"),
+    }
+}
+
+/// "12.3 MB", "1.5 GB"
+fn describe_bytes(bytes: u64) -> String {
+    let mb = bytes as f64 / (1024.0 * 1024.0);
+    if mb < 1024.0 {
+        format!("{mb:.1} MB")
+    } else {
+        format!("{:.1} GB", mb / 1024.0)
+    }
+}
+
+/// The details of a status reply worth showing under its comment
+fn describe_status(status: &StatusResponce) -> Vec<String> {
+    let mut lines = Vec::new();
+    if status.responding {
+        lines.push(format!("motd: {}", status.motd));
+    }
+    if !status.players.is_empty() {
+        lines.push(format!("players: {}", status.players.join(", ")));
+    }
+    if !status.plugins.is_empty() {
+        lines.push(format!("plugins: {}", status.plugins));
+    }
+    if !status.eula_accepted_by.is_empty() {
+        lines.push(format!("EULA accepted by: {}", status.eula_accepted_by));
+    }
+    lines
+}
+
+fn describe_backup(backup: &BackupFile) -> String {
+    format!("{} {}", backup.name, describe_bytes(backup.size))
+}
+
+/// One line summing up a resource sample
+fn describe_sample(sample: &MetricsSample) -> String {
+    let mut line = format!(
+        "cpu {:.0}%, memory {}, world {}, backups {}",
+        sample.cpu_percent,
+        describe_bytes(sample.memory_bytes),
+        describe_bytes(sample.world_bytes),
+        describe_bytes(sample.backup_bytes)
+    );
+    if sample.tps > 0.0 {
+        line.push_str(&format!(", {:.1} tps", sample.tps));
+    }
+    if sample.mspt > 0.0 {
+        line.push_str(&format!(", {:.1} mspt", sample.mspt));
+    }
+    line
+}
+
+/// Ask which player list to work on, defaults to the whitelist
+fn read_player_list() -> PlayerList {
+    print!("Which list? \'whitelist\', \'ops\', \'bans\' or \'ip-bans\' \n=> ");
+    let input = read_input();
+    if regex_is_match!(r"^\s*(?i)ops?\s*$", &input) {
+        PlayerList::Ops
+    } else if regex_is_match!(r"^\s*(?i)ip", &input) {
+        PlayerList::BannedIps
+    } else if regex_is_match!(r"^\s*(?i)bans?\s*$", &input) {
+        PlayerList::BannedPlayers
+    } else {
+        PlayerList::Whitelist
+    }
+}
+
 async fn recive_world_download(
     client: &mut ControllerClient<Channel>,
     config: &Config,
@@ -144,24 +1067,20 @@ async fn recive_world_download(
     // Generate file name
     let ufid = ran_letters(32);
     let path = format!("worldbackup-[{ufid}].tar.gz",);
-    let token = auth(client, AuthAction::Download, &config).await?;
-    let request = DownloadRequest { token };
     // Download file
-    let mut stream = client.download(request).await?.into_inner();
     let mut file = fs::File::create(&path)?;
-    while let Some(msg) = stream.message().await? {
-        println!("{}", msg.comment);
-
-        // Check for errors
-        if let Some(res) = OpResult::from_i32(msg.result) {
-            if res != OpResult::Success {
-                // Throw away redundant file to avoid confusion
-                let _ = std::fs::remove_file(path);
-                return Ok(());
-            }
-        }
-
-        file.write(&msg.data)?;
+    let result = client::download(client, config, "", &mut file, |received, size| {
+        println!(
+            "Download progress: {}%",
+            (received as f64 / size as f64 * 100.) as u64
+        )
+    })
+    .await;
+    if let Err(error) = result {
+        println!("Download failed: {error}");
+        // Throw away redundant file to avoid confusion
+        let _ = std::fs::remove_file(path);
+        return Ok(());
     }
     // Download complete, show location
     let working_directory = std::env::current_dir();
@@ -169,5 +1088,5 @@ async fn recive_world_download(
         Ok(wdir) => println!("Saved as {:?} {}", wdir, path),
         Err(_) => println!("Download saved as {}", path),
     }
-    return Ok(());
+    Ok(())
 }
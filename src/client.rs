@@ -0,0 +1,354 @@
+use crate::actions::{
+    controller_client::ControllerClient, AuthAction, AuthRequest, DownloadRequest, Error, ErrorCode,
+};
+use magic_crypt::{new_magic_crypt, MagicCryptTrait};
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    io::Write,
+    path::{Path, PathBuf},
+};
+use tonic::transport::Channel;
+
+/// Looked for in the working directory and then the xdg config directories, under mcsc/
+pub const CONFIG_FILE: &str = "mcsc_client.toml";
+/// Name of the profile a config without [profiles.*] tables defines at the top level
+pub const DEFAULT_PROFILE: &str = "default";
+/// Environment variables starting with this override the profile's ip, key or user, eg.
+/// MCSC_CLIENT_KEY
+const ENV_PREFIX: &str = "MCSC_CLIENT_";
+
+/// Which server to talk to and how to authenticate with it
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct Config {
+    pub ip: String,
+    pub key: String,
+    /// User from the server config the key belongs to, leave unset for the shared key
+    #[serde(default)]
+    pub user: String,
+}
+
+/// A profile as written in the config file, the key may come from elsewhere
+#[derive(Deserialize, Debug, Default)]
+pub struct Profile {
+    #[serde(default)]
+    pub ip: String,
+    pub key: Option<String>,
+    /// Environment variable holding the key
+    pub key_env: Option<String>,
+    /// File holding the key, a trailing newline is ignored
+    pub key_file: Option<PathBuf>,
+    #[serde(default)]
+    pub user: String,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct ConfigFile {
+    /// Profile to use when none is asked for
+    default: Option<String>,
+    #[serde(default)]
+    profiles: BTreeMap<String, Profile>,
+    /// Older configs are a single profile at the top level
+    #[serde(flatten)]
+    single: Profile,
+}
+
+impl ConfigFile {
+    /// Read `path`, or the first config found when there isn't one
+    fn read(path: Option<&Path>) -> Result<(PathBuf, Self), String> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => config_paths()
+                .into_iter()
+                .find(|path| path.is_file())
+                .ok_or_else(|| {
+                    let searched: Vec<String> = config_paths()
+                        .iter()
+                        .map(|path| path.display().to_string())
+                        .collect();
+                    format!("No {CONFIG_FILE} found, looked at {}", searched.join(", "))
+                })?,
+        };
+        let text = std::fs::read_to_string(&path)
+            .map_err(|error| format!("Couldn't read {}: {error}", path.display()))?;
+        let file = toml::from_str(&text).map_err(|error: toml::de::Error| {
+            let line = error.span().map_or(String::new(), |span| {
+                let line = text[..span.start.min(text.len())].matches('\n').count() + 1;
+                format!(":{line}")
+            });
+            format!("{}{line}: {}", path.display(), error.message())
+        })?;
+        Ok((path, file))
+    }
+
+    fn into_profiles(self) -> BTreeMap<String, Profile> {
+        let mut profiles = self.profiles;
+        // Imported profiles get appended to configs that may still have one at the top level
+        if profiles.is_empty() || !self.single.ip.is_empty() {
+            profiles
+                .entry(DEFAULT_PROFILE.to_string())
+                .or_insert(self.single);
+        }
+        profiles
+    }
+}
+
+impl Profile {
+    /// Take the ip, key and user from the environment where it sets them
+    fn override_from_env(mut self) -> Self {
+        for (variable, value) in std::env::vars() {
+            match variable.strip_prefix(ENV_PREFIX) {
+                Some("IP") => self.ip = value,
+                Some("USER") => self.user = value,
+                Some("KEY") => {
+                    self.key = Some(value);
+                    self.key_env = None;
+                    self.key_file = None;
+                }
+                _ => {}
+            }
+        }
+        self
+    }
+
+    /// Read the key from wherever the profile keeps it
+    pub fn resolve(self, name: &str) -> Result<Config, String> {
+        let key = match (self.key, self.key_env, self.key_file) {
+            (Some(key), None, None) => key,
+            (None, Some(variable), None) => std::env::var(&variable).map_err(|_| {
+                format!("Profile {name} takes its key from ${variable}, which isn't set")
+            })?,
+            (None, None, Some(path)) => std::fs::read_to_string(&path)
+                .map_err(|error| {
+                    format!(
+                        "Profile {name} takes its key from {}, which couldn't be read: {error}",
+                        path.display()
+                    )
+                })?
+                .trim_end_matches(['\r', '\n'])
+                .to_string(),
+            (None, None, None) => {
+                return Err(format!("Profile {name} needs a key, key_env or key_file"))
+            }
+            _ => {
+                return Err(format!(
+                    "Profile {name} should only have one of key, key_env and key_file"
+                ))
+            }
+        };
+        if self.ip.is_empty() {
+            return Err(format!("Profile {name} needs an ip"));
+        }
+        Ok(Config {
+            ip: self.ip,
+            key,
+            user: self.user,
+        })
+    }
+}
+
+impl Config {
+    /// Load a profile by name, or the default one, from `path` or the first config found
+    #[allow(dead_code)] // The gui shows them all
+    pub fn load(path: Option<&Path>, profile: Option<&str>) -> Result<Self, String> {
+        let (path, file) = ConfigFile::read(path)?;
+        let name = profile
+            .map(str::to_string)
+            .or_else(|| file.default.clone())
+            .unwrap_or_else(|| DEFAULT_PROFILE.to_string());
+        let mut profiles = file.into_profiles();
+        // A config with just one profile needn't name it as the default
+        if profile.is_none() && profiles.len() == 1 {
+            let (name, only) = profiles.pop_first().expect("There is one profile");
+            return only.override_from_env().resolve(&name);
+        }
+        let available: Vec<String> = profiles.keys().cloned().collect();
+        profiles
+            .remove(&name)
+            .ok_or_else(|| {
+                format!(
+                    "No profile {name} in {}, it has {}",
+                    path.display(),
+                    available.join(", ")
+                )
+            })?
+            .override_from_env()
+            .resolve(&name)
+    }
+
+    /// Every profile in the config by name, the default one first. Their keys are left where
+    /// they are, resolve them when connecting
+    #[allow(dead_code)] // The cli uses one at a time
+    pub fn load_all() -> Result<Vec<(String, Profile)>, String> {
+        let (_, file) = ConfigFile::read(None)?;
+        let default = file.default.clone();
+        let mut profiles: Vec<(String, Profile)> = file.into_profiles().into_iter().collect();
+        profiles.sort_by_key(|(name, _)| Some(name) != default.as_ref());
+        Ok(profiles)
+    }
+}
+
+/// Add a profile to `path`, the config found otherwise, or a new one in the user's config
+/// directory. Gives the file written to
+#[allow(dead_code)] // Only the cli imports profiles
+pub fn add_profile(path: Option<&Path>, name: &str, config: &Config) -> Result<PathBuf, String> {
+    let existing = match path {
+        Some(path) => path.is_file().then(|| path.to_path_buf()),
+        None => config_paths().into_iter().find(|path| path.is_file()),
+    };
+    let quote = |text: &str| toml::Value::String(text.to_string()).to_string();
+    let header = if name
+        .chars()
+        .all(|char| char.is_ascii_alphanumeric() || char == '-' || char == '_')
+    {
+        format!("[profiles.{name}]")
+    } else {
+        format!("[profiles.{}]", quote(name))
+    };
+    let mut profile = format!(
+        "{header}\nip = {}\nkey = {}\n",
+        quote(&config.ip),
+        quote(&config.key)
+    );
+    if !config.user.is_empty() {
+        profile.push_str(&format!("user = {}\n", quote(&config.user)));
+    }
+    match existing {
+        Some(path) => {
+            let (_, file) = ConfigFile::read(Some(&path))?;
+            if file.into_profiles().contains_key(name) {
+                return Err(format!(
+                    "{} already has a profile {name}, pick another name with --profile",
+                    path.display()
+                ));
+            }
+            let mut file = std::fs::OpenOptions::new()
+                .append(true)
+                .open(&path)
+                .map_err(|error| format!("Couldn't open {}: {error}", path.display()))?;
+            file.write_all(format!("\n{profile}").as_bytes())
+                .map_err(|error| format!("Couldn't write {}: {error}", path.display()))?;
+            Ok(path)
+        }
+        None => {
+            let path = match path {
+                Some(path) => path.to_path_buf(),
+                None => config_paths()
+                    .into_iter()
+                    .nth(1)
+                    .ok_or("Nowhere to put a new config, set $HOME or pass --config")?,
+            };
+            if let Some(directory) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+                std::fs::create_dir_all(directory)
+                    .map_err(|error| format!("Couldn't create {}: {error}", directory.display()))?;
+            }
+            // Keys shouldn't be readable by everyone
+            let mut file = {
+                use std::os::unix::fs::OpenOptionsExt;
+                std::fs::OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .mode(0o600)
+                    .open(&path)
+            }
+            .map_err(|error| format!("Couldn't create {}: {error}", path.display()))?;
+            file.write_all(format!("default = {}\n\n{profile}", quote(name)).as_bytes())
+                .map_err(|error| format!("Couldn't write {}: {error}", path.display()))?;
+            Ok(path)
+        }
+    }
+}
+
+/// Where to look for the config, most specific first
+fn config_paths() -> Vec<PathBuf> {
+    let mut paths = vec![PathBuf::from(CONFIG_FILE)];
+    let home = std::env::var_os("HOME").map(PathBuf::from);
+    match std::env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => paths.push(PathBuf::from(dir).join("mcsc").join(CONFIG_FILE)),
+        None => {
+            if let Some(home) = home {
+                paths.push(home.join(".config").join("mcsc").join(CONFIG_FILE));
+            }
+        }
+    }
+    let dirs = std::env::var("XDG_CONFIG_DIRS")
+        .ok()
+        .filter(|dirs| !dirs.is_empty())
+        .unwrap_or_else(|| "/etc/xdg".to_string());
+    for dir in dirs.split(':').filter(|dir| !dir.is_empty()) {
+        paths.push(PathBuf::from(dir).join("mcsc").join(CONFIG_FILE));
+    }
+    paths
+}
+
+pub async fn connect(
+    config: &Config,
+) -> Result<ControllerClient<Channel>, tonic::transport::Error> {
+    ControllerClient::connect(config.ip.to_owned()).await
+}
+
+/// Ask the server for a token for one action, it comes encrypted with our key
+pub async fn auth(
+    client: &mut ControllerClient<Channel>,
+    action: AuthAction,
+    config: &Config,
+) -> Result<Vec<u8>, tonic::Status> {
+    let key = client
+        .auth(AuthRequest {
+            action: action.into(),
+            user: config.user.clone(),
+        })
+        .await?
+        .into_inner();
+    decrypt(&key.key, &config.key)
+        .map_err(|_| tonic::Status::unauthenticated("Couldn't decrypt the token, check the key"))
+}
+
+/// The typed error mcsc-server put in a failed rpc's status. Failures from tonic or the client
+/// itself carry none, one is made up from the status code
+#[allow(dead_code)] // The gui only shows the message
+pub fn error_of(status: &tonic::Status) -> Error {
+    match prost::Message::decode(status.details()) {
+        Ok(error) if !status.details().is_empty() => error,
+        _ => Error {
+            code: match status.code() {
+                tonic::Code::Unavailable => ErrorCode::Unreachable,
+                tonic::Code::Unauthenticated => ErrorCode::InvalidToken,
+                tonic::Code::PermissionDenied => ErrorCode::NotPermitted,
+                _ => ErrorCode::InternalError,
+            }
+            .into(),
+            message: status.message().to_string(),
+            details: Default::default(),
+        },
+    }
+}
+
+fn decrypt(data: &Vec<u8>, key: &str) -> Result<Vec<u8>, magic_crypt::MagicCryptError> {
+    let key = new_magic_crypt!(key, 256);
+    key.decrypt_bytes_to_bytes(data)
+}
+
+/// Download a backup into `out`, the newest when `name` is empty. `progress` is called with the
+/// bytes received so far and the size of the backup
+pub async fn download(
+    client: &mut ControllerClient<Channel>,
+    config: &Config,
+    name: &str,
+    out: &mut impl Write,
+    mut progress: impl FnMut(u64, u64),
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let token = auth(client, AuthAction::Download, config).await?;
+    let request = DownloadRequest {
+        token,
+        name: name.to_string(),
+    };
+    let mut stream = client.download(request).await?.into_inner();
+    let mut received = 0;
+    while let Some(part) = stream.message().await? {
+        out.write_all(&part.data)?;
+        received += part.data.len() as u64;
+        progress(received, part.size);
+    }
+    Ok(())
+}
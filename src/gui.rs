@@ -1,3 +1,680 @@
-fn main() {
-    println!("Gui comming soon");
+mod client;
+pub mod actions {
+    tonic::include_proto!("actions");
+}
+
+use actions::{
+    AuthAction, BackupFile, BackupRequest, BackupsRequest, CommandRequest, ConsoleRequest,
+    LaunchRequest, ServerPhase, StatusRequest, StatusResponce, StopRequest,
+};
+use eframe::egui;
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    future::Future,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::mpsc::{channel, Receiver, Sender},
+    time::{Duration, Instant},
+};
+
+/// Saved servers, relative to the working directory
+const PROFILES_FILE: &str = "mcsc_gui.toml";
+/// How often to ask the server how it's doing
+const STATUS_INTERVAL: Duration = Duration::from_secs(5);
+/// How many console lines to keep on screen
+const CONSOLE_LINES: usize = 1000;
+
+fn main() -> eframe::Result<()> {
+    let options = eframe::NativeOptions {
+        viewport: egui::ViewportBuilder::default().with_inner_size([960.0, 640.0]),
+        ..Default::default()
+    };
+    eframe::run_native(
+        "mcsc",
+        options,
+        Box::new(|cc| Ok(Box::new(Gui::new(cc.egui_ctx.clone())))),
+    )
+}
+
+/// A server the gui knows about
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+struct Profile {
+    name: String,
+    ip: String,
+    #[serde(default)]
+    key: String,
+    /// Environment variable holding the key, for profiles from mcsc_client.toml
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    key_env: Option<String>,
+    /// File holding the key, for profiles from mcsc_client.toml
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    key_file: Option<PathBuf>,
+    #[serde(default)]
+    user: String,
+}
+
+impl Profile {
+    /// How to connect, reading the key from where the profile keeps it
+    fn config(&self) -> Result<client::Config, String> {
+        client::Profile {
+            ip: self.ip.clone(),
+            key: (!self.key.is_empty()).then(|| self.key.clone()),
+            key_env: self.key_env.clone(),
+            key_file: self.key_file.clone(),
+            user: self.user.clone(),
+        }
+        .resolve(&self.name)
+    }
+
+    /// Where the key comes from when it isn't typed in
+    fn key_source(&self) -> Option<String> {
+        match (&self.key_env, &self.key_file) {
+            (Some(variable), _) => Some(format!("from ${variable}")),
+            (_, Some(path)) => Some(format!("from {}", path.display())),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Default)]
+struct Profiles {
+    #[serde(default)]
+    profiles: Vec<Profile>,
+}
+
+/// Load the saved servers, starting with mcsc_client.toml's profiles when there are none yet
+fn load_profiles() -> Vec<Profile> {
+    let saved = std::fs::read_to_string(PROFILES_FILE)
+        .ok()
+        .and_then(|text| toml::from_str::<Profiles>(&text).ok())
+        .map(|saved| saved.profiles)
+        .unwrap_or_default();
+    if !saved.is_empty() {
+        return saved;
+    }
+    client::Config::load_all()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(name, profile)| Profile {
+            name,
+            ip: profile.ip,
+            key: profile.key.unwrap_or_default(),
+            key_env: profile.key_env,
+            key_file: profile.key_file,
+            user: profile.user,
+        })
+        .collect()
+}
+
+fn save_profiles(profiles: &[Profile]) -> Result<(), String> {
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+    let text = toml::to_string(&Profiles {
+        profiles: profiles.to_vec(),
+    })
+    .map_err(|error| error.to_string())?;
+    // Keys shouldn't be readable by everyone, files from before this was done are fixed up too
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(PROFILES_FILE)
+        .map_err(|error| error.to_string())?;
+    file.set_permissions(std::fs::Permissions::from_mode(0o600))
+        .map_err(|error| error.to_string())?;
+    file.write_all(text.as_bytes())
+        .map_err(|error| error.to_string())
+}
+
+/// Results of the requests running in the background, tagged with the selection they were for
+enum Event {
+    Status(u64, Result<StatusResponce, String>),
+    /// Comment from an operation like Launch
+    Message(u64, String),
+    ConsoleLine(u64, String),
+    Backups(u64, Result<Vec<BackupFile>, String>),
+    DownloadProgress(u64, u64),
+    DownloadDone(Result<String, String>),
+}
+
+/// Operations replied to with an OpResponce
+enum Op {
+    Launch,
+    Stop { force: bool },
+    Backup,
+    Command(String),
+}
+
+struct Gui {
+    /// Runs the grpc requests, the results come back through `events`
+    runtime: tokio::runtime::Runtime,
+    ctx: egui::Context,
+    send: Sender<Event>,
+    events: Receiver<Event>,
+    profiles: Vec<Profile>,
+    selected: Option<usize>,
+    /// Counts selections, so replies for a profile that has since been deselected, edited or
+    /// removed can be told apart from the current one's even when it's at the same index
+    selection: u64,
+    /// The profile being added or edited and its index, None for a new one
+    editing: Option<(Option<usize>, Profile)>,
+    status: Option<Result<StatusResponce, String>>,
+    status_asked: Option<Instant>,
+    message: String,
+    console: Vec<String>,
+    console_task: Option<tokio::task::JoinHandle<()>>,
+    command: String,
+    backups: Vec<BackupFile>,
+    download_directory: String,
+    /// Bytes received and the size of the backup being downloaded
+    download: Option<(u64, u64)>,
+    /// Someone is online and the user is asked whether to stop anyway
+    confirm_stop: bool,
+}
+
+impl Gui {
+    fn new(ctx: egui::Context) -> Self {
+        let (send, events) = channel();
+        let profiles = load_profiles();
+        let mut gui = Gui {
+            runtime: tokio::runtime::Runtime::new().expect("Couldn't start the async runtime"),
+            ctx,
+            send,
+            events,
+            selected: None,
+            selection: 0,
+            profiles,
+            editing: None,
+            status: None,
+            status_asked: None,
+            message: String::new(),
+            console: Vec::new(),
+            console_task: None,
+            command: String::new(),
+            backups: Vec::new(),
+            download_directory: ".".to_string(),
+            download: None,
+            confirm_stop: false,
+        };
+        if !gui.profiles.is_empty() {
+            gui.select(0);
+        }
+        gui
+    }
+
+    /// Run a request in the background, its event wakes the gui up
+    fn spawn(&self, request: impl Future<Output = Event> + Send + 'static) {
+        let send = self.send.clone();
+        let ctx = self.ctx.clone();
+        self.runtime.spawn(async move {
+            let _ = send.send(request.await);
+            ctx.request_repaint();
+        });
+    }
+
+    fn select(&mut self, index: usize) {
+        if let Some(task) = self.console_task.take() {
+            task.abort();
+        }
+        self.selected = Some(index);
+        self.selection += 1;
+        self.status = None;
+        self.status_asked = None;
+        self.message.clear();
+        self.console.clear();
+        self.backups.clear();
+        let selection = self.selection;
+        let config = match self.profiles[index].config() {
+            Ok(config) => config,
+            Err(error) => {
+                self.message = error;
+                return;
+            }
+        };
+        let send = self.send.clone();
+        let ctx = self.ctx.clone();
+        self.console_task = Some(self.runtime.spawn(async move {
+            if let Err(error) = follow_console(selection, config, &send, &ctx).await {
+                let _ = send.send(Event::ConsoleLine(selection, format!("[{error}]")));
+                ctx.request_repaint();
+            }
+        }));
+        self.refresh_backups();
+    }
+
+    /// The selected profile's config, with the selection to tag replies with
+    fn config(&self) -> Option<(u64, client::Config)> {
+        let index = self.selected?;
+        Some((self.selection, self.profiles.get(index)?.config().ok()?))
+    }
+
+    fn refresh_status(&mut self) {
+        if let Some((selection, config)) = self.config() {
+            self.status_asked = Some(Instant::now());
+            self.spawn(async move { Event::Status(selection, status(config).await) });
+        }
+    }
+
+    fn refresh_backups(&self) {
+        if let Some((selection, config)) = self.config() {
+            self.spawn(async move { Event::Backups(selection, backups(config).await) });
+        }
+    }
+
+    fn run(&mut self, op: Op) {
+        if let Some((selection, config)) = self.config() {
+            self.message = "Waiting for the server...".to_string();
+            self.spawn(async move { Event::Message(selection, run_op(config, op).await) });
+        }
+    }
+
+    fn download(&mut self, name: String) {
+        let Some((_, config)) = self.config() else {
+            return;
+        };
+        let path = Path::new(&self.download_directory).join(&name);
+        let send = self.send.clone();
+        let ctx = self.ctx.clone();
+        self.download = Some((0, 0));
+        self.spawn(async move {
+            let result = download(config, name, &path, |received, size| {
+                let _ = send.send(Event::DownloadProgress(received, size));
+                ctx.request_repaint();
+            })
+            .await;
+            Event::DownloadDone(result.map(|_| path.display().to_string()))
+        });
+    }
+
+    fn handle_events(&mut self) {
+        while let Ok(event) = self.events.try_recv() {
+            match event {
+                // Replies about a server that's no longer selected are stale
+                Event::Status(selection, status) if selection == self.selection => {
+                    self.status = Some(status)
+                }
+                Event::Message(selection, message) if selection == self.selection => {
+                    self.message = message;
+                    self.refresh_status();
+                    self.refresh_backups();
+                }
+                Event::ConsoleLine(selection, line) if selection == self.selection => {
+                    self.console.push(line);
+                    if self.console.len() > CONSOLE_LINES {
+                        self.console.remove(0);
+                    }
+                }
+                Event::Backups(selection, backups) if selection == self.selection => {
+                    match backups {
+                        Ok(backups) => self.backups = backups,
+                        Err(error) => self.message = error,
+                    }
+                }
+                Event::DownloadProgress(received, size) => self.download = Some((received, size)),
+                Event::DownloadDone(result) => {
+                    self.download = None;
+                    self.message = match result {
+                        Ok(path) => format!("Saved {path}"),
+                        Err(error) => format!("Download failed: {error}"),
+                    };
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn profiles_panel(&mut self, ui: &mut egui::Ui) {
+        ui.heading("Servers");
+        let mut selected = None;
+        for (index, profile) in self.profiles.iter().enumerate() {
+            if ui
+                .selectable_label(self.selected == Some(index), &profile.name)
+                .clicked()
+            {
+                selected = Some(index);
+            }
+        }
+        if let Some(index) = selected {
+            self.select(index);
+        }
+        ui.separator();
+        ui.horizontal(|ui| {
+            if ui.button("Add").clicked() {
+                self.editing = Some((None, Profile::default()));
+            }
+            if let Some(index) = self.selected {
+                if ui.button("Edit").clicked() {
+                    self.editing = Some((Some(index), self.profiles[index].clone()));
+                }
+                if ui.button("Remove").clicked() {
+                    self.profiles.remove(index);
+                    self.selected = None;
+                    self.selection += 1;
+                    // Drop an edit of the removed profile, one of a later profile follows it up a place
+                    self.editing = match self.editing.take() {
+                        Some((Some(editing), _)) if editing == index => None,
+                        Some((Some(editing), profile)) if editing > index => {
+                            Some((Some(editing - 1), profile))
+                        }
+                        editing => editing,
+                    };
+                    if let Some(task) = self.console_task.take() {
+                        task.abort();
+                    }
+                    self.message = save_profiles(&self.profiles).err().unwrap_or_default();
+                }
+            }
+        });
+        let Some((index, profile)) = &mut self.editing else {
+            return;
+        };
+        ui.separator();
+        egui::Grid::new("profile").num_columns(2).show(ui, |ui| {
+            ui.label("Name");
+            ui.text_edit_singleline(&mut profile.name);
+            ui.end_row();
+            ui.label("Address");
+            ui.add(egui::TextEdit::singleline(&mut profile.ip).hint_text("http://host:port"));
+            ui.end_row();
+            ui.label("User");
+            ui.add(egui::TextEdit::singleline(&mut profile.user).hint_text("shared key"));
+            ui.end_row();
+            ui.label("Key");
+            let source = profile.key_source().unwrap_or_default();
+            ui.add(
+                egui::TextEdit::singleline(&mut profile.key)
+                    .password(true)
+                    .hint_text(source),
+            );
+            ui.end_row();
+        });
+        let mut done = false;
+        ui.horizontal(|ui| {
+            if ui.button("Save").clicked() {
+                // A typed in key replaces the one from elsewhere
+                if !profile.key.is_empty() {
+                    profile.key_env = None;
+                    profile.key_file = None;
+                }
+                let index = match *index {
+                    Some(index) if index < self.profiles.len() => {
+                        self.profiles[index] = profile.clone();
+                        index
+                    }
+                    _ => {
+                        self.profiles.push(profile.clone());
+                        self.profiles.len() - 1
+                    }
+                };
+                self.message = save_profiles(&self.profiles).err().unwrap_or_default();
+                selected = Some(index);
+                done = true;
+            }
+            if ui.button("Cancel").clicked() {
+                done = true;
+            }
+        });
+        if done {
+            self.editing = None;
+            if let Some(index) = selected {
+                self.select(index);
+            }
+        }
+    }
+
+    fn server_panel(&mut self, ui: &mut egui::Ui) {
+        let online = match &self.status {
+            Some(Ok(status)) => {
+                let phase = ServerPhase::from_i32(status.phase).unwrap_or_default();
+                ui.heading(phase.as_str_name());
+                ui.label(&status.comment);
+                if !status.players.is_empty() {
+                    ui.label(format!("Online: {}", status.players.join(", ")));
+                }
+                status.online
+            }
+            Some(Err(error)) => {
+                ui.heading("Unreachable");
+                ui.label(error);
+                0
+            }
+            None => {
+                ui.heading("...");
+                0
+            }
+        };
+        ui.horizontal(|ui| {
+            if ui.button("Launch").clicked() {
+                self.run(Op::Launch);
+            }
+            if ui.button("Stop").clicked() {
+                if online > 0 {
+                    self.confirm_stop = true;
+                } else {
+                    self.run(Op::Stop { force: false });
+                }
+            }
+            if ui.button("Back up").clicked() {
+                self.run(Op::Backup);
+            }
+        });
+        if !self.message.is_empty() {
+            ui.label(&self.message);
+        }
+        if self.confirm_stop {
+            egui::Window::new("Stop the server?")
+                .collapsible(false)
+                .resizable(false)
+                .show(ui.ctx(), |ui| {
+                    ui.label(match online {
+                        1 => "1 player is online".to_string(),
+                        online => format!("{online} players are online"),
+                    });
+                    ui.horizontal(|ui| {
+                        if ui.button("Stop anyway").clicked() {
+                            self.confirm_stop = false;
+                            self.run(Op::Stop { force: true });
+                        }
+                        if ui.button("Cancel").clicked() {
+                            self.confirm_stop = false;
+                        }
+                    });
+                });
+        }
+
+        ui.separator();
+        ui.heading("Console");
+        egui::ScrollArea::vertical()
+            .stick_to_bottom(true)
+            .auto_shrink([false, false])
+            .max_height(ui.available_height() * 0.55)
+            .show(ui, |ui| {
+                for line in &self.console {
+                    ui.label(egui::RichText::new(line).monospace());
+                }
+            });
+        ui.horizontal(|ui| {
+            let input = ui.add(
+                egui::TextEdit::singleline(&mut self.command)
+                    .hint_text("Command, eg. say hello")
+                    .desired_width(ui.available_width() - 60.0),
+            );
+            let entered = input.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+            if (ui.button("Run").clicked() || entered) && !self.command.is_empty() {
+                let command = std::mem::take(&mut self.command);
+                self.run(Op::Command(command));
+                input.request_focus();
+            }
+        });
+
+        ui.separator();
+        ui.horizontal(|ui| {
+            ui.heading("Backups");
+            if ui.button("Refresh").clicked() {
+                self.refresh_backups();
+            }
+            ui.label("Save to");
+            ui.text_edit_singleline(&mut self.download_directory);
+        });
+        if let Some((received, size)) = self.download {
+            let fraction = if size > 0 {
+                received as f32 / size as f32
+            } else {
+                0.0
+            };
+            ui.add(egui::ProgressBar::new(fraction).show_percentage());
+        }
+        let mut download = None;
+        egui::ScrollArea::vertical()
+            .id_salt("backups")
+            .auto_shrink([false, true])
+            .show(ui, |ui| {
+                egui::Grid::new("backups").striped(true).show(ui, |ui| {
+                    for backup in &self.backups {
+                        ui.label(&backup.name);
+                        ui.label(format!("{:.1} MB", backup.size as f64 / 1048576.0));
+                        if ui
+                            .add_enabled(self.download.is_none(), egui::Button::new("Download"))
+                            .clicked()
+                        {
+                            download = Some(backup.name.clone());
+                        }
+                        ui.end_row();
+                    }
+                });
+            });
+        if let Some(name) = download {
+            self.download(name);
+        }
+    }
+}
+
+impl eframe::App for Gui {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.handle_events();
+        if self.selected.is_some()
+            && self
+                .status_asked
+                .is_none_or(|asked| asked.elapsed() >= STATUS_INTERVAL)
+        {
+            self.refresh_status();
+        }
+        ctx.request_repaint_after(STATUS_INTERVAL);
+        egui::SidePanel::left("profiles")
+            .resizable(true)
+            .show(ctx, |ui| self.profiles_panel(ui));
+        egui::CentralPanel::default().show(ctx, |ui| {
+            if self.selected.is_some() {
+                self.server_panel(ui);
+            } else {
+                ui.heading("Add a server to get started");
+            }
+        });
+    }
+}
+
+async fn status(config: client::Config) -> Result<StatusResponce, String> {
+    let mut client = client::connect(&config)
+        .await
+        .map_err(|error| error.to_string())?;
+    let token = client::auth(&mut client, AuthAction::Status, &config)
+        .await
+        .map_err(|status| status.message().to_string())?;
+    let status = client
+        .status(StatusRequest { token })
+        .await
+        .map_err(|status| status.message().to_string())?
+        .into_inner();
+    Ok(status)
+}
+
+async fn backups(config: client::Config) -> Result<Vec<BackupFile>, String> {
+    let mut client = client::connect(&config)
+        .await
+        .map_err(|error| error.to_string())?;
+    let token = client::auth(&mut client, AuthAction::Backups, &config)
+        .await
+        .map_err(|status| status.message().to_string())?;
+    let backups = client
+        .backups(BackupsRequest { token })
+        .await
+        .map_err(|status| status.message().to_string())?
+        .into_inner();
+    Ok(backups.backups)
+}
+
+/// The server's comment on how the operation went
+async fn run_op(config: client::Config, op: Op) -> String {
+    let result: Result<String, tonic::Status> = async {
+        let mut client = client::connect(&config)
+            .await
+            .map_err(|error| tonic::Status::unavailable(error.to_string()))?;
+        let action = match op {
+            Op::Launch => AuthAction::Launch,
+            Op::Stop { .. } => AuthAction::Stop,
+            Op::Backup => AuthAction::Backup,
+            Op::Command(_) => AuthAction::Command,
+        };
+        let token = client::auth(&mut client, action, &config).await?;
+        let responce = match op {
+            Op::Launch => client.launch(LaunchRequest { token, wait: true }).await?,
+            Op::Stop { force } => client.stop(StopRequest { token, force }).await?,
+            Op::Backup => client.backup(BackupRequest { token }).await?,
+            Op::Command(command) => client.command(CommandRequest { token, command }).await?,
+        };
+        Ok(responce.into_inner().comment)
+    }
+    .await;
+    result.unwrap_or_else(|status| status.message().to_string())
+}
+
+/// Pass console lines to the gui until the stream ends or the task is aborted
+async fn follow_console(
+    selection: u64,
+    config: client::Config,
+    send: &Sender<Event>,
+    ctx: &egui::Context,
+) -> Result<(), String> {
+    let mut client = client::connect(&config)
+        .await
+        .map_err(|error| error.to_string())?;
+    let token = client::auth(&mut client, AuthAction::Console, &config)
+        .await
+        .map_err(|status| status.message().to_string())?;
+    let mut lines = client
+        .console(ConsoleRequest {
+            token,
+            history: 200,
+        })
+        .await
+        .map_err(|status| status.message().to_string())?
+        .into_inner();
+    while let Some(line) = lines
+        .message()
+        .await
+        .map_err(|status| status.message().to_string())?
+    {
+        let _ = send.send(Event::ConsoleLine(selection, line.line));
+        ctx.request_repaint();
+    }
+    Ok(())
+}
+
+async fn download(
+    config: client::Config,
+    name: String,
+    path: &Path,
+    progress: impl FnMut(u64, u64),
+) -> Result<(), String> {
+    let mut client = client::connect(&config)
+        .await
+        .map_err(|error| error.to_string())?;
+    let mut file = std::fs::File::create(path).map_err(|error| error.to_string())?;
+    let result = client::download(&mut client, &config, &name, &mut file, progress).await;
+    if let Err(error) = result {
+        // Don't leave half a backup behind
+        let _ = std::fs::remove_file(path);
+        return Err(error.to_string());
+    }
+    Ok(())
 }
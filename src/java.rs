@@ -0,0 +1,315 @@
+use std::{
+    ffi::OsString,
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::Mutex,
+};
+
+/// Name of the file inside `java_directory` listing extra java homes registered by path
+const REGISTERED_FILE: &str = "registered.txt";
+
+/// The java directory and what the last discover found in it, so a launch doesn't have to run
+/// every java on the machine again
+static DISCOVERED: Mutex<Option<(String, Vec<JavaRuntime>)>> = Mutex::new(None);
+
+/// A java installation that can be used to run the minecraft server
+#[derive(Debug, Clone)]
+pub struct JavaRuntime {
+    /// Major version, 8 for "1.8.0_292", 17 for "17.0.2"
+    pub major: u32,
+    /// Full version string as reported by the runtime
+    pub version: String,
+    /// The java home, bin/java lives in here
+    pub home: PathBuf,
+    /// Where the runtime was found, eg. "JAVA_HOME", "PATH", "system", "managed" or "registered"
+    pub source: &'static str,
+}
+
+impl JavaRuntime {
+    /// Path to the java executable of this runtime
+    pub fn executable(&self) -> PathBuf {
+        java_executable(&self.home)
+    }
+
+    /// PATH with the bin directory of this runtime prepended, so `java` in launch.sh resolves to it
+    pub fn path_var(&self) -> OsString {
+        let mut paths = vec![self.home.join("bin")];
+        if let Some(path) = std::env::var_os("PATH") {
+            paths.extend(std::env::split_paths(&path));
+        }
+        std::env::join_paths(paths).unwrap_or_default()
+    }
+}
+
+#[derive(Debug)]
+pub enum RegisterError {
+    /// Nothing exists at the given path
+    NotFound,
+    /// The path (or the archive contents) doesn't contain a java home
+    NotJava,
+    /// The archive format isn't supported, only .tar.gz, .tgz, .tar.xz and .zip are
+    UnsupportedArchive,
+    /// Failed to extract the archive
+    Extraction,
+    /// Failed to write to the java directory
+    Io,
+}
+
+/// Find every java runtime available on this machine, the first runtime of each major version
+/// is the preferred one, managed and registered runtimes are preferred over system ones
+pub fn discover(java_directory: &str) -> Vec<JavaRuntime> {
+    let mut candidates: Vec<(PathBuf, &'static str)> = Vec::new();
+
+    // Runtimes extracted into the java directory by mcsc
+    for dir in sub_directories(Path::new(java_directory)) {
+        candidates.push((dir, "managed"));
+    }
+    // Runtimes registered by path
+    for dir in registered(java_directory) {
+        candidates.push((dir, "registered"));
+    }
+    if let Some(home) = std::env::var_os("JAVA_HOME") {
+        candidates.push((PathBuf::from(home), "JAVA_HOME"));
+    }
+    if let Some(path) = std::env::var_os("PATH") {
+        for dir in std::env::split_paths(&path) {
+            let java = dir.join("java");
+            if let Ok(real) = fs::canonicalize(java) {
+                // <home>/bin/java
+                if let Some(home) = real.parent().and_then(Path::parent) {
+                    candidates.push((home.to_path_buf(), "PATH"));
+                }
+            }
+        }
+    }
+    for root in [
+        "/usr/lib/jvm",
+        "/usr/java",
+        "/opt/java",
+        "/Library/Java/JavaVirtualMachines",
+    ] {
+        for dir in sub_directories(Path::new(root)) {
+            candidates.push((dir, "system"));
+        }
+    }
+    if let Some(home) = std::env::var_os("HOME") {
+        for dir in sub_directories(&Path::new(&home).join(".jdks")) {
+            candidates.push((dir, "system"));
+        }
+    }
+
+    let mut seen = Vec::new();
+    let mut runtimes = Vec::new();
+    for (dir, source) in candidates {
+        let home = match find_home(&dir) {
+            Some(home) => home,
+            None => continue,
+        };
+        let canonical = fs::canonicalize(&home).unwrap_or_else(|_| home.clone());
+        if seen.contains(&canonical) {
+            continue;
+        }
+        seen.push(canonical);
+        if let Some(version) = java_version(&home) {
+            if let Some(major) = major_version(&version) {
+                runtimes.push(JavaRuntime {
+                    major,
+                    version,
+                    home,
+                    source,
+                });
+            }
+        }
+    }
+    if let Ok(mut discovered) = DISCOVERED.lock() {
+        *discovered = Some((java_directory.to_string(), runtimes.clone()));
+    }
+    runtimes
+}
+
+/// Find the preferred runtime for a major version. Uses the last discover while its runtime is
+/// still there, looking again otherwise
+pub fn find(java_directory: &str, major: u32) -> Option<JavaRuntime> {
+    let cached = DISCOVERED.lock().ok().and_then(|discovered| {
+        let (directory, runtimes) = discovered.as_ref()?;
+        if directory != java_directory {
+            return None;
+        }
+        runtimes
+            .iter()
+            .find(|runtime| runtime.major == major && runtime.executable().is_file())
+            .cloned()
+    });
+    cached.or_else(|| {
+        discover(java_directory)
+            .into_iter()
+            .find(|runtime| runtime.major == major)
+    })
+}
+
+/// Make a runtime available to mcsc, directories are recorded in `registered.txt` while archives
+/// are extracted into the java directory
+pub fn register(java_directory: &str, path: &str) -> Result<JavaRuntime, RegisterError> {
+    // It may be preferred over a runtime found before
+    if let Ok(mut discovered) = DISCOVERED.lock() {
+        *discovered = None;
+    }
+    let path = Path::new(path);
+    if !path.exists() {
+        return Err(RegisterError::NotFound);
+    }
+    if fs::create_dir_all(java_directory).is_err() {
+        return Err(RegisterError::Io);
+    }
+
+    let home = if path.is_dir() {
+        let home = find_home(path).ok_or(RegisterError::NotJava)?;
+        let home = fs::canonicalize(&home).unwrap_or(home);
+        if !registered(java_directory).contains(&home) {
+            let mut list = fs::read_to_string(Path::new(java_directory).join(REGISTERED_FILE))
+                .unwrap_or_default();
+            list.push_str(&format!("{}\n", home.display()));
+            if fs::write(Path::new(java_directory).join(REGISTERED_FILE), list).is_err() {
+                return Err(RegisterError::Io);
+            }
+        }
+        home
+    } else {
+        extract(java_directory, path)?
+    };
+
+    let version = java_version(&home).ok_or(RegisterError::NotJava)?;
+    let major = major_version(&version).ok_or(RegisterError::NotJava)?;
+    Ok(JavaRuntime {
+        major,
+        version,
+        home,
+        source: if path.is_dir() {
+            "registered"
+        } else {
+            "managed"
+        },
+    })
+}
+
+/// Extract a jdk archive into its own folder in the java directory and return the java home
+fn extract(java_directory: &str, archive: &Path) -> Result<PathBuf, RegisterError> {
+    let name = archive
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let (stem, mut command) = if let Some(stem) = [".tar.gz", ".tgz", ".tar.xz"]
+        .iter()
+        .find_map(|ext| name.strip_suffix(ext))
+    {
+        let mut command = Command::new("tar");
+        command.arg("-xf").arg(archive).arg("-C");
+        (stem.to_string(), command)
+    } else if let Some(stem) = name.strip_suffix(".zip") {
+        let mut command = Command::new("unzip");
+        command.arg("-q").arg(archive).arg("-d");
+        (stem.to_string(), command)
+    } else {
+        return Err(RegisterError::UnsupportedArchive);
+    };
+
+    let destination = Path::new(java_directory).join(stem);
+    if destination.exists() {
+        // Already extracted
+        return find_home(&destination).ok_or(RegisterError::NotJava);
+    }
+    if fs::create_dir_all(&destination).is_err() {
+        return Err(RegisterError::Io);
+    }
+    match command.arg(&destination).status() {
+        Ok(status) if status.success() => {}
+        _ => {
+            let _ = fs::remove_dir_all(&destination);
+            return Err(RegisterError::Extraction);
+        }
+    }
+    match find_home(&destination) {
+        Some(home) => Ok(home),
+        None => {
+            let _ = fs::remove_dir_all(&destination);
+            Err(RegisterError::NotJava)
+        }
+    }
+}
+
+/// Java homes registered by path
+fn registered(java_directory: &str) -> Vec<PathBuf> {
+    fs::read_to_string(Path::new(java_directory).join(REGISTERED_FILE))
+        .unwrap_or_default()
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(PathBuf::from)
+        .collect()
+}
+
+fn sub_directories(dir: &Path) -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.is_dir())
+            .collect(),
+        Err(_) => return Vec::new(),
+    };
+    // Newest versions tend to sort last
+    dirs.sort();
+    dirs.reverse();
+    dirs
+}
+
+/// Look for a java home in a directory, archives usually nest it one level deep and macOS
+/// bundles keep it in Contents/Home
+fn find_home(dir: &Path) -> Option<PathBuf> {
+    let mut candidates = vec![dir.to_path_buf(), dir.join("Contents/Home")];
+    for sub in sub_directories(dir) {
+        candidates.push(sub.join("Contents/Home"));
+        candidates.push(sub);
+    }
+    candidates
+        .into_iter()
+        .find(|home| java_executable(home).is_file())
+}
+
+fn java_executable(home: &Path) -> PathBuf {
+    home.join("bin/java")
+}
+
+/// Read the version from the `release` file of the java home, falling back to `java -version`
+fn java_version(home: &Path) -> Option<String> {
+    if let Ok(release) = fs::read_to_string(home.join("release")) {
+        let version = release
+            .lines()
+            .find_map(|line| line.strip_prefix("JAVA_VERSION="))
+            .map(|version| version.trim().trim_matches('"').to_string());
+        if version.is_some() {
+            return version;
+        }
+    }
+    // `java -version` prints to stderr, eg. openjdk version "17.0.2" 2022-01-18
+    let output = Command::new(java_executable(home))
+        .arg("-version")
+        .output()
+        .ok()?;
+    let text = String::from_utf8_lossy(&output.stderr);
+    let start = text.find('"')? + 1;
+    let end = start + text[start..].find('"')?;
+    Some(text[start..end].to_string())
+}
+
+/// "1.8.0_292" -> 8, "17.0.2" -> 17, "21" -> 21
+fn major_version(version: &str) -> Option<u32> {
+    let mut parts = version.split(|c: char| !c.is_ascii_digit());
+    let first: u32 = parts.next()?.parse().ok()?;
+    if first == 1 {
+        parts.next()?.parse().ok()
+    } else {
+        Some(first)
+    }
+}
@@ -0,0 +1,276 @@
+use lazy_regex::regex_is_match;
+use serde_json::{json, Value};
+use std::{fs, net::IpAddr, time::SystemTime};
+
+/// The player lists minecraft keeps next to server.properties
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum List {
+    Whitelist,
+    Ops,
+    BannedPlayers,
+    BannedIps,
+}
+
+impl List {
+    pub fn file(&self) -> &'static str {
+        match self {
+            List::Whitelist => "whitelist.json",
+            List::Ops => "ops.json",
+            List::BannedPlayers => "banned-players.json",
+            List::BannedIps => "banned-ips.json",
+        }
+    }
+}
+
+/// An entry of any list, fields a list doesn't have are left empty
+#[derive(Debug, Default)]
+pub struct Entry {
+    pub name: String,
+    pub uuid: String,
+    pub ip: String,
+    pub level: u32,
+    pub reason: String,
+    pub created: String,
+    pub source: String,
+    pub expires: String,
+}
+
+/// A change to a list
+#[derive(Debug, Clone)]
+pub struct Edit {
+    pub list: List,
+    pub add: bool,
+    /// Player name, or ip for the ip ban list
+    pub name: String,
+    /// Ban reason
+    pub reason: String,
+}
+
+#[derive(Debug)]
+pub enum ListError {
+    /// Not a valid player name or ip
+    InvalidName,
+    /// Couldn't find the uuid of the player
+    UnknownPlayer,
+    /// The player or ip is already on the list
+    AlreadyListed,
+    /// The player or ip isn't on the list
+    NotListed,
+    /// The list file exists but isn't valid json
+    Corrupt,
+    Io,
+}
+
+impl Edit {
+    /// Check the name so it can't smuggle anything into a console command
+    pub fn validate(&self) -> Result<(), ListError> {
+        let valid = match self.list {
+            List::BannedIps => self.name.parse::<IpAddr>().is_ok(),
+            _ => regex_is_match!(r"^[A-Za-z0-9_]{1,16}$", &self.name),
+        };
+        if valid {
+            Ok(())
+        } else {
+            Err(ListError::InvalidName)
+        }
+    }
+
+    /// The console command making this change while the server is running
+    pub fn command(&self) -> String {
+        let reason = self.reason.replace(['\n', '\r'], " ");
+        let command = match (self.list, self.add) {
+            (List::Whitelist, true) => format!("whitelist add {}", self.name),
+            (List::Whitelist, false) => format!("whitelist remove {}", self.name),
+            (List::Ops, true) => format!("op {}", self.name),
+            (List::Ops, false) => format!("deop {}", self.name),
+            (List::BannedPlayers, true) => format!("ban {} {reason}", self.name),
+            (List::BannedPlayers, false) => format!("pardon {}", self.name),
+            (List::BannedIps, true) => format!("ban-ip {} {reason}", self.name),
+            (List::BannedIps, false) => format!("pardon-ip {}", self.name),
+        };
+        command.trim_end().to_string()
+    }
+
+    /// Make the change by editing the list file, only safe while the server is stopped since
+    /// minecraft overwrites the files with its own copy. Adds put `new_entry` in the list
+    pub fn apply(&self, new_entry: Option<Value>) -> Result<(), ListError> {
+        let mut entries = read(self.list)?;
+        let position = entries.iter().position(|entry| self.matches(entry));
+        if self.add {
+            if position.is_some() {
+                return Err(ListError::AlreadyListed);
+            }
+            entries.push(new_entry.ok_or(ListError::UnknownPlayer)?);
+        } else {
+            match position {
+                Some(i) => {
+                    entries.remove(i);
+                }
+                None => return Err(ListError::NotListed),
+            }
+        }
+        let text = serde_json::to_string_pretty(&entries).map_err(|_| ListError::Io)?;
+        fs::write(self.list.file(), text).map_err(|_| ListError::Io)
+    }
+
+    fn matches(&self, entry: &Value) -> bool {
+        let field = if self.list == List::BannedIps {
+            "ip"
+        } else {
+            "name"
+        };
+        entry[field]
+            .as_str()
+            .is_some_and(|value| value.eq_ignore_ascii_case(&self.name))
+    }
+
+    /// The entry adding the player or ip puts in the list file. Player lists need the uuid, which
+    /// may be looked up over the network
+    pub fn new_entry(&self, lookup: &Lookup) -> Result<Value, ListError> {
+        let reason = if self.reason.is_empty() {
+            "Banned by an operator."
+        } else {
+            &self.reason
+        };
+        Ok(match self.list {
+            List::BannedIps => json!({
+                "ip": self.name,
+                "created": now(),
+                "source": "mcsc",
+                "expires": "forever",
+                "reason": reason,
+            }),
+            list => {
+                let (uuid, name) = lookup.profile(&self.name)?;
+                match list {
+                    List::Ops => json!({
+                        "uuid": uuid,
+                        "name": name,
+                        "level": lookup.op_level,
+                        "bypassesPlayerLimit": false,
+                    }),
+                    List::BannedPlayers => json!({
+                        "uuid": uuid,
+                        "name": name,
+                        "created": now(),
+                        "source": "mcsc",
+                        "expires": "forever",
+                        "reason": reason,
+                    }),
+                    _ => json!({ "uuid": uuid, "name": name }),
+                }
+            }
+        })
+    }
+}
+
+/// How to find player uuids when editing the files directly
+pub struct Lookup {
+    /// Mojang profile api, `<api>/<name>` returns the uuid of an account
+    pub api: String,
+    /// Offline mode servers derive uuids from the name instead
+    pub online_mode: bool,
+    /// Permission level given to new ops
+    pub op_level: u32,
+}
+
+impl Lookup {
+    /// (uuid, correctly capitalised name)
+    fn profile(&self, name: &str) -> Result<(String, String), ListError> {
+        if !self.online_mode {
+            return Ok((offline_uuid(name), name.to_string()));
+        }
+        let url = format!("{}/{name}", self.api.trim_end_matches('/'));
+        let profile: Value = ureq::get(&url)
+            .call()
+            .map_err(|_| ListError::UnknownPlayer)?
+            .into_json()
+            .map_err(|_| ListError::UnknownPlayer)?;
+        match (profile["id"].as_str(), profile["name"].as_str()) {
+            (Some(id), Some(name)) if id.len() == 32 => Ok((
+                format!(
+                    "{}-{}-{}-{}-{}",
+                    &id[0..8],
+                    &id[8..12],
+                    &id[12..16],
+                    &id[16..20],
+                    &id[20..32]
+                ),
+                name.to_string(),
+            )),
+            _ => Err(ListError::UnknownPlayer),
+        }
+    }
+}
+
+/// Read a list, a missing file is an empty list
+pub fn entries(list: List) -> Result<Vec<Entry>, ListError> {
+    Ok(read(list)?
+        .iter()
+        .map(|value| {
+            let text = |field: &str| value[field].as_str().unwrap_or_default().to_string();
+            Entry {
+                name: text("name"),
+                uuid: text("uuid"),
+                ip: text("ip"),
+                level: value["level"].as_u64().unwrap_or_default() as u32,
+                reason: text("reason"),
+                created: text("created"),
+                source: text("source"),
+                expires: text("expires"),
+            }
+        })
+        .collect())
+}
+
+fn read(list: List) -> Result<Vec<Value>, ListError> {
+    match fs::read_to_string(list.file()) {
+        Ok(text) if text.trim().is_empty() => Ok(Vec::new()),
+        Ok(text) => serde_json::from_str(&text).map_err(|_| ListError::Corrupt),
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(_) => Err(ListError::Io),
+    }
+}
+
+/// The uuid minecraft gives players on offline mode servers, a v3 uuid of "OfflinePlayer:<name>"
+fn offline_uuid(name: &str) -> String {
+    use md5::{Digest, Md5};
+    let mut hash = Md5::digest(format!("OfflinePlayer:{name}").as_bytes());
+    hash[6] = (hash[6] & 0x0f) | 0x30;
+    hash[8] = (hash[8] & 0x3f) | 0x80;
+    let hex: String = hash.iter().map(|byte| format!("{byte:02x}")).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+/// Current time in the format minecraft uses for bans, eg. "2023-06-12 18:04:11 +0000"
+fn now() -> String {
+    let seconds = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+    let days = (seconds / 86400) as i64;
+    let time = seconds % 86400;
+    // Days since the epoch to a civil date, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02} +0000",
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
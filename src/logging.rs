@@ -0,0 +1,128 @@
+use std::{io::IsTerminal, path::Path, sync::OnceLock};
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::{
+    fmt::{self, writer::BoxMakeWriter},
+    layer::SubscriberExt,
+    reload,
+    util::SubscriberInitExt,
+    EnvFilter, Registry,
+};
+
+/// Levels a directive may be on its own, anything else needs a target, eg. "mcsc_server=debug"
+const LEVELS: &[&str] = &["off", "error", "warn", "info", "debug", "trace"];
+
+/// Swaps the filter when log_level changes on a config reload
+static FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+/// How each line is written
+#[derive(serde_derive::Deserialize, serde_derive::Serialize, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// One line of text per event, for people
+    #[default]
+    Text,
+    /// One json object per event, for log collectors
+    Json,
+}
+
+/// When to start a new log file
+#[derive(serde_derive::Deserialize, serde_derive::Serialize, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    Hourly,
+    #[default]
+    Daily,
+    /// Keep writing to the one file
+    Never,
+}
+
+/// Where the logs go and how
+pub struct LogSettings<'a> {
+    pub level: &'a str,
+    pub format: LogFormat,
+    /// stdout when unset
+    pub file: Option<&'a Path>,
+    pub rotation: LogRotation,
+    /// Rotated files to keep, 0 keeps all of them
+    pub files_kept: usize,
+}
+
+/// Parse log_level, which is a level like "info" or comma separated directives like
+/// "info,mcsc_server::rcon=debug,minecraft=warn". A bare level is for mcsc and the minecraft
+/// server, libraries log their warnings and errors unless they're named, eg. "debug,h2=debug"
+pub fn parse_level(level: &str) -> Result<EnvFilter, String> {
+    let mut directives = Vec::new();
+    for directive in level.split(',').map(str::trim) {
+        if directive.is_empty() || directive.contains(['=', '[']) {
+            directives.push(directive.to_string());
+            continue;
+        }
+        // A bare word parses as a target with every level on, so a misspelled level would log
+        // everything
+        let level = directive.to_lowercase();
+        let Some(index) = LEVELS.iter().position(|known| *known == level) else {
+            return Err(format!(
+                "{directive} isn't a level, use one of {} or target=level",
+                LEVELS.join(", ")
+            ));
+        };
+        let libraries = LEVELS[index.min(2)];
+        directives.push(format!("{libraries},mcsc_server={level},minecraft={level}"));
+    }
+    EnvFilter::builder()
+        .parse(directives.join(","))
+        .map_err(|error| error.to_string())
+}
+
+/// Install the global subscriber, only call once
+pub fn init(settings: LogSettings) -> Result<(), String> {
+    let (filter, handle) = reload::Layer::new(parse_level(settings.level)?);
+    let (writer, ansi) = match settings.file {
+        Some(file) => {
+            let rotation = match settings.rotation {
+                LogRotation::Hourly => Rotation::HOURLY,
+                LogRotation::Daily => Rotation::DAILY,
+                LogRotation::Never => Rotation::NEVER,
+            };
+            let name = file.file_name().unwrap_or("mcsc-server.log".as_ref());
+            let mut builder = RollingFileAppender::builder()
+                .rotation(rotation)
+                .filename_prefix(name.to_string_lossy());
+            if settings.files_kept > 0 {
+                builder = builder.max_log_files(settings.files_kept);
+            }
+            let directory = file.parent().unwrap_or(Path::new("."));
+            let failed = |error: &dyn std::fmt::Display| {
+                format!("Couldn't log to {}: {error}", file.display())
+            };
+            std::fs::create_dir_all(directory).map_err(|error| failed(&error))?;
+            let appender = builder.build(directory).map_err(|error| failed(&error))?;
+            (BoxMakeWriter::new(appender), false)
+        }
+        None => (
+            BoxMakeWriter::new(std::io::stdout),
+            std::io::stdout().is_terminal(),
+        ),
+    };
+    let (text, json) = match settings.format {
+        LogFormat::Text => (Some(fmt::layer().with_ansi(ansi).with_writer(writer)), None),
+        LogFormat::Json => (None, Some(fmt::layer().json().with_writer(writer))),
+    };
+    Registry::default()
+        .with(filter)
+        .with(text)
+        .with(json)
+        .try_init()
+        .map_err(|error| error.to_string())?;
+    let _ = FILTER.set(handle);
+    Ok(())
+}
+
+/// Switch to a new log_level, does nothing before init
+pub fn set_level(level: &str) -> Result<(), String> {
+    let filter = parse_level(level)?;
+    match FILTER.get() {
+        Some(handle) => handle.reload(filter).map_err(|error| error.to_string()),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,287 @@
+use lazy_regex::{regex_captures, regex_replace_all};
+use std::{
+    fs,
+    path::Path,
+    process::Command,
+    time::{Duration, Instant, SystemTime},
+};
+
+/// One look at how the server and its host are doing
+#[derive(Debug, Clone)]
+pub struct Sample {
+    pub time: SystemTime,
+    /// Of the server's whole process group, 100 is one core
+    pub cpu_percent: f64,
+    /// Resident memory of the process group
+    pub memory_bytes: u64,
+    pub world_bytes: u64,
+    pub backup_bytes: u64,
+    /// Ticks per second, when the console reported it since the last sample
+    pub tps: Option<f64>,
+    /// Milliseconds per tick, when the console reported it since the last sample
+    pub mspt: Option<f64>,
+}
+
+/// Works out cpu use from how much cpu time a process group took between two samples
+#[derive(Debug)]
+pub struct Sampler {
+    clock_ticks: u64,
+    page_size: u64,
+    /// Process group, its cpu time in clock ticks and when it was read
+    last: Option<(u32, u64, Instant)>,
+}
+
+impl Sampler {
+    pub fn new() -> Self {
+        Sampler {
+            clock_ticks: getconf("CLK_TCK").unwrap_or(100),
+            page_size: getconf("PAGESIZE").unwrap_or(4096),
+            last: None,
+        }
+    }
+
+    /// Cpu percent and memory of every process in the group, launch.sh and java both count
+    pub fn process_group(&mut self, group: u32) -> (f64, u64) {
+        let (ticks, pages) = group_usage(group);
+        let now = Instant::now();
+        let cpu_percent = match self.last {
+            Some((last_group, last_ticks, last_time)) if last_group == group => {
+                let seconds = now.duration_since(last_time).as_secs_f64();
+                let used = ticks.saturating_sub(last_ticks) as f64 / self.clock_ticks as f64;
+                if seconds > 0.0 {
+                    used / seconds * 100.0
+                } else {
+                    0.0
+                }
+            }
+            // Nothing to compare with yet
+            _ => 0.0,
+        };
+        self.last = Some((group, ticks, now));
+        (cpu_percent, pages * self.page_size)
+    }
+
+    /// Forget the last reading, for when the server isn't running
+    pub fn reset(&mut self) {
+        self.last = None;
+    }
+}
+
+/// Total cpu time in clock ticks and resident pages of the processes in a group
+fn group_usage(group: u32) -> (u64, u64) {
+    let mut ticks = 0;
+    let mut pages = 0;
+    let entries = match fs::read_dir("/proc") {
+        Ok(entries) => entries,
+        Err(_) => return (0, 0),
+    };
+    for entry in entries.flatten() {
+        let stat = match fs::read_to_string(entry.path().join("stat")) {
+            Ok(stat) => stat,
+            Err(_) => continue,
+        };
+        // The command name in brackets may contain spaces, the fields after it are state,
+        // ppid, pgrp, ... utime (12th), stime (13th) ... rss (22nd)
+        let fields: Vec<&str> = match stat.rsplit_once(')') {
+            Some((_, rest)) => rest.split_whitespace().collect(),
+            None => continue,
+        };
+        let field = |i: usize| -> u64 {
+            fields
+                .get(i)
+                .and_then(|field| field.parse().ok())
+                .unwrap_or_default()
+        };
+        if field(2) == u64::from(group) {
+            ticks += field(11) + field(12);
+            pages += field(21);
+        }
+    }
+    (ticks, pages)
+}
+
+/// How long a process has been running, from its start time in /proc
+pub fn process_uptime(pid: u32) -> Option<Duration> {
+    let stat = fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    // Clock ticks after boot, the 20th field after the command name
+    let started: u64 = stat
+        .rsplit_once(')')?
+        .1
+        .split_whitespace()
+        .nth(19)?
+        .parse()
+        .ok()?;
+    let boot: f64 = fs::read_to_string("/proc/uptime")
+        .ok()?
+        .split_whitespace()
+        .next()?
+        .parse()
+        .ok()?;
+    let started = started as f64 / getconf("CLK_TCK").unwrap_or(100) as f64;
+    Some(Duration::from_secs_f64((boot - started).max(0.0)))
+}
+
+fn getconf(name: &str) -> Option<u64> {
+    let output = Command::new("getconf").arg(name).output().ok()?;
+    String::from_utf8(output.stdout).ok()?.trim().parse().ok()
+}
+
+/// Size of everything in a directory, 0 when it doesn't exist
+pub fn disk_usage(path: &Path) -> u64 {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_) => return 0,
+    };
+    if !metadata.is_dir() {
+        return metadata.len();
+    }
+    fs::read_dir(path).map_or(0, |entries| {
+        entries
+            .flatten()
+            .map(|entry| disk_usage(&entry.path()))
+            .sum()
+    })
+}
+
+/// Tick rate as reported by the console, from vanilla's `tick query` or paper's `tps` and `mspt`
+#[derive(Debug, Default)]
+pub struct TickWatch {
+    /// Vanilla's target tick rate, the server never runs faster than it
+    target: Option<f64>,
+    tps: Option<f64>,
+    mspt: Option<f64>,
+    /// Paper puts its tick times on the line after the heading
+    mspt_follows: bool,
+}
+
+impl TickWatch {
+    /// Parse console output, which may be several lines when it came through rcon
+    pub fn observe(&mut self, text: &str) {
+        for line in text.lines() {
+            // Colours would get in the way of the numbers
+            let line = regex_replace_all!(r"\x1b\[[0-9;]*[A-Za-z]|\u{a7}.", line, |_| "");
+            let message = crate::players::strip_prefix(&line);
+            if self.mspt_follows {
+                self.mspt_follows = false;
+                if let Some((_, mspt)) = regex_captures!(r"^\D*([\d.]+)/", message) {
+                    self.mspt = mspt.parse().ok();
+                }
+            }
+            if let Some((_, tps)) =
+                regex_captures!(r"TPS from last 1m, 5m, 15m: \D*([\d.]+)", message)
+            {
+                self.tps = tps.parse().ok();
+            } else if let Some((_, target)) =
+                regex_captures!(r"Target tick rate: ([\d.]+) per second", message)
+            {
+                self.target = target.parse().ok();
+            } else if let Some((_, mspt)) =
+                regex_captures!(r"Average time per tick: ([\d.]+)ms", message)
+            {
+                self.mspt = mspt.parse().ok();
+            } else if message.starts_with("Server tick times (avg/min/max)") {
+                self.mspt_follows = true;
+            }
+        }
+    }
+
+    /// Tps and mspt seen since the last call. Vanilla only reports mspt, tps follows from it
+    pub fn take(&mut self) -> (Option<f64>, Option<f64>) {
+        let mspt = self.mspt.take();
+        let tps = self.tps.take().or_else(|| {
+            let mspt = mspt.filter(|mspt| *mspt > 0.0)?;
+            Some((1000.0 / mspt).min(self.target.unwrap_or(20.0)))
+        });
+        (tps, mspt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn watched(text: &str) -> (Option<f64>, Option<f64>) {
+        let mut watch = TickWatch::default();
+        watch.observe(text);
+        watch.take()
+    }
+
+    #[test]
+    fn vanilla_tick_query() {
+        let console = "[12:00:01] [Server thread/INFO]: The game is running normally\n\
+                       [12:00:01] [Server thread/INFO]: Target tick rate: 20.0 per second.\n\
+                       Average time per tick: 12.5ms (Target: 50.0ms)\n\
+                       [12:00:01] [Server thread/INFO]: Percentiles: P50: 11.9ms P95: 15.2ms P99: 20.0ms, sample: 100";
+        assert_eq!(watched(console), (Some(20.0), Some(12.5)));
+        // Lagging, tps follows from mspt
+        let rcon = "The game is running normally\nTarget tick rate: 20.0 per second.\nAverage time per tick: 80.0ms (Target: 50.0ms)";
+        assert_eq!(watched(rcon), (Some(12.5), Some(80.0)));
+    }
+
+    #[test]
+    fn vanilla_tick_rate_caps_tps() {
+        let mut watch = TickWatch::default();
+        watch.observe(
+            "Target tick rate: 10.0 per second.\nAverage time per tick: 5.0ms (Target: 100.0ms)",
+        );
+        assert_eq!(watch.take(), (Some(10.0), Some(5.0)));
+        // The target is remembered for later queries
+        watch.observe("Average time per tick: 50.0ms (Target: 100.0ms)");
+        assert_eq!(watch.take(), (Some(10.0), Some(50.0)));
+    }
+
+    #[test]
+    fn paper_tps_and_mspt() {
+        let tps = "[12:00:01 INFO]: \u{a7}6TPS from last 1m, 5m, 15m: \u{a7}a*20.0, \u{a7}a19.97, \u{a7}a19.99";
+        let mut watch = TickWatch::default();
+        watch.observe(tps);
+        assert_eq!(watch.take(), (Some(20.0), None));
+
+        watch.observe(
+            "[12:00:02 INFO]: \u{a7}6Server tick times \u{a7}e(\u{a7}7avg\u{a7}e/\u{a7}7min\u{a7}e/\u{a7}7max\u{a7}e)\u{a7}6 from last 5s\u{a7}7,\u{a7}6 10s\u{a7}7,\u{a7}6 1m\u{a7}e:",
+        );
+        watch.observe("[12:00:02 INFO]: \u{a7}6\u{25f4} \u{a7}a3.1\u{a7}7/\u{a7}a1.8\u{a7}7/\u{a7}a9.4\u{a7}e, \u{a7}a2.9\u{a7}7/\u{a7}a1.7\u{a7}7/\u{a7}a9.4");
+        let (tps, mspt) = watch.take();
+        assert_eq!(mspt, Some(3.1));
+        assert!((tps.unwrap() - 20.0).abs() < f64::EPSILON);
+        // Colour codes from the terminal
+        assert_eq!(
+            watched("\x1b[33m[12:00:03 INFO]: TPS from last 1m, 5m, 15m: 17.5, 18.0, 19.0\x1b[0m"),
+            (Some(17.5), None)
+        );
+    }
+
+    #[test]
+    fn other_lines_report_nothing() {
+        assert_eq!(watched(""), (None, None));
+        assert_eq!(
+            watched("[12:00:01] [Server thread/INFO]: Steve joined the game"),
+            (None, None)
+        );
+        assert_eq!(watched("Average time per tick: fastms"), (None, None));
+        assert_eq!(
+            watched("Unknown or incomplete command, see below for error"),
+            (None, None)
+        );
+        // A heading without the times that should follow
+        let mut watch = TickWatch::default();
+        watch.observe("Server tick times (avg/min/max) from last 5s, 10s, 1m:");
+        watch.observe("Steve joined the game");
+        assert_eq!(watch.take(), (None, None));
+        // Taken readings don't come back
+        watch.observe("Average time per tick: 10.0ms (Target: 50.0ms)");
+        assert_eq!(watch.take(), (Some(20.0), Some(10.0)));
+        assert_eq!(watch.take(), (None, None));
+    }
+
+    #[test]
+    fn disk_usage_adds_up_directories() {
+        let directory = std::env::temp_dir().join(format!("mcsc-metrics-{}", std::process::id()));
+        fs::create_dir_all(directory.join("region")).unwrap();
+        fs::write(directory.join("level.dat"), [0; 100]).unwrap();
+        fs::write(directory.join("region/r.0.0.mca"), [0; 50]).unwrap();
+        assert_eq!(disk_usage(&directory), 150);
+        assert_eq!(disk_usage(&directory.join("missing")), 0);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,359 @@
+use serde_derive::{Deserialize, Serialize};
+use std::{fs, io::Read, path::Path, sync::Mutex};
+
+/// Tracks installed mods, relative to minecraft dir
+const LOCK_FILE: &str = "mcsc-mods.lock";
+/// Modrinth asks api users to identify themselves
+const USER_AGENT: &str = concat!("ElSargo/mcsc/", env!("CARGO_PKG_VERSION"));
+
+/// Held while the lockfile is read, changed and written back, requests run on separate threads
+static LOCK_FILE_GUARD: Mutex<()> = Mutex::new(());
+
+/// Where to find mods and what they have to be compatible with
+pub struct Instance {
+    /// Base url of the api, eg. https://api.modrinth.com/v2
+    pub api: String,
+    /// Server loader, eg. fabric, forge, quilt, neoforge, paper, spigot or purpur
+    pub loader: String,
+    pub game_version: String,
+    /// Where mod or plugin jars are placed
+    pub directory: String,
+}
+
+/// A mod as recorded in the lockfile
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LockedMod {
+    pub project_id: String,
+    pub slug: String,
+    pub title: String,
+    pub version_id: String,
+    pub version_number: String,
+    pub filename: String,
+    pub sha1: String,
+    pub url: String,
+    /// Installed as a dependency of another mod rather than by request
+    #[serde(default)]
+    pub dependency: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct Lock {
+    #[serde(default)]
+    mods: Vec<LockedMod>,
+}
+
+/// A search result
+#[derive(Deserialize, Debug)]
+pub struct Hit {
+    pub project_id: String,
+    pub slug: String,
+    pub title: String,
+    pub description: String,
+    #[serde(default)]
+    pub latest_version: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct SearchResult {
+    hits: Vec<Hit>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Project {
+    id: String,
+    slug: String,
+    title: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct Version {
+    id: String,
+    project_id: String,
+    version_number: String,
+    files: Vec<VersionFile>,
+    #[serde(default)]
+    dependencies: Vec<Dependency>,
+}
+
+#[derive(Deserialize, Debug)]
+struct VersionFile {
+    hashes: Hashes,
+    url: String,
+    filename: String,
+    #[serde(default)]
+    primary: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct Hashes {
+    sha1: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct Dependency {
+    project_id: Option<String>,
+    dependency_type: String,
+}
+
+#[derive(Debug)]
+pub enum ModError {
+    /// The api couldn't be reached or returned an error
+    Api(String),
+    /// Not a slug or id, they're only letters, digits, - and _
+    InvalidProject,
+    /// No project with that id or slug
+    NotFound,
+    /// The project has no version for this loader and game version
+    NoCompatibleVersion,
+    /// A downloaded file doesn't match the sha1 the api gave
+    Checksum(String),
+    /// The mod isn't in the lockfile
+    NotInstalled,
+    /// The lockfile exists but couldn't be parsed
+    Lockfile,
+    /// Failed to write a jar or the lockfile
+    Io,
+}
+
+/// Search for mods or plugins compatible with the instance
+pub fn search(instance: &Instance, query: &str) -> Result<Vec<Hit>, ModError> {
+    let loaders = compatible_loaders(&instance.loader)
+        .iter()
+        .map(|loader| format!("\"categories:{loader}\""))
+        .collect::<Vec<String>>()
+        .join(",");
+    let facets = format!("[[{loaders}],[\"versions:{}\"]]", instance.game_version);
+    let result: SearchResult = get(ureq::get(&format!("{}/search", instance.api))
+        .query("query", query)
+        .query("facets", &facets)
+        .query("limit", "20"))?;
+    Ok(result.hits)
+}
+
+/// Mods listed in the lockfile
+pub fn installed() -> Result<Vec<LockedMod>, ModError> {
+    Ok(load_lock()?.mods)
+}
+
+/// Install the newest compatible version of a project along with its required dependencies,
+/// returns the mods that were installed
+pub fn install(instance: &Instance, project: &str) -> Result<Vec<LockedMod>, ModError> {
+    let _guard = LOCK_FILE_GUARD.lock();
+    let mut lock = load_lock()?;
+    let mut installed = Vec::new();
+    let mut queue = vec![(project.to_string(), false)];
+    while let Some((project, dependency)) = queue.pop() {
+        let project = fetch_project(instance, &project)?;
+        if lock.mods.iter().any(|m| m.project_id == project.id) {
+            if !dependency {
+                // Asked for explicitly, no longer just a dependency
+                for locked in lock.mods.iter_mut().filter(|m| m.project_id == project.id) {
+                    locked.dependency = false;
+                }
+                save_lock(&lock)?;
+            }
+            continue;
+        }
+        let version = newest_version(instance, &project.id)?;
+        for dep in &version.dependencies {
+            if dep.dependency_type == "required" {
+                if let Some(id) = &dep.project_id {
+                    queue.push((id.clone(), true));
+                }
+            }
+        }
+        let locked = download(instance, &project, &version, dependency)?;
+        lock.mods.push(locked.clone());
+        save_lock(&lock)?;
+        installed.push(locked);
+    }
+    Ok(installed)
+}
+
+/// Update one project, or every installed one when `project` is empty, to the newest compatible
+/// version. Returns the mods that changed
+pub fn update(instance: &Instance, project: &str) -> Result<Vec<LockedMod>, ModError> {
+    let _guard = LOCK_FILE_GUARD.lock();
+    let mut lock = load_lock()?;
+    let targets: Vec<usize> = lock
+        .mods
+        .iter()
+        .enumerate()
+        .filter(|(_, m)| project.is_empty() || m.slug == project || m.project_id == project)
+        .map(|(i, _)| i)
+        .collect();
+    if targets.is_empty() && !project.is_empty() {
+        return Err(ModError::NotInstalled);
+    }
+
+    let mut updated = Vec::new();
+    for i in targets {
+        let locked = &lock.mods[i];
+        let version = newest_version(instance, &locked.project_id)?;
+        if version.id == locked.version_id {
+            continue;
+        }
+        let project = Project {
+            id: locked.project_id.clone(),
+            slug: locked.slug.clone(),
+            title: locked.title.clone(),
+        };
+        let old_file = Path::new(&instance.directory).join(&locked.filename);
+        let new = download(instance, &project, &version, locked.dependency)?;
+        if new.filename != locked.filename {
+            let _ = fs::remove_file(old_file);
+        }
+        lock.mods[i] = new.clone();
+        save_lock(&lock)?;
+        updated.push(new);
+    }
+    Ok(updated)
+}
+
+/// Delete a mod's jar and drop it from the lockfile
+pub fn remove(instance: &Instance, project: &str) -> Result<LockedMod, ModError> {
+    let _guard = LOCK_FILE_GUARD.lock();
+    let mut lock = load_lock()?;
+    let index = lock
+        .mods
+        .iter()
+        .position(|m| m.slug == project || m.project_id == project)
+        .ok_or(ModError::NotInstalled)?;
+    let removed = lock.mods.remove(index);
+    let path = Path::new(&instance.directory).join(&removed.filename);
+    if path.exists() && fs::remove_file(path).is_err() {
+        return Err(ModError::Io);
+    }
+    save_lock(&lock)?;
+    Ok(removed)
+}
+
+/// Where a loader looks for jars, plugins/ for bukkit style servers and mods/ for the rest
+pub fn default_directory(loader: &str) -> &'static str {
+    match loader {
+        "paper" | "purpur" | "folia" | "spigot" | "bukkit" => "plugins",
+        _ => "mods",
+    }
+}
+
+/// Loaders whose mods or plugins run on the given server loader
+fn compatible_loaders(loader: &str) -> Vec<&str> {
+    match loader {
+        "paper" => vec!["paper", "spigot", "bukkit"],
+        "purpur" => vec!["purpur", "paper", "spigot", "bukkit"],
+        "folia" => vec!["folia"],
+        "spigot" => vec!["spigot", "bukkit"],
+        "quilt" => vec!["quilt", "fabric"],
+        loader => vec![loader],
+    }
+}
+
+/// Whether `project` can go in a url path as is, the client picks it so it could otherwise reach
+/// other endpoints with ../, ? or #
+fn is_project_name(project: &str) -> bool {
+    !project.is_empty()
+        && project
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+fn fetch_project(instance: &Instance, project: &str) -> Result<Project, ModError> {
+    if !is_project_name(project) {
+        return Err(ModError::InvalidProject);
+    }
+    get(ureq::get(&format!("{}/project/{project}", instance.api)))
+}
+
+/// Newest version of a project for the instance's loader and game version
+fn newest_version(instance: &Instance, project_id: &str) -> Result<Version, ModError> {
+    if !is_project_name(project_id) {
+        return Err(ModError::InvalidProject);
+    }
+    let loaders = compatible_loaders(&instance.loader)
+        .iter()
+        .map(|loader| format!("\"{loader}\""))
+        .collect::<Vec<String>>()
+        .join(",");
+    let versions: Vec<Version> = get(ureq::get(&format!(
+        "{}/project/{project_id}/version",
+        instance.api
+    ))
+    .query("loaders", &format!("[{loaders}]"))
+    .query("game_versions", &format!("[\"{}\"]", instance.game_version)))?;
+    // The api lists newest first
+    versions
+        .into_iter()
+        .next()
+        .ok_or(ModError::NoCompatibleVersion)
+}
+
+/// Download the primary file of a version into the mod directory, checking its sha1
+fn download(
+    instance: &Instance,
+    project: &Project,
+    version: &Version,
+    dependency: bool,
+) -> Result<LockedMod, ModError> {
+    let file = version
+        .files
+        .iter()
+        .find(|file| file.primary)
+        .or_else(|| version.files.first())
+        .ok_or(ModError::NoCompatibleVersion)?;
+    let response = request(ureq::get(&file.url))?;
+    let mut bytes = Vec::new();
+    response
+        .into_reader()
+        .read_to_end(&mut bytes)
+        .map_err(|error| ModError::Api(error.to_string()))?;
+    if crate::versions::sha1_hex(&bytes) != file.hashes.sha1.to_lowercase() {
+        return Err(ModError::Checksum(file.filename.clone()));
+    }
+    if fs::create_dir_all(&instance.directory).is_err() {
+        return Err(ModError::Io);
+    }
+    // Only plain file names, the name comes from the api
+    let filename = Path::new(&file.filename)
+        .file_name()
+        .ok_or(ModError::Io)?
+        .to_string_lossy()
+        .to_string();
+    fs::write(Path::new(&instance.directory).join(&filename), bytes).map_err(|_| ModError::Io)?;
+    Ok(LockedMod {
+        project_id: version.project_id.clone(),
+        slug: project.slug.clone(),
+        title: project.title.clone(),
+        version_id: version.id.clone(),
+        version_number: version.version_number.clone(),
+        filename,
+        sha1: file.hashes.sha1.to_lowercase(),
+        url: file.url.clone(),
+        dependency,
+    })
+}
+
+fn request(request: ureq::Request) -> Result<ureq::Response, ModError> {
+    match request.set("User-Agent", USER_AGENT).call() {
+        Ok(response) => Ok(response),
+        Err(ureq::Error::Status(404, _)) => Err(ModError::NotFound),
+        Err(error) => Err(ModError::Api(error.to_string())),
+    }
+}
+
+fn get<T: serde::de::DeserializeOwned>(req: ureq::Request) -> Result<T, ModError> {
+    request(req)?
+        .into_json()
+        .map_err(|error| ModError::Api(error.to_string()))
+}
+
+fn load_lock() -> Result<Lock, ModError> {
+    match fs::read_to_string(LOCK_FILE) {
+        Ok(text) => toml::from_str(&text).map_err(|_| ModError::Lockfile),
+        Err(_) => Ok(Lock::default()),
+    }
+}
+
+fn save_lock(lock: &Lock) -> Result<(), ModError> {
+    let text = toml::to_string(lock).map_err(|_| ModError::Io)?;
+    fs::write(LOCK_FILE, text).map_err(|_| ModError::Io)
+}
@@ -0,0 +1,318 @@
+use lazy_regex::{regex_captures, regex_replace_all};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    time::{Duration, SystemTime},
+};
+
+/// How many finished sessions to remember
+const SESSION_HISTORY: usize = 100;
+
+/// Phrases that follow the player's name in vanilla death messages
+const DEATH_PHRASES: &[&str] = &[
+    "was slain by",
+    "was shot by",
+    "was killed",
+    "was blown up by",
+    "blew up",
+    "was fireballed by",
+    "was pummeled by",
+    "was impaled",
+    "was skewered",
+    "was stung to death",
+    "was poked to death",
+    "was pricked to death",
+    "was squashed",
+    "was squished",
+    "was struck by lightning",
+    "was roasted",
+    "was burnt",
+    "was burned",
+    "was frozen",
+    "was obliterated",
+    "was doomed to fall",
+    "was speared",
+    "was smashed",
+    "drowned",
+    "died",
+    "hit the ground too hard",
+    "fell ",
+    "burned to death",
+    "went up in flames",
+    "went off with a bang",
+    "walked into",
+    "tried to swim in lava",
+    "discovered the floor was lava",
+    "starved to death",
+    "suffocated in a wall",
+    "froze to death",
+    "withered away",
+    "experienced kinetic energy",
+    "left the confines of this world",
+    "didn't want to live",
+    "was too soft for this world",
+];
+
+/// Something a player did, as seen in the server console
+#[derive(Debug, Clone)]
+pub enum Event {
+    Join {
+        player: String,
+    },
+    Leave {
+        player: String,
+        /// How long they were online
+        session: Duration,
+    },
+    Death {
+        player: String,
+        message: String,
+    },
+    Chat {
+        player: String,
+        message: String,
+    },
+    Advancement {
+        player: String,
+        advancement: String,
+    },
+}
+
+/// A finished play session
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub player: String,
+    pub joined: SystemTime,
+    pub left: SystemTime,
+}
+
+/// Who is online, kept up to date from the console log
+#[derive(Debug, Default)]
+pub struct Roster {
+    /// Online players and when they joined
+    online: BTreeMap<String, SystemTime>,
+    sessions: VecDeque<Session>,
+}
+
+impl Roster {
+    /// Online players and when they joined, alphabetically
+    pub fn online(&self) -> impl Iterator<Item = (&str, SystemTime)> {
+        self.online
+            .iter()
+            .map(|(name, joined)| (name.as_str(), *joined))
+    }
+
+    pub fn count(&self) -> usize {
+        self.online.len()
+    }
+
+    /// Recently finished sessions, newest first
+    pub fn sessions(&self) -> impl Iterator<Item = &Session> {
+        self.sessions.iter().rev()
+    }
+
+    /// Parse a console line, updating the roster if it's a join or leave
+    pub fn observe(&mut self, line: &str) -> Option<Event> {
+        // Some servers colour their output
+        let line = regex_replace_all!(r"\x1b\[[0-9;]*[A-Za-z]", line, |_| "");
+        let message = strip_prefix(&line);
+        if let Some((_, player)) = regex_captures!(r"^(\w{1,16}) joined the game$", message) {
+            self.online.insert(player.to_string(), SystemTime::now());
+            return Some(Event::Join {
+                player: player.to_string(),
+            });
+        }
+        if let Some((_, player)) = regex_captures!(r"^(\w{1,16}) left the game$", message) {
+            let session = self.end_session(player);
+            return Some(Event::Leave {
+                player: player.to_string(),
+                session,
+            });
+        }
+        if let Some((_, player, text)) =
+            regex_captures!(r"^(?:\[Not Secure\] )?<(\w{1,16})> (.*)$", message)
+        {
+            return Some(Event::Chat {
+                player: player.to_string(),
+                message: text.to_string(),
+            });
+        }
+        if let Some((_, player, advancement)) = regex_captures!(
+            r"^(\w{1,16}) has (?:made the advancement|completed the challenge|reached the goal) \[(.+)\]$",
+            message
+        ) {
+            return Some(Event::Advancement {
+                player: player.to_string(),
+                advancement: advancement.to_string(),
+            });
+        }
+        // Only trust death messages about someone who is online, plenty of other lines start
+        // with a word followed by "died" or "fell"
+        let (player, rest) = message.split_once(' ')?;
+        if self.online.contains_key(player)
+            && DEATH_PHRASES.iter().any(|phrase| rest.starts_with(phrase))
+        {
+            return Some(Event::Death {
+                player: player.to_string(),
+                message: message.to_string(),
+            });
+        }
+        None
+    }
+
+    /// Everyone is gone once the server stops, without the leave lines being printed
+    pub fn clear(&mut self) -> Vec<Event> {
+        let players: Vec<String> = self.online.keys().cloned().collect();
+        players
+            .into_iter()
+            .map(|player| Event::Leave {
+                session: self.end_session(&player),
+                player,
+            })
+            .collect()
+    }
+
+    fn end_session(&mut self, player: &str) -> Duration {
+        let joined = match self.online.remove(player) {
+            Some(joined) => joined,
+            None => return Duration::ZERO,
+        };
+        let left = SystemTime::now();
+        self.sessions.push_back(Session {
+            player: player.to_string(),
+            joined,
+            left,
+        });
+        if self.sessions.len() > SESSION_HISTORY {
+            self.sessions.pop_front();
+        }
+        left.duration_since(joined).unwrap_or_default()
+    }
+}
+
+/// Drop the time and thread info before the message, vanilla logs look like
+/// `[12:34:56] [Server thread/INFO]: Steve joined the game` and paper like
+/// `[12:34:56 INFO]: Steve joined the game`
+pub fn strip_prefix(line: &str) -> &str {
+    match line.find("]: ") {
+        Some(end) if line.starts_with('[') => &line[end + 3..],
+        _ => line,
+    }
+    .trim_end()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vanilla(message: &str) -> String {
+        format!("[12:34:56] [Server thread/INFO]: {message}")
+    }
+
+    fn paper(message: &str) -> String {
+        format!("[12:34:56 INFO]: {message}")
+    }
+
+    fn online(roster: &Roster) -> Vec<&str> {
+        roster.online().map(|(name, _)| name).collect()
+    }
+
+    #[test]
+    fn tracks_joins_and_leaves() {
+        let mut roster = Roster::default();
+        let joined = roster.observe(&vanilla("Steve joined the game"));
+        assert!(matches!(joined, Some(Event::Join { player }) if player == "Steve"));
+        roster.observe(&paper("Alex joined the game"));
+        assert_eq!(online(&roster), ["Alex", "Steve"]);
+
+        let left = roster.observe(&vanilla("Steve left the game"));
+        assert!(matches!(left, Some(Event::Leave { player, .. }) if player == "Steve"));
+        assert_eq!(online(&roster), ["Alex"]);
+        let sessions: Vec<&str> = roster.sessions().map(|s| s.player.as_str()).collect();
+        assert_eq!(sessions, ["Steve"]);
+    }
+
+    #[test]
+    fn names_may_have_underscores_and_digits() {
+        let mut roster = Roster::default();
+        roster.observe(&vanilla("__x_Steve_99__ joined the game"));
+        assert_eq!(online(&roster), ["__x_Steve_99__"]);
+        // Coloured output
+        roster.observe("\x1b[33m[12:34:56 INFO]: Dinnerbone_ joined the game\x1b[0m");
+        assert_eq!(online(&roster), ["Dinnerbone_", "__x_Steve_99__"]);
+        // Longer than minecraft allows
+        assert!(roster
+            .observe(&vanilla("abcdefghijklmnopq joined the game"))
+            .is_none());
+    }
+
+    #[test]
+    fn chat_cant_pretend_to_join() {
+        let mut roster = Roster::default();
+        roster.observe(&vanilla("Steve joined the game"));
+        let chat = roster.observe(&vanilla("<Steve> Herobrine joined the game"));
+        assert!(matches!(chat, Some(Event::Chat { player, message })
+                if player == "Steve" && message == "Herobrine joined the game"));
+        roster.observe(&paper("[Not Secure] <Steve> Alex left the game"));
+        roster.observe(&vanilla("[Server] Notch joined the game"));
+        roster.observe(&vanilla("* Steve joined the game"));
+        assert_eq!(online(&roster), ["Steve"]);
+    }
+
+    #[test]
+    fn deaths_need_the_player_online() {
+        let mut roster = Roster::default();
+        assert!(roster
+            .observe(&vanilla("Steve was slain by Zombie"))
+            .is_none());
+        roster.observe(&vanilla("Steve joined the game"));
+        let died = roster.observe(&vanilla("Steve was slain by Zombie"));
+        assert!(matches!(died, Some(Event::Death { player, message })
+            if player == "Steve" && message == "Steve was slain by Zombie"));
+        // Other lines that happen to fit
+        assert!(roster
+            .observe(&vanilla("Preparing level \"world\""))
+            .is_none());
+        assert!(roster
+            .observe(&vanilla("Steve lost connection: Disconnected"))
+            .is_none());
+    }
+
+    #[test]
+    fn advancements() {
+        let mut roster = Roster::default();
+        let event = roster.observe(&paper("Steve has made the advancement [Stone Age]"));
+        assert!(
+            matches!(event, Some(Event::Advancement { player, advancement })
+            if player == "Steve" && advancement == "Stone Age")
+        );
+    }
+
+    #[test]
+    fn restarts_clear_the_roster() {
+        let mut roster = Roster::default();
+        roster.observe(&vanilla("Steve joined the game"));
+        roster.observe(&vanilla("Alex joined the game"));
+        let left = roster.clear();
+        let players: Vec<String> = left
+            .into_iter()
+            .filter_map(|event| match event {
+                Event::Leave { player, .. } => Some(player),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(players, ["Alex", "Steve"]);
+        assert_eq!(roster.count(), 0);
+        assert_eq!(roster.sessions().count(), 2);
+        // Whoever joins the new server starts over
+        roster.observe(&vanilla("Steve joined the game"));
+        assert_eq!(online(&roster), ["Steve"]);
+        assert!(roster.clear().len() == 1 && roster.clear().is_empty());
+    }
+
+    #[test]
+    fn strips_log_prefixes() {
+        assert_eq!(strip_prefix(&vanilla("Done (1.2s)!  ")), "Done (1.2s)!");
+        assert_eq!(strip_prefix(&paper("Done")), "Done");
+        assert_eq!(strip_prefix("no prefix]: here"), "no prefix]: here");
+    }
+}
@@ -0,0 +1,154 @@
+use std::{
+    fs, io,
+    os::unix::fs::MetadataExt,
+    path::Path,
+    process::{Command, Stdio},
+};
+
+/// Relative to minecraft dir
+pub const EULA_FILE: &str = "eula.txt";
+/// Marks the comment in eula.txt recording who accepted it through mcsc
+const ACCEPTED_BY: &str = "#Accepted through mcsc by ";
+
+/// Something that would stop the server from starting, or put the world at risk if it did
+#[derive(Debug)]
+pub enum Problem {
+    /// The procces with this pid holds the world's session.lock, usually another server
+    WorldLocked(u32),
+    /// level.dat is missing or damaged, says which
+    WorldCorrupt(String),
+    LowDiskSpace {
+        free_mb: u64,
+        required_mb: u64,
+    },
+    /// eula.txt doesn't say eula=true
+    EulaNotAccepted,
+}
+
+/// Check the server in the current directory before launching it
+pub fn check(world: &str, min_free_mb: u64) -> Result<(), Problem> {
+    if !eula_accepted() {
+        return Err(Problem::EulaNotAccepted);
+    }
+    session_lock(world)?;
+    level_dat(world)?;
+    match free_mb() {
+        Some(free_mb) if free_mb < min_free_mb => Err(Problem::LowDiskSpace {
+            free_mb,
+            required_mb: min_free_mb,
+        }),
+        _ => Ok(()),
+    }
+}
+
+pub fn eula_accepted() -> bool {
+    fs::read_to_string(EULA_FILE).is_ok_and(|text| {
+        text.lines().any(|line| {
+            line.split_once('=')
+                .is_some_and(|(key, value)| key.trim() == "eula" && value.trim() == "true")
+        })
+    })
+}
+
+/// Who accepted the EULA through mcsc and when, in unix time
+pub fn eula_acceptance() -> Option<(String, u64)> {
+    let text = fs::read_to_string(EULA_FILE).ok()?;
+    let (name, time) = text
+        .lines()
+        .find_map(|line| line.strip_prefix(ACCEPTED_BY))?
+        .rsplit_once(" at ")?;
+    Some((name.to_string(), time.trim().parse().ok()?))
+}
+
+/// Write an eula.txt agreeing to the EULA, noting who agreed and when
+pub fn accept_eula(name: &str, time: u64) -> io::Result<()> {
+    fs::write(
+        EULA_FILE,
+        format!(
+            "#By changing the setting below to TRUE you are indicating your agreement to our EULA (https://aka.ms/MinecraftEULA).\n\
+             {ACCEPTED_BY}{name} at {time}\n\
+             eula=true\n"
+        ),
+    )
+}
+
+/// Minecraft holds a lock on session.lock while it has the world open. One left behind by a
+/// server that has exited isn't held by anyone and is fine
+fn session_lock(world: &str) -> Result<(), Problem> {
+    let metadata = match fs::metadata(Path::new(world).join("session.lock")) {
+        Ok(metadata) => metadata,
+        Err(_) => return Ok(()),
+    };
+    // Lists every held lock like "1: POSIX  ADVISORY  WRITE 1234 08:02:131 0 EOF", the pid
+    // followed by the file as major:minor:inode
+    let locks = match fs::read_to_string("/proc/locks") {
+        Ok(locks) => locks,
+        Err(_) => return Ok(()),
+    };
+    let dev = metadata.dev();
+    let major = ((dev >> 8) & 0xfff) | ((dev >> 32) & !0xfff);
+    let minor = (dev & 0xff) | ((dev >> 12) & !0xff);
+    let file = format!("{major:02x}:{minor:02x}:{}", metadata.ino());
+    for line in locks.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if let Some(i) = fields.iter().position(|field| *field == file) {
+            let pid = fields[i - 1].parse().unwrap_or_default();
+            return Err(Problem::WorldLocked(pid));
+        }
+    }
+    Ok(())
+}
+
+/// A world that exists needs a level.dat minecraft can read
+fn level_dat(world: &str) -> Result<(), Problem> {
+    let world = Path::new(world);
+    // No world yet, minecraft generates one
+    if fs::read_dir(world).map_or(true, |mut entries| entries.next().is_none()) {
+        return Ok(());
+    }
+    let level = world.join("level.dat");
+    let problem = if !level.exists() {
+        "level.dat is missing"
+    } else if !gzip_intact(&level) {
+        "level.dat is corrupt"
+    } else {
+        return Ok(());
+    };
+    let old = world.join("level.dat_old");
+    Err(Problem::WorldCorrupt(
+        if old.exists() && gzip_intact(&old) {
+            format!("{problem}, level.dat_old looks intact and could replace it")
+        } else {
+            problem.to_string()
+        },
+    ))
+}
+
+/// level.dat is gzipped, so gzip can check it all the way through. Counts as intact when gzip
+/// isn't installed
+fn gzip_intact(path: &Path) -> bool {
+    match Command::new("gzip")
+        .arg("-t")
+        .arg(path)
+        .stderr(Stdio::null())
+        .status()
+    {
+        Ok(status) => status.success(),
+        Err(_) => true,
+    }
+}
+
+/// Megabytes free where the server lives, from df as std can't tell
+fn free_mb() -> Option<u64> {
+    let output = Command::new("df").arg("-Pk").arg(".").output().ok()?;
+    let text = String::from_utf8(output.stdout).ok()?;
+    // Filesystem 1024-blocks Used Available Capacity Mounted-on
+    let available: u64 = text
+        .lines()
+        .nth(1)?
+        .split_whitespace()
+        .nth(3)?
+        .parse()
+        .ok()?;
+    Some(available / 1024)
+}
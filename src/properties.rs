@@ -0,0 +1,372 @@
+use std::{collections::BTreeMap, fs};
+
+/// Relative to minecraft dir
+pub const PROPERTIES_FILE: &str = "server.properties";
+
+/// What values a key accepts
+#[derive(Debug, Clone, Copy)]
+pub enum Kind {
+    Bool,
+    Int { min: i64, max: i64 },
+    Enum(&'static [&'static str]),
+    String,
+}
+
+impl Kind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Kind::Bool => "bool",
+            Kind::Int { min: _, max: _ } => "int",
+            Kind::Enum(_) => "enum",
+            Kind::String => "string",
+        }
+    }
+
+    fn check(&self, value: &str) -> Result<(), String> {
+        match self {
+            Kind::Bool => match value {
+                "true" | "false" => Ok(()),
+                _ => Err("expected true or false".to_string()),
+            },
+            Kind::Int { min, max } => match value.parse::<i64>() {
+                Ok(n) if (*min..=*max).contains(&n) => Ok(()),
+                _ => Err(format!("expected a whole number from {min} to {max}")),
+            },
+            Kind::Enum(options) => {
+                if options.contains(&value) {
+                    Ok(())
+                } else {
+                    Err(format!("expected one of {}", options.join(", ")))
+                }
+            }
+            Kind::String => {
+                if value.contains(['\n', '\r']) {
+                    Err("must be a single line".to_string())
+                } else {
+                    Ok(())
+                }
+            }
+        }
+    }
+}
+
+/// Type of a server.properties key, unknown keys are treated as strings
+pub fn kind(key: &str) -> Kind {
+    const MAX: i64 = i32::MAX as i64;
+    match key {
+        "allow-flight"
+        | "allow-nether"
+        | "broadcast-console-to-ops"
+        | "broadcast-rcon-to-ops"
+        | "enable-command-block"
+        | "enable-jmx-monitoring"
+        | "enable-query"
+        | "enable-rcon"
+        | "enable-status"
+        | "enforce-secure-profile"
+        | "enforce-whitelist"
+        | "force-gamemode"
+        | "generate-structures"
+        | "hardcore"
+        | "hide-online-players"
+        | "online-mode"
+        | "prevent-proxy-connections"
+        | "pvp"
+        | "require-resource-pack"
+        | "spawn-animals"
+        | "spawn-monsters"
+        | "spawn-npcs"
+        | "sync-chunk-writes"
+        | "use-native-transport"
+        | "white-list"
+        | "log-ips"
+        | "accepts-transfers" => Kind::Bool,
+        "server-port" | "query.port" | "rcon.port" => Kind::Int { min: 1, max: 65535 },
+        "view-distance" | "simulation-distance" => Kind::Int { min: 3, max: 32 },
+        "max-world-size" => Kind::Int {
+            min: 1,
+            max: 29999984,
+        },
+        "op-permission-level" => Kind::Int { min: 0, max: 4 },
+        "function-permission-level" => Kind::Int { min: 1, max: 4 },
+        "entity-broadcast-range-percentage" => Kind::Int { min: 10, max: 1000 },
+        "max-tick-time" | "network-compression-threshold" => Kind::Int { min: -1, max: MAX },
+        "max-players"
+        | "spawn-protection"
+        | "player-idle-timeout"
+        | "rate-limit"
+        | "max-chained-neighbor-updates"
+        | "max-build-height" => Kind::Int { min: 0, max: MAX },
+        "difficulty" => Kind::Enum(&["peaceful", "easy", "normal", "hard"]),
+        "gamemode" => Kind::Enum(&["survival", "creative", "adventure", "spectator"]),
+        _ => Kind::String,
+    }
+}
+
+enum Line {
+    /// Comments and blank lines, kept verbatim
+    Other(String),
+    Entry {
+        key: String,
+        value: String,
+        /// The line as it was read, None once the value has been changed
+        raw: Option<String>,
+    },
+}
+
+/// server.properties, keeping comments and key order so rewriting only touches changed lines
+pub struct Properties {
+    lines: Vec<Line>,
+}
+
+impl Properties {
+    pub fn load() -> Result<Self, std::io::Error> {
+        let text = match fs::read_to_string(PROPERTIES_FILE) {
+            Ok(text) => text,
+            // Minecraft creates it on first launch
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(error) => return Err(error),
+        };
+        Ok(Self::parse(&text))
+    }
+
+    fn parse(text: &str) -> Self {
+        let lines = text
+            .lines()
+            .map(|line| {
+                let trimmed = line.trim_start();
+                if trimmed.is_empty() || trimmed.starts_with('#') || trimmed.starts_with('!') {
+                    return Line::Other(line.to_string());
+                }
+                let (key, value) = split_entry(trimmed);
+                Line::Entry {
+                    key: unescape(key),
+                    value: unescape(value),
+                    raw: Some(line.to_string()),
+                }
+            })
+            .collect();
+        Self { lines }
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.lines.iter().filter_map(|line| match line {
+            Line::Entry { key, value, .. } => Some((key.as_str(), value.as_str())),
+            Line::Other(_) => None,
+        })
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries().find(|(k, _)| *k == key).map(|(_, v)| v)
+    }
+
+    /// Check every change against the key types, all problems are returned at once
+    pub fn validate(changes: &BTreeMap<String, String>) -> Result<(), Vec<String>> {
+        let problems: Vec<String> = changes
+            .iter()
+            .filter_map(|(key, value)| {
+                if key.is_empty() || key.contains(['\n', '\r', '=', ':']) {
+                    return Some(format!("{key:?} is not a valid key"));
+                }
+                kind(key)
+                    .check(value)
+                    .err()
+                    .map(|problem| format!("{key}: {problem}"))
+            })
+            .collect();
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems)
+        }
+    }
+
+    /// Set values in place, keys that aren't in the file yet are appended
+    pub fn set(&mut self, changes: &BTreeMap<String, String>) {
+        for (key, value) in changes {
+            let existing = self.lines.iter_mut().find_map(|line| match line {
+                Line::Entry { key: k, value, raw } if k == key => Some((value, raw)),
+                _ => None,
+            });
+            match existing {
+                Some((existing, _)) if existing == value => {}
+                Some((existing, raw)) => {
+                    *existing = value.clone();
+                    *raw = None;
+                }
+                None => self.lines.push(Line::Entry {
+                    key: key.clone(),
+                    value: value.clone(),
+                    raw: None,
+                }),
+            }
+        }
+    }
+
+    pub fn save(&self) -> Result<(), std::io::Error> {
+        fs::write(PROPERTIES_FILE, self.render())
+    }
+
+    /// The file's text, lines that weren't changed are written back as they were read
+    fn render(&self) -> String {
+        let mut text = String::new();
+        for line in &self.lines {
+            match line {
+                Line::Other(raw) | Line::Entry { raw: Some(raw), .. } => text.push_str(raw),
+                Line::Entry {
+                    key,
+                    value,
+                    raw: None,
+                } => {
+                    text.push_str(&escape(key));
+                    text.push('=');
+                    text.push_str(&escape(value));
+                }
+            }
+            text.push('\n');
+        }
+        text
+    }
+}
+
+/// Split at the first unescaped `=` or `:`
+fn split_entry(line: &str) -> (&str, &str) {
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '\\' if !escaped => escaped = true,
+            '=' | ':' if !escaped => return (line[..i].trim_end(), line[i + 1..].trim_start()),
+            _ => escaped = false,
+        }
+    }
+    (line.trim_end(), "")
+}
+
+/// Undo java properties escapes, eg. `https\://` and `\u00e9`
+fn unescape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some('r') => out.push('\r'),
+            Some('f') => out.push('\u{c}'),
+            Some('u') => {
+                let code: String = chars.by_ref().take(4).collect();
+                let mut units = vec![u16::from_str_radix(&code, 16).unwrap_or(0xfffd)];
+                // Characters outside the BMP are written as two escapes
+                if (0xd800..0xdc00).contains(&units[0]) && chars.as_str().starts_with("\\u") {
+                    let low: String = chars.by_ref().skip(2).take(4).collect();
+                    units.push(u16::from_str_radix(&low, 16).unwrap_or(0xfffd));
+                }
+                out.extend(char::decode_utf16(units).map(|c| c.unwrap_or('\u{fffd}')));
+            }
+            Some(c) => out.push(c),
+            None => {}
+        }
+    }
+    out
+}
+
+/// Escape like java's Properties.store, so minecraft reads back exactly what was set
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\t' => out.push_str("\\t"),
+            '=' | ':' | '#' | '!' => {
+                out.push('\\');
+                out.push(c);
+            }
+            c if c.is_ascii() && !c.is_ascii_control() => out.push(c),
+            c => {
+                let mut units = [0; 2];
+                for unit in c.encode_utf16(&mut units) {
+                    out.push_str(&format!("\\u{unit:04x}"));
+                }
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = "#Minecraft server properties
+#Mon Jan 01 00:00:00 UTC 2024
+
+motd = A Minecraft Server
+level-name=world
+resource-pack=https\\://example.com/pack.zip
+spawn-protection:16
+! bang comment
+motto=Caf\\u00e9 \\ud83d\\ude00
+";
+
+    fn changes(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn parses_escapes_and_separators() {
+        let properties = Properties::parse(TEXT);
+        assert_eq!(properties.get("motd"), Some("A Minecraft Server"));
+        assert_eq!(
+            properties.get("resource-pack"),
+            Some("https://example.com/pack.zip")
+        );
+        assert_eq!(properties.get("spawn-protection"), Some("16"));
+        assert_eq!(properties.get("motto"), Some("Caf\u{e9} \u{1f600}"));
+        assert_eq!(properties.entries().count(), 5);
+    }
+
+    #[test]
+    fn unchanged_file_round_trips() {
+        assert_eq!(Properties::parse(TEXT).render(), TEXT);
+    }
+
+    #[test]
+    fn setting_the_same_value_keeps_the_line() {
+        let mut properties = Properties::parse(TEXT);
+        properties.set(&changes(&[("motd", "A Minecraft Server")]));
+        assert_eq!(properties.render(), TEXT);
+    }
+
+    #[test]
+    fn only_changed_lines_are_rewritten() {
+        let mut properties = Properties::parse(TEXT);
+        properties.set(&changes(&[("motd", "Hi: #1 Café"), ("pvp", "false")]));
+        let expected =
+            TEXT.replace("motd = A Minecraft Server", "motd=Hi\\: \\#1 Caf\\u00e9") + "pvp=false\n";
+        assert_eq!(properties.render(), expected);
+        let reread = Properties::parse(&expected);
+        assert_eq!(reread.get("motd"), Some("Hi: #1 Café"));
+        assert_eq!(reread.get("pvp"), Some("false"));
+    }
+
+    #[test]
+    fn escape_round_trips() {
+        for text in ["plain", "a=b:c", "back\\slash", "tab\tand #!", "é and 😀"] {
+            assert_eq!(unescape(&escape(text)), text);
+        }
+    }
+
+    #[test]
+    fn validates_changes() {
+        assert!(Properties::validate(&changes(&[("max-players", "20")])).is_ok());
+        let problems =
+            Properties::validate(&changes(&[("pvp", "maybe"), ("bad=key", "1")])).unwrap_err();
+        assert_eq!(problems.len(), 2);
+    }
+}
@@ -0,0 +1,418 @@
+use serde_json::{json, Value};
+use std::{
+    io::{self, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    time::{Duration, Instant},
+};
+
+/// Handshakes and status requests are tiny, anything bigger isn't a minecraft client
+const MAX_PACKET: usize = 32 * 1024;
+
+/// What the client said it wants in its handshake
+#[derive(Debug, PartialEq)]
+pub enum Intent {
+    /// Server list ping
+    Status,
+    /// Someone trying to join, with their name
+    Login(String),
+    /// Legacy pings and anything we don't understand
+    Other,
+}
+
+/// What a server says about itself in the server list
+#[derive(Debug, Clone)]
+pub struct Status {
+    /// Version name, eg. "1.20.1" or "Paper 1.20.1"
+    pub version: String,
+    pub protocol: i32,
+    /// Message of the day with formatting removed
+    pub motd: String,
+    pub online: u32,
+    pub max: u32,
+    /// Round trip of the ping packet
+    pub latency: Duration,
+}
+
+/// Server List Ping a server, the way the multiplayer screen does
+pub fn ping(address: &str, timeout: Duration) -> io::Result<Status> {
+    let (host, port) = match address.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse().map_err(|_| invalid("bad port"))?),
+        None => (address, 25565),
+    };
+    let socket = (host, port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| invalid("address didn't resolve"))?;
+    let mut stream = TcpStream::connect_timeout(&socket, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    let mut handshake = Vec::new();
+    // -1 as we don't know which version the server runs
+    write_varint(&mut handshake, -1);
+    write_string(&mut handshake, host);
+    handshake.extend_from_slice(&port.to_be_bytes());
+    write_varint(&mut handshake, 1);
+    write_packet(&mut stream, 0x00, &handshake)?;
+    write_packet(&mut stream, 0x00, &[])?;
+
+    let (id, response) = read_packet(&mut stream)?;
+    if id != 0x00 {
+        return Err(invalid("expected a status response"));
+    }
+    let status: Value = serde_json::from_str(&read_string(&mut response.as_slice())?)
+        .map_err(|_| invalid("status isn't json"))?;
+
+    let sent = Instant::now();
+    write_packet(&mut stream, 0x01, &[0; 8])?;
+    read_packet(&mut stream)?;
+    let latency = sent.elapsed();
+
+    Ok(Status {
+        version: status["version"]["name"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string(),
+        protocol: status["version"]["protocol"].as_i64().unwrap_or(-1) as i32,
+        motd: strip_formatting(&flatten_text(&status["description"])),
+        online: status["players"]["online"].as_u64().unwrap_or_default() as u32,
+        max: status["players"]["max"].as_u64().unwrap_or_default() as u32,
+        latency,
+    })
+}
+
+/// Text of a chat component, which is either a string or an object with text and extra parts
+fn flatten_text(component: &Value) -> String {
+    match component {
+        Value::String(text) => text.clone(),
+        Value::Array(parts) => parts.iter().map(flatten_text).collect(),
+        Value::Object(_) => {
+            let mut text = component["text"].as_str().unwrap_or_default().to_string();
+            if let Some(extra) = component["extra"].as_array() {
+                text.extend(extra.iter().map(flatten_text));
+            }
+            text
+        }
+        _ => String::new(),
+    }
+}
+
+/// Drop § colour and style codes
+fn strip_formatting(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\u{a7}' {
+            chars.next();
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// Play the part of a stopped server: answer server list pings with `motd` and turn joining
+/// players away with `kick`. Returns what the client wanted
+pub fn serve_sleeping(mut stream: TcpStream, motd: &str, kick: &str) -> io::Result<Intent> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    stream.set_write_timeout(Some(Duration::from_secs(5)))?;
+    answer_sleeping(&mut stream, motd, kick)
+}
+
+/// The exchange serve_sleeping has with a client, on any stream
+fn answer_sleeping(stream: &mut (impl Read + Write), motd: &str, kick: &str) -> io::Result<Intent> {
+    let mut first = [0];
+    stream.read_exact(&mut first)?;
+    // Pre 1.7 clients start with 0xFE, they only get a closed connection
+    if first[0] == 0xFE {
+        return Ok(Intent::Other);
+    }
+    let (id, handshake) = read_packet_after(stream, first[0])?;
+    if id != 0x00 {
+        return Ok(Intent::Other);
+    }
+    let mut handshake = handshake.as_slice();
+    let protocol = read_varint(&mut handshake)?;
+    let _address = read_string(&mut handshake)?;
+    let mut port = [0; 2];
+    handshake.read_exact(&mut port)?;
+    let next_state = read_varint(&mut handshake)?;
+
+    match next_state {
+        1 => {
+            // Status request, then an optional ping to time the round trip
+            read_packet(stream)?;
+            let status = json!({
+                // Echo the client's protocol so it shows the motd instead of "outdated server"
+                "version": { "name": "Sleeping", "protocol": protocol },
+                "players": { "max": 0, "online": 0 },
+                "description": { "text": motd },
+            });
+            let mut payload = Vec::new();
+            write_string(&mut payload, &status.to_string());
+            write_packet(stream, 0x00, &payload)?;
+            if let Ok((0x01, ping)) = read_packet(stream) {
+                write_packet(stream, 0x01, &ping)?;
+            }
+            Ok(Intent::Status)
+        }
+        2 | 3 => {
+            let (_, login) = read_packet(stream)?;
+            let name = read_string(&mut login.as_slice()).unwrap_or_default();
+            let mut payload = Vec::new();
+            write_string(&mut payload, &json!({ "text": kick }).to_string());
+            write_packet(stream, 0x00, &payload)?;
+            Ok(Intent::Login(name))
+        }
+        _ => Ok(Intent::Other),
+    }
+}
+
+/// Read a length prefixed packet, returning its id and the rest of its data
+pub fn read_packet(stream: &mut impl Read) -> io::Result<(i32, Vec<u8>)> {
+    let length = read_varint(stream)?;
+    read_body(stream, length)
+}
+
+/// Like read_packet but the first byte of the length was already read
+fn read_packet_after(stream: &mut impl Read, first: u8) -> io::Result<(i32, Vec<u8>)> {
+    let length = read_varint(&mut [first].as_slice().chain(&mut *stream))?;
+    read_body(stream, length)
+}
+
+fn read_body(stream: &mut impl Read, length: i32) -> io::Result<(i32, Vec<u8>)> {
+    let length = usize::try_from(length)
+        .ok()
+        .filter(|length| *length <= MAX_PACKET)
+        .ok_or_else(|| invalid("bad packet length"))?;
+    let mut body = vec![0; length];
+    stream.read_exact(&mut body)?;
+    let mut data = body.as_slice();
+    let id = read_varint(&mut data)?;
+    Ok((id, data.to_vec()))
+}
+
+pub fn write_packet(stream: &mut impl Write, id: i32, payload: &[u8]) -> io::Result<()> {
+    let mut body = Vec::with_capacity(payload.len() + 5);
+    write_varint(&mut body, id);
+    body.extend_from_slice(payload);
+    let mut packet = Vec::with_capacity(body.len() + 5);
+    write_varint(&mut packet, body.len() as i32);
+    packet.extend_from_slice(&body);
+    stream.write_all(&packet)
+}
+
+/// Protocol varints are little endian groups of 7 bits, the high bit set on all but the last
+pub fn read_varint(stream: &mut impl Read) -> io::Result<i32> {
+    let mut value: u32 = 0;
+    for i in 0..5 {
+        let mut byte = [0];
+        stream.read_exact(&mut byte)?;
+        value |= u32::from(byte[0] & 0x7f) << (7 * i);
+        if byte[0] & 0x80 == 0 {
+            return Ok(value as i32);
+        }
+    }
+    Err(invalid("varint too long"))
+}
+
+pub fn write_varint(buffer: &mut Vec<u8>, value: i32) {
+    let mut value = value as u32;
+    loop {
+        if value & !0x7f == 0 {
+            buffer.push(value as u8);
+            return;
+        }
+        buffer.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+}
+
+pub fn read_string(stream: &mut impl Read) -> io::Result<String> {
+    let length = read_varint(stream)?;
+    let length = usize::try_from(length)
+        .ok()
+        .filter(|length| *length <= MAX_PACKET)
+        .ok_or_else(|| invalid("bad string length"))?;
+    let mut bytes = vec![0; length];
+    stream.read_exact(&mut bytes)?;
+    String::from_utf8(bytes).map_err(|_| invalid("string isn't utf-8"))
+}
+
+pub fn write_string(buffer: &mut Vec<u8>, text: &str) {
+    write_varint(buffer, text.len() as i32);
+    buffer.extend_from_slice(text.as_bytes());
+}
+
+fn invalid(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reads what a client sent, keeps what the server wrote back
+    struct Exchange {
+        input: io::Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Exchange {
+        fn new(packets: &[(i32, Vec<u8>)]) -> Self {
+            let mut input = Vec::new();
+            for (id, payload) in packets {
+                write_packet(&mut input, *id, payload).unwrap();
+            }
+            Self {
+                input: io::Cursor::new(input),
+                output: Vec::new(),
+            }
+        }
+
+        /// Packets the server wrote
+        fn replies(&self) -> Vec<(i32, Vec<u8>)> {
+            let mut output = self.output.as_slice();
+            let mut replies = Vec::new();
+            while !output.is_empty() {
+                replies.push(read_packet(&mut output).unwrap());
+            }
+            replies
+        }
+    }
+
+    impl Read for Exchange {
+        fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buffer)
+        }
+    }
+
+    impl Write for Exchange {
+        fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+            self.output.write(buffer)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn handshake(protocol: i32, next_state: i32) -> (i32, Vec<u8>) {
+        let mut payload = Vec::new();
+        write_varint(&mut payload, protocol);
+        write_string(&mut payload, "localhost");
+        payload.extend_from_slice(&25565u16.to_be_bytes());
+        write_varint(&mut payload, next_state);
+        (0x00, payload)
+    }
+
+    fn varint(value: i32) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        write_varint(&mut buffer, value);
+        buffer
+    }
+
+    #[test]
+    fn varints_round_trip() {
+        for value in [0, 1, 127, 128, 255, 25565, 2097151, i32::MAX, -1, i32::MIN] {
+            assert_eq!(read_varint(&mut varint(value).as_slice()).unwrap(), value);
+        }
+        assert_eq!(varint(127), [0x7f]);
+        assert_eq!(varint(128), [0x80, 0x01]);
+        assert_eq!(varint(-1), [0xff, 0xff, 0xff, 0xff, 0x0f]);
+    }
+
+    #[test]
+    fn varints_stop_after_five_bytes() {
+        let error = read_varint(&mut [0xff; 6].as_slice()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        // Cut off before the last byte
+        let error = read_varint(&mut [0x80, 0x80].as_slice()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn lengths_are_bounded() {
+        for length in [-1, i32::MIN, MAX_PACKET as i32 + 1, i32::MAX] {
+            let bytes = varint(length);
+            let error = read_packet(&mut bytes.as_slice()).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+            let error = read_string(&mut bytes.as_slice()).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+        // A length that fits but isn't followed by that much data
+        let mut short = varint(10);
+        short.extend_from_slice(&[0x00, 0x01]);
+        let error = read_body(&mut &short[1..], 10).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn strings_must_be_utf8() {
+        let mut bytes = varint(2);
+        bytes.extend_from_slice(&[0xc3, 0x28]);
+        assert!(read_string(&mut bytes.as_slice()).is_err());
+        let mut bytes = Vec::new();
+        write_string(&mut bytes, "Steve é");
+        assert_eq!(read_string(&mut bytes.as_slice()).unwrap(), "Steve é");
+    }
+
+    #[test]
+    fn sleeping_server_answers_pings() {
+        let mut exchange = Exchange::new(&[
+            handshake(763, 1),
+            (0x00, Vec::new()),
+            (0x01, 42u64.to_be_bytes().to_vec()),
+        ]);
+        let intent = answer_sleeping(&mut exchange, "Asleep", "Waking up").unwrap();
+        assert_eq!(intent, Intent::Status);
+        let replies = exchange.replies();
+        assert_eq!(replies.len(), 2);
+        assert_eq!(replies[0].0, 0x00);
+        let status: Value =
+            serde_json::from_str(&read_string(&mut replies[0].1.as_slice()).unwrap()).unwrap();
+        assert_eq!(status["description"]["text"], "Asleep");
+        assert_eq!(status["version"]["protocol"], 763);
+        assert_eq!(replies[1], (0x01, 42u64.to_be_bytes().to_vec()));
+    }
+
+    #[test]
+    fn sleeping_server_kicks_joining_players() {
+        let mut login = Vec::new();
+        write_string(&mut login, "Steve");
+        let mut exchange = Exchange::new(&[handshake(763, 2), (0x00, login)]);
+        let intent = answer_sleeping(&mut exchange, "Asleep", "Waking up").unwrap();
+        assert_eq!(intent, Intent::Login("Steve".to_string()));
+        let replies = exchange.replies();
+        assert_eq!(replies.len(), 1);
+        let reason = read_string(&mut replies[0].1.as_slice()).unwrap();
+        assert_eq!(reason, json!({ "text": "Waking up" }).to_string());
+    }
+
+    #[test]
+    fn sleeping_server_ignores_the_rest() {
+        let mut legacy = Exchange {
+            input: io::Cursor::new(vec![0xfe, 0x01]),
+            output: Vec::new(),
+        };
+        assert_eq!(answer_sleeping(&mut legacy, "", "").unwrap(), Intent::Other);
+        assert!(legacy.output.is_empty());
+        let mut unknown = Exchange::new(&[handshake(763, 7)]);
+        assert_eq!(
+            answer_sleeping(&mut unknown, "", "").unwrap(),
+            Intent::Other
+        );
+        let mut not_handshake = Exchange::new(&[(0x05, Vec::new())]);
+        assert_eq!(
+            answer_sleeping(&mut not_handshake, "", "").unwrap(),
+            Intent::Other
+        );
+        // Claims a huge packet
+        let mut oversized = Exchange {
+            input: io::Cursor::new(varint(i32::MAX)),
+            output: Vec::new(),
+        };
+        assert!(answer_sleeping(&mut oversized, "", "").is_err());
+    }
+}
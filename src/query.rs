@@ -0,0 +1,205 @@
+use std::{collections::HashMap, io, net::UdpSocket, time::Duration};
+
+/// What the full stat of the GameSpy4 query protocol adds to a server list ping, the protocol is
+/// enabled with enable-query in server.properties
+#[derive(Debug, Clone, Default)]
+pub struct QueryStatus {
+    /// Server software and its plugins, eg. "Paper on 1.20.1: WorldEdit 7.2.15; EssentialsX 2.20.1"
+    pub plugins: String,
+    pub map: String,
+    /// Names of the players online
+    pub players: Vec<String>,
+}
+
+const MAGIC: [u8; 2] = [0xFE, 0xFD];
+const HANDSHAKE: u8 = 0x09;
+const STAT: u8 = 0x00;
+
+/// Ask a server for its full stat over udp
+pub fn query(address: &str, timeout: Duration) -> io::Result<QueryStatus> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.set_read_timeout(Some(timeout))?;
+    socket.connect(address)?;
+    // Only the low 4 bits of each byte are used by the server
+    let session = rand::random::<i32>() & 0x0F0F0F0F;
+
+    // The handshake hands out a challenge token the stat request has to include
+    let mut request = MAGIC.to_vec();
+    request.push(HANDSHAKE);
+    request.extend_from_slice(&session.to_be_bytes());
+    socket.send(&request)?;
+    let response = receive(&socket, HANDSHAKE, session)?;
+    let token = cstrings(&response)
+        .next()
+        .and_then(|token| token.parse::<i64>().ok())
+        .ok_or_else(|| invalid("bad challenge token"))? as i32;
+
+    let mut request = MAGIC.to_vec();
+    request.push(STAT);
+    request.extend_from_slice(&session.to_be_bytes());
+    request.extend_from_slice(&token.to_be_bytes());
+    // Padding asks for the full stat rather than the basic one
+    request.extend_from_slice(&[0; 4]);
+    socket.send(&request)?;
+    let response = receive(&socket, STAT, session)?;
+    parse_full_stat(&response)
+}
+
+/// Wait for a reply of the given type to our session, returning what follows the header
+fn receive(socket: &UdpSocket, kind: u8, session: i32) -> io::Result<Vec<u8>> {
+    let mut buffer = [0; 4096];
+    let length = socket.recv(&mut buffer)?;
+    let reply = &buffer[..length];
+    if reply.len() < 5 || reply[0] != kind || reply[1..5] != session.to_be_bytes() {
+        return Err(invalid("unexpected reply"));
+    }
+    Ok(reply[5..].to_vec())
+}
+
+/// The full stat is "splitnum\0\x80\0" padding, key\0value\0 pairs ending in an empty key, more
+/// padding "\x01player_\0\0" and the player names ending in an empty name
+fn parse_full_stat(data: &[u8]) -> io::Result<QueryStatus> {
+    let data = data
+        .get(11..)
+        .ok_or_else(|| invalid("full stat too short"))?;
+    let mut strings = cstrings(data);
+    let mut values = HashMap::new();
+    loop {
+        let key = strings.next().ok_or_else(|| invalid("full stat cut off"))?;
+        if key.is_empty() {
+            break;
+        }
+        values.insert(key, strings.next().unwrap_or_default());
+    }
+    // "\x01player_" and the empty string after it
+    strings.next();
+    strings.next();
+    let players = strings.take_while(|name| !name.is_empty()).collect();
+    let value = |key: &str| values.get(key).cloned().unwrap_or_default();
+    Ok(QueryStatus {
+        plugins: value("plugins"),
+        map: value("map"),
+        players,
+    })
+}
+
+/// Split null terminated strings, minecraft sends them as latin-1
+fn cstrings(data: &[u8]) -> impl Iterator<Item = String> + '_ {
+    data.split(|byte| *byte == 0)
+        .map(|bytes| bytes.iter().map(|byte| *byte as char).collect())
+}
+
+fn invalid(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A full stat like a paper server with two players sends it, after the type and session
+    fn full_stat() -> Vec<u8> {
+        let mut data = b"splitnum\0\x80\0".to_vec();
+        for (key, value) in [
+            ("hostname", "A Minecraft Server"),
+            ("gametype", "SMP"),
+            ("game_id", "MINECRAFT"),
+            ("version", "1.20.1"),
+            (
+                "plugins",
+                "Paper on 1.20.1: WorldEdit 7.2.15; EssentialsX 2.20.1",
+            ),
+            ("map", "world"),
+            ("numplayers", "2"),
+            ("maxplayers", "20"),
+            ("hostport", "25565"),
+            ("hostip", "127.0.0.1"),
+        ] {
+            data.extend_from_slice(key.as_bytes());
+            data.push(0);
+            data.extend_from_slice(value.as_bytes());
+            data.push(0);
+        }
+        data.extend_from_slice(b"\0\x01player_\0\0");
+        // Latin-1, not utf-8
+        data.extend_from_slice(b"Steve\0Jos\xe9\0\0");
+        data
+    }
+
+    #[test]
+    fn parses_full_stats() {
+        let status = parse_full_stat(&full_stat()).unwrap();
+        assert_eq!(
+            status.plugins,
+            "Paper on 1.20.1: WorldEdit 7.2.15; EssentialsX 2.20.1"
+        );
+        assert_eq!(status.map, "world");
+        assert_eq!(status.players, ["Steve", "José"]);
+    }
+
+    #[test]
+    fn parses_empty_servers() {
+        let mut data = b"splitnum\0\x80\0map\0world\0\0\x01player_\0\0\0".to_vec();
+        let status = parse_full_stat(&data).unwrap();
+        assert_eq!(status.map, "world");
+        assert_eq!(status.plugins, "");
+        assert!(status.players.is_empty());
+        // Cut off before the players, there just aren't any
+        data.truncate(data.len() - 12);
+        assert!(parse_full_stat(&data).unwrap().players.is_empty());
+    }
+
+    #[test]
+    fn refuses_short_stats() {
+        assert!(parse_full_stat(b"splitnum").is_err());
+        assert!(parse_full_stat(b"splitnum\0\x80\0map\0world").is_err());
+    }
+
+    #[test]
+    fn queries_over_udp() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = server.local_addr().unwrap().to_string();
+        std::thread::spawn(move || {
+            let mut buffer = [0; 64];
+            // Handshake, answered with the token as a decimal string
+            let (length, client) = server.recv_from(&mut buffer).unwrap();
+            assert_eq!(&buffer[..3], [0xFE, 0xFD, HANDSHAKE]);
+            assert_eq!(length, 7);
+            let session = buffer[3..7].to_vec();
+            let mut reply = vec![HANDSHAKE];
+            reply.extend_from_slice(&session);
+            reply.extend_from_slice(b"-1234567\0");
+            server.send_to(&reply, client).unwrap();
+            // Full stat request with the token and padding
+            let (length, client) = server.recv_from(&mut buffer).unwrap();
+            assert_eq!(length, 15);
+            assert_eq!(&buffer[..3], [0xFE, 0xFD, STAT]);
+            assert_eq!(buffer[3..7], session);
+            assert_eq!(buffer[7..11], (-1234567i32).to_be_bytes());
+            let mut reply = vec![STAT];
+            reply.extend_from_slice(&session);
+            reply.extend_from_slice(&full_stat());
+            server.send_to(&reply, client).unwrap();
+        });
+        let status = query(&address, Duration::from_secs(5)).unwrap();
+        assert_eq!(status.players, ["Steve", "José"]);
+    }
+
+    #[test]
+    fn ignores_other_sessions() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = server.local_addr().unwrap().to_string();
+        std::thread::spawn(move || {
+            let mut buffer = [0; 64];
+            let (_, client) = server.recv_from(&mut buffer).unwrap();
+            let mut reply = vec![HANDSHAKE];
+            reply.extend_from_slice(
+                &(!i32::from_be_bytes([buffer[3], buffer[4], buffer[5], buffer[6]])).to_be_bytes(),
+            );
+            reply.extend_from_slice(b"1\0");
+            server.send_to(&reply, client).unwrap();
+        });
+        let error = query(&address, Duration::from_secs(5)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,241 @@
+use std::{
+    io::{self, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+const LOGIN: i32 = 3;
+const COMMAND: i32 = 2;
+/// Minecraft won't take bigger packets
+const MAX_COMMAND: usize = 1446;
+/// Replies are split into packets of at most this much text
+const MAX_REPLY_PART: usize = 4096;
+
+#[derive(Debug)]
+pub enum RconError {
+    /// enable-rcon or rcon.password isn't set in server.properties
+    Disabled,
+    /// Nothing is listening, the server isn't running or rcon is disabled
+    Connect(io::Error),
+    /// Wrong rcon.password
+    Auth,
+    /// The command is too long for one packet
+    TooLong,
+    Io(io::Error),
+}
+
+impl From<io::Error> for RconError {
+    fn from(error: io::Error) -> Self {
+        RconError::Io(error)
+    }
+}
+
+/// A logged in rcon connection
+pub struct Rcon {
+    stream: TcpStream,
+    next_id: i32,
+}
+
+impl Rcon {
+    pub fn connect(address: &str, password: &str, timeout: Duration) -> Result<Self, RconError> {
+        let socket = address
+            .to_socket_addrs()
+            .map_err(RconError::Connect)?
+            .next()
+            .ok_or_else(|| {
+                RconError::Connect(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "address didn't resolve",
+                ))
+            })?;
+        let stream = TcpStream::connect_timeout(&socket, timeout).map_err(RconError::Connect)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        let mut rcon = Rcon { stream, next_id: 1 };
+        let id = rcon.send(LOGIN, password)?;
+        // A failed login is answered with id -1
+        let (reply_id, _) = rcon.receive()?;
+        if reply_id != id {
+            return Err(RconError::Auth);
+        }
+        Ok(rcon)
+    }
+
+    /// Run a command, returning what it printed
+    pub fn command(&mut self, command: &str) -> Result<String, RconError> {
+        if command.len() > MAX_COMMAND {
+            return Err(RconError::TooLong);
+        }
+        let id = self.send(COMMAND, command)?;
+        let (_, mut reply) = self.receive()?;
+        // Long replies come in several packets without anything marking the last one, so keep
+        // reading for a moment after a full one
+        let timeout = self.stream.read_timeout()?;
+        let mut part_length = reply.len();
+        while part_length >= MAX_REPLY_PART {
+            self.stream
+                .set_read_timeout(Some(Duration::from_millis(200)))?;
+            match self.receive() {
+                Ok((reply_id, part)) if reply_id == id => {
+                    part_length = part.len();
+                    reply.push_str(&part);
+                }
+                _ => break,
+            }
+        }
+        self.stream.set_read_timeout(timeout)?;
+        Ok(reply)
+    }
+
+    fn send(&mut self, kind: i32, body: &str) -> io::Result<i32> {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.stream.write_all(&encode(id, kind, body))?;
+        Ok(id)
+    }
+
+    /// (request id, body) of the next packet
+    fn receive(&mut self) -> io::Result<(i32, String)> {
+        let (id, _, body) = decode(&mut self.stream)?;
+        Ok((id, body))
+    }
+}
+
+/// A packet with its length in front
+fn encode(id: i32, kind: i32, body: &str) -> Vec<u8> {
+    // id, type, body and two terminating nulls
+    let length = 4 + 4 + body.len() + 2;
+    let mut packet = Vec::with_capacity(4 + length);
+    packet.extend_from_slice(&(length as i32).to_le_bytes());
+    packet.extend_from_slice(&id.to_le_bytes());
+    packet.extend_from_slice(&kind.to_le_bytes());
+    packet.extend_from_slice(body.as_bytes());
+    packet.extend_from_slice(&[0, 0]);
+    packet
+}
+
+/// (request id, type, body) of the next packet
+fn decode(stream: &mut impl Read) -> io::Result<(i32, i32, String)> {
+    let mut header = [0; 4];
+    stream.read_exact(&mut header)?;
+    let length = i32::from_le_bytes(header);
+    if !(10..=(MAX_REPLY_PART as i32 + 10)).contains(&length) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "bad rcon packet length",
+        ));
+    }
+    let mut packet = vec![0; length as usize];
+    stream.read_exact(&mut packet)?;
+    let id = i32::from_le_bytes([packet[0], packet[1], packet[2], packet[3]]);
+    let kind = i32::from_le_bytes([packet[4], packet[5], packet[6], packet[7]]);
+    let body = &packet[8..packet.len() - 2];
+    Ok((id, kind, String::from_utf8_lossy(body).to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    const RESPONSE: i32 = 0;
+
+    /// Answer one connection like minecraft would, the password is "hunter2" and commands are
+    /// answered with `reply` split into MAX_REPLY_PART sized packets
+    fn server(reply: String) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            while let Ok((id, kind, body)) = decode(&mut stream) {
+                match kind {
+                    LOGIN if body == "hunter2" => stream.write_all(&encode(id, COMMAND, "")),
+                    LOGIN => stream.write_all(&encode(-1, COMMAND, "")),
+                    _ => {
+                        let parts: Vec<&[u8]> = reply.as_bytes().chunks(MAX_REPLY_PART).collect();
+                        let parts = if parts.is_empty() {
+                            vec![&[][..]]
+                        } else {
+                            parts
+                        };
+                        parts.into_iter().try_for_each(|part| {
+                            let part = std::str::from_utf8(part).unwrap();
+                            stream.write_all(&encode(id, RESPONSE, part))
+                        })
+                    }
+                }
+                .unwrap();
+            }
+        });
+        address
+    }
+
+    #[test]
+    fn packets_round_trip() {
+        let packet = encode(7, COMMAND, "list");
+        assert_eq!(
+            packet,
+            [14, 0, 0, 0, 7, 0, 0, 0, 2, 0, 0, 0, b'l', b'i', b's', b't', 0, 0]
+        );
+        assert_eq!(
+            decode(&mut packet.as_slice()).unwrap(),
+            (7, COMMAND, "list".to_string())
+        );
+        let empty = encode(-1, LOGIN, "");
+        assert_eq!(empty.len(), 14);
+        assert_eq!(
+            decode(&mut empty.as_slice()).unwrap(),
+            (-1, LOGIN, String::new())
+        );
+    }
+
+    #[test]
+    fn bad_packets_are_refused() {
+        for length in [0, 9, -1, MAX_REPLY_PART as i32 + 11, i32::MAX] {
+            let mut packet = length.to_le_bytes().to_vec();
+            packet.extend_from_slice(&[0; 16]);
+            let error = decode(&mut packet.as_slice()).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+        // Cut off
+        let packet = encode(1, COMMAND, "list");
+        let error = decode(&mut &packet[..10]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn logs_in_and_runs_commands() {
+        let address = server("There are 0 of a max of 20 players online: ".to_string());
+        let mut rcon = Rcon::connect(&address, "hunter2", Duration::from_secs(5)).unwrap();
+        assert_eq!(
+            rcon.command("list").unwrap(),
+            "There are 0 of a max of 20 players online: "
+        );
+        assert!(matches!(
+            rcon.command(&"a".repeat(MAX_COMMAND + 1)),
+            Err(RconError::TooLong)
+        ));
+    }
+
+    #[test]
+    fn wrong_passwords_are_refused() {
+        let address = server(String::new());
+        let error = Rcon::connect(&address, "wrong", Duration::from_secs(5)).err();
+        assert!(matches!(error, Some(RconError::Auth)));
+    }
+
+    #[test]
+    fn long_replies_are_put_back_together() {
+        let reply: String = (0..3 * MAX_REPLY_PART + 100)
+            .map(|i| char::from(b'a' + (i % 26) as u8))
+            .collect();
+        let address = server(reply.clone());
+        let mut rcon = Rcon::connect(&address, "hunter2", Duration::from_secs(5)).unwrap();
+        assert_eq!(rcon.command("help").unwrap(), reply);
+        // Exactly one full packet, the wait for more ends without any
+        let reply = "b".repeat(MAX_REPLY_PART);
+        let address = server(reply.clone());
+        let mut rcon = Rcon::connect(&address, "hunter2", Duration::from_secs(5)).unwrap();
+        assert_eq!(rcon.command("help").unwrap(), reply);
+    }
+}
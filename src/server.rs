@@ -2,57 +2,165 @@
 extern crate lazy_static;
 
 mod common;
+mod java;
+mod lists;
+mod logging;
+mod metrics;
+mod modrinth;
+mod players;
+mod preflight;
+mod prometheus;
+mod properties;
+mod protocol;
+mod query;
+mod rcon;
+mod versions;
+mod web;
 mod actions {
     tonic::include_proto!("actions");
 }
 
 use actions::{
     controller_server::{Controller, ControllerServer},
-    AuthAction, AuthRequest, AuthResponce, BackupRequest, CommandRequest, DownloadRequest,
-    LaunchRequest, OpResponce, OpResult, StopRequest, WorldDownload,
+    AcceptEulaRequest, AddEntryRequest, AuthAction, AuthRequest, AuthResponce, BackupFile,
+    BackupRequest, BackupsRequest, BackupsResponce, CommandRequest, ConsoleLine, ConsoleRequest,
+    DownloadRequest, ErrorCode, GetPropertiesRequest, InstallModRequest, InstallVersionRequest,
+    InstalledModsRequest, JavaRuntime, JavaRuntimesRequest, JavaRuntimesResponce, LaunchRequest,
+    ListEntriesRequest, ListEntriesResponce, ListEntry, MetricsRequest, MetricsResponce,
+    MetricsSample, MinecraftVersion, Mod, ModsResponce, OnlinePlayer, OpResponce, OpResult,
+    PlayerEvent, PlayerEventKind, PlayerEventsRequest, PlayerList, PlayerSession, PlayersRequest,
+    PlayersResponce, PropertiesResponce, Property, RegisterJavaRequest, ReloadConfigRequest,
+    ReloadConfigResponce, RemoveEntryRequest, RemoveModRequest, RestartRequest, SearchModsRequest,
+    ServerPhase, SetPropertiesRequest, StatusRequest, StatusResponce, StopRequest,
+    UpdateModsRequest, VersionsRequest, VersionsResponce, WatchMetricsRequest, WorldDownload,
 };
 use antidote::RwLock;
 use futures::Stream;
-use magic_crypt::{new_magic_crypt, MagicCrypt256, MagicCryptTrait};
+use magic_crypt::{new_magic_crypt, MagicCryptTrait};
 use rand::prelude::*;
 use rolling_set::RollingSet;
+use serde_json::json;
 use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
     fs::File,
     io::{BufRead, BufReader, Write},
-    path::PathBuf,
+    os::unix::process::CommandExt,
+    path::{Path, PathBuf},
     pin::Pin,
-    process::{Child, Command, Stdio},
-    time::SystemTime,
+    process::{Child, ChildStdin, ChildStdout, Command, Stdio},
+    time::{Duration, SystemTime},
 };
-use tokio::sync::mpsc;
+use tokio::sync::{broadcast, mpsc};
 use tokio_stream::{wrappers::ReceiverStream, StreamExt};
 use tonic::{transport::Server, Request, Response, Status};
+use tracing::{debug, error, info, warn, Instrument};
 use ServerState::*;
 
 ///////////////////////////////////////////////////////////////////////////////////////////////////////////////
 // Server setup
 ///////////////////////////////////////////////////////////////////////////////////////////////////////////////
 
+/// Run a minecraft server and let clients launch, stop and back it up
+#[derive(clap::Parser, Debug)]
+#[command(name = "mcsc-server", version)]
+struct Args {
+    /// Config file to use, paths in it are relative to its directory
+    #[arg(long, short, global = true, env = "MCSC_SERVER_CONFIG", default_value = CONFIG_FILE)]
+    config: PathBuf,
+    #[command(subcommand)]
+    command: Option<ServerCommand>,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum ServerCommand {
+    /// Report everything wrong with the config and exit
+    CheckConfig,
+    /// Write a new config with a random key, a matching client config and the directories they
+    /// need
+    Init(InitArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct InitArgs {
+    /// Take the defaults for anything not given instead of asking
+    #[arg(long, short)]
+    yes: bool,
+    /// Replace configs that already exist
+    #[arg(long)]
+    force: bool,
+    /// Socket to serve on
+    #[arg(long)]
+    socket: Option<String>,
+    /// Where clients reach the server, eg. mc.example.com:7878. Defaults to this machine's
+    /// hostname and the socket's port
+    #[arg(long)]
+    address: Option<String>,
+    /// Directory of the minecraft server, relative to the config
+    #[arg(long)]
+    minecraft_directory: Option<String>,
+    /// Folder to store backups in, relative to the minecraft directory
+    #[arg(long)]
+    backup_directory: Option<String>,
+    /// Where to write the matching client config, relative to the server's config
+    #[arg(long, default_value = "mcsc_client.toml")]
+    client_config: PathBuf,
+}
+
 /// Create a server that will allow users to launch, stop a minecraft server as well as download the world file
 // #[tokio::main]
 #[tokio::main(flavor = "current_thread")] // no need to use many threads as traffic will be very low
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = <Args as clap::Parser>::parse();
+    let config_path = std::path::absolute(&args.config)?;
+    CONFIG_PATH
+        .set(config_path.clone())
+        .expect("The config is only picked once");
+    match args.command {
+        Some(ServerCommand::CheckConfig) => std::process::exit(check_config(&config_path)),
+        Some(ServerCommand::Init(init_args)) => std::process::exit(init(&config_path, init_args)),
+        None => {}
+    }
     {
         // Change working dir that of .minecraft
         // This is required for java to load the minecraft sever properly
-        let current_working_directory =
-            std::env::current_dir().expect("Couldn't load current working directory");
-        let minecraft_directory = std::path::Path::new(&CONFIG.minecraft_directory);
-        let mut working_directory = current_working_directory.to_path_buf();
-        working_directory.push(minecraft_directory);
+        let working_directory = config_directory().join(&config().minecraft_directory);
         std::env::set_current_dir(&working_directory)
-            .expect(format!("Unable to set working-dir to {:?}", working_directory).as_ref());
+            .unwrap_or_else(|_| panic!("Unable to set working-dir to {:?}", working_directory));
+    }
+    {
+        let config = config();
+        let log_file = config
+            .log_file
+            .as_ref()
+            .map(|file| config_directory().join(file));
+        logging::init(logging::LogSettings {
+            level: &config.log_level,
+            format: config.log_format,
+            file: log_file.as_deref(),
+            rotation: config.log_rotation,
+            files_kept: config.log_files_kept,
+        })?;
+    }
+
+    if config().java_version.is_some() {
+        // Launches hold the state lock, have the runtimes found before the first one
+        std::thread::spawn(|| java::discover(&config().java_directory));
     }
+    reattach();
+    watch_health();
+    watch_metrics();
+    watch_idle();
+    listen_while_sleeping();
+    serve_prometheus();
+    serve_web();
+
+    tokio::spawn(reload_on_hangup());
 
-    let socket = CONFIG.socket.parse()?;
+    let socket = config().socket.parse()?;
     let server_loader = ControllerService::default();
-    println!("Starting service");
+    info!("Serving grpc on {socket}");
     Server::builder()
+        .layer(tower_layer::layer_fn(|inner| CountRpcs { inner }))
         .add_service(ControllerServer::new(server_loader))
         .serve(socket)
         .await?;
@@ -63,30 +171,131 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 #[derive(Debug, Default)]
 struct ControllerService {}
 
-/// Shorthand for Ok(Responce::new(OpResponce{result: code, comment: comment}))
-fn respond(code: OpResult, comment: &str) -> Result<Response<OpResponce>, Status> {
-    println!("Replying with: {}", comment);
+/// Shorthand for Ok(Responce::new(OpResponce{result: Success, comment: comment}))
+#[allow(clippy::result_large_err)]
+fn respond(comment: &str) -> Result<Response<OpResponce>, Status> {
+    info!(comment, "Replying");
     Ok(Response::new(OpResponce {
-        result: code.into(),
+        result: OpResult::Success.into(),
         comment: comment.to_owned(),
     }))
 }
 
+/// The status a failed rpc ends with, the typed Error rides along in its details
+fn fail(code: ErrorCode, message: impl Into<String>) -> Status {
+    fail_with(code, message, [])
+}
+
+/// fail with machine readable details, eg. [("online", "3".to_string())]
+fn fail_with(
+    code: ErrorCode,
+    message: impl Into<String>,
+    details: impl IntoIterator<Item = (&'static str, String)>,
+) -> Status {
+    let error = actions::Error {
+        code: code.into(),
+        message: message.into(),
+        details: details
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value))
+            .collect(),
+    };
+    warn!(code = code.as_str_name(), "Failing: {}", error.message);
+    let grpc_code = match code {
+        ErrorCode::InternalError | ErrorCode::IoFailed => tonic::Code::Internal,
+        ErrorCode::InvalidToken | ErrorCode::UnknownUser => tonic::Code::Unauthenticated,
+        ErrorCode::NotPermitted => tonic::Code::PermissionDenied,
+        ErrorCode::InvalidRequest => tonic::Code::InvalidArgument,
+        ErrorCode::NotFound => tonic::Code::NotFound,
+        ErrorCode::ServerIdle
+        | ErrorCode::ServerRunning
+        | ErrorCode::PlayersOnline
+        | ErrorCode::EulaNotAccepted
+        | ErrorCode::LaunchFailed
+        | ErrorCode::InvalidConfig
+        | ErrorCode::NotConfigured => tonic::Code::FailedPrecondition,
+        ErrorCode::Busy => tonic::Code::Aborted,
+        ErrorCode::TimedOut => tonic::Code::DeadlineExceeded,
+        ErrorCode::UpstreamFailed | ErrorCode::Unreachable => tonic::Code::Unavailable,
+    };
+    Status::with_details(
+        grpc_code,
+        error.message.clone(),
+        prost::Message::encode_to_vec(&error).into(),
+    )
+}
+
+fn not_permitted(action: AuthAction) -> Status {
+    fail_with(
+        ErrorCode::NotPermitted,
+        format!("Not permitted to {}", action.as_str_name()),
+        [("action", action.as_str_name().to_string())],
+    )
+}
+
+/// Run work that blocks, like a download, on tokio's blocking threads so other rpcs carry on
+/// meanwhile. It stays in the rpc's log span
+async fn blocking<T: Send + 'static>(
+    work: impl FnOnce() -> T + Send + 'static,
+) -> Result<T, Status> {
+    let span = tracing::Span::current();
+    tokio::task::spawn_blocking(move || span.in_scope(work))
+        .await
+        .map_err(|error| fail(ErrorCode::InternalError, format!("Stopped: {error}")))
+}
+
+/// Who a user is in the logs
+fn log_name(user: &User) -> &str {
+    if user.name.is_empty() {
+        "shared key"
+    } else {
+        &user.name
+    }
+}
+
+/// Fail with InvalidToken unless `token` was handed out for `action` and is still fresh
+#[allow(clippy::result_large_err)]
+fn require_token(token: Vec<u8>, action: AuthAction) -> Result<(), Status> {
+    let user = token_user(&token);
+    if verify_key(Key { key: token, action }) {
+        if let Some(user) = user {
+            tracing::Span::current().record("user", log_name(&user));
+        }
+        Ok(())
+    } else {
+        Err(fail_with(
+            ErrorCode::InvalidToken,
+            "Invalid token",
+            [("action", action.as_str_name().to_string())],
+        ))
+    }
+}
+
 #[tonic::async_trait]
 impl Controller for ControllerService {
     async fn auth(&self, req: Request<AuthRequest>) -> Result<Response<AuthResponce>, Status> {
-        let action = match AuthAction::from_i32(req.into_inner().action) {
+        let req = req.into_inner();
+        let action = match AuthAction::from_i32(req.action) {
             Some(action) => action,
+            None => return Err(fail(ErrorCode::InvalidRequest, "Invalid action")),
+        };
+        let user = match find_user(&req.user) {
+            Some(user) => user,
             None => {
-                return Ok(Response::new(AuthResponce {
-                    result: OpResult::Fail as i32,
-                    key: Vec::new(),
-                    comment: "Invalid action".to_string(),
-                }))
+                count_auth_failure(action, "unknown_user");
+                return Err(fail_with(
+                    ErrorCode::UnknownUser,
+                    "Unknown user",
+                    [("user", req.user)],
+                ));
             }
         };
-        let key = authorize_key(action);
-        let encrypted_key = encrypt(key);
+        if !user.may(action) {
+            count_auth_failure(action, "not_permitted");
+            return Err(not_permitted(action));
+        }
+        let key = authorize_key(action, &user);
+        let encrypted_key = encrypt(key, &user.key);
         let result = OpResult::Success.into();
         Ok(Response::new(AuthResponce {
             result,
@@ -97,57 +306,41 @@ impl Controller for ControllerService {
 
     async fn backup(&self, req: Request<BackupRequest>) -> Result<Response<OpResponce>, Status> {
         let key = req.into_inner().token;
-        if !verify_key(Key {
-            key,
-            action: AuthAction::Backup,
-        }) {
-            return Err(Status::new(tonic::Code::InvalidArgument, "Invalid token"));
-        }
-        let mut state = STATE.write();
-        let result = state.backup();
-        match result {
-            Ok(_) => respond(OpResult::Success, "Backed up successfully"),
-            Err(download_error) => match download_error {
-                BackupError::OtherBackup => respond(OpResult::Fail, "backed up successfully"),
-                BackupError::ServerRunning => {
-                    respond(OpResult::Fail, "Back up failed, server still running")
-                }
-                BackupError::Compression => respond(
-                    OpResult::Fail,
-                    "Back up failed to compress the world folder",
-                ),
-            },
+        require_token(key, AuthAction::Backup)?;
+        match blocking(backup_world).await? {
+            Ok(_) => respond("Backed up successfully"),
+            Err(backup_error) => Err(backup_failure(backup_error)),
         }
     }
 
     async fn command(&self, req: Request<CommandRequest>) -> Result<Response<OpResponce>, Status> {
         let req = req.into_inner();
         let key = req.token;
-        if !verify_key(Key {
-            key,
-            action: AuthAction::Command,
-        }) {
-            return respond(OpResult::Denied, "Invalid token");
-        }
+        require_token(key, AuthAction::Command)?;
         let mut state = STATE.write();
         let res = state.run_command(&req.command);
         match res {
             Err(command_error) => {
-                match command_error{
+                Err(match command_error{
                     CommandError::Idle => {
-                        respond(OpResult::Fail, "Server idle, command can't be run")
+                        fail(ErrorCode::ServerIdle, "Server idle, command can't be run")
                     },
                     CommandError::Downloading=> {
-                        respond(OpResult::Fail, "Download in progress! Command can't be run")
+                        fail(ErrorCode::Busy, "Download in progress! Command can't be run")
                     },
                     CommandError::ProccesError => {
-                        respond(OpResult::Fail, "Error running command on procces")
+                        fail(ErrorCode::InternalError, "Error running command on procces")
                     },
-                }
+                    CommandError::Rcon(rcon_error) => rcon_failure(rcon_error),
+                    CommandError::NoConsole => {
+                        fail_with(ErrorCode::NotConfigured, "Server was launched by an earlier mcsc-server, commands need rcon or console_pipe", [("setting", "rcon".to_string())])
+                    },
+                })
             }
-            Ok(_) => {
-                respond(OpResult::Success, "Command ran successfully! note this does not necessarily mean the command was valid only that it's execution was attempted")
+            Ok(output) if output.is_empty() => {
+                respond("Command ran successfully! note this does not necessarily mean the command was valid only that it's execution was attempted")
             }
+            Ok(output) => respond(&output),
         }
     }
 
@@ -157,36 +350,39 @@ impl Controller for ControllerService {
         &self,
         req: Request<DownloadRequest>,
     ) -> Result<Response<Self::DownloadStream>, Status> {
-        let key = req.into_inner().token;
-        if !verify_key(Key {
-            key,
-            action: AuthAction::Download,
-        }) {
-            return Err(Status::new(tonic::Code::InvalidArgument, "Invalid token"));
-        }
+        let req = req.into_inner();
+        require_token(req.token, AuthAction::Download)?;
 
-        let file = match latest_file(&CONFIG.backup_directory) {
+        let path = if req.name.is_empty() {
+            latest_file(&config().backup_directory)
+        } else {
+            backup_path(&req.name)
+        };
+        let file = match path {
             Some(path) => match File::open(path) {
                 Ok(handle) => handle,
-                Err(_) => return Err(Status::not_found("No backups")),
+                Err(_) => return Err(fail(ErrorCode::NotFound, "No such backup")),
             },
-            None => return Err(Status::not_found("No backups")),
+            None if req.name.is_empty() => return Err(fail(ErrorCode::NotFound, "No backups")),
+            None => return Err(fail(ErrorCode::NotFound, "No such backup")),
         };
 
         // Create iterator that yields WorldDownload
         let wdl = match WorldDownloadIterator::new(file) {
             Some(dl) => dl,
-            None => return Err(Status::aborted("Unable to fetch file metadata")),
+            None => return Err(fail(ErrorCode::IoFailed, "Unable to fetch file metadata")),
         };
 
         let mut stream = Box::pin(tokio_stream::iter(wdl));
 
         let (send_channel, receive_channel) = mpsc::channel(128);
-        tokio::spawn(async move {
+        let forward = async move {
             while let Some(item) = stream.next().await {
-                match send_channel.send(Result::<_, Status>::Ok(item)).await {
+                let bytes = item.as_ref().map_or(0, |part| part.data.len() as u64);
+                match send_channel.send(item).await {
                     Ok(_) => {
                         // item (server response) was queued to be send to client
+                        STATS.write().download_bytes += bytes;
                     }
                     Err(_item) => {
                         // output_stream was build from receive_channel and both are dropped
@@ -194,8 +390,9 @@ impl Controller for ControllerService {
                     }
                 }
             }
-            println!("\tclient disconnected");
-        });
+            debug!("Client disconnected");
+        };
+        tokio::spawn(forward.in_current_span());
 
         let output_stream = ReceiverStream::new(receive_channel);
         Ok(Response::new(
@@ -205,222 +402,2938 @@ impl Controller for ControllerService {
 
     /// Handle launch request
     async fn launch(&self, req: Request<LaunchRequest>) -> Result<Response<OpResponce>, Status> {
-        let key = req.into_inner().token;
-        if !verify_key(Key {
-            key,
-            action: AuthAction::Launch,
-        }) {
-            return respond(OpResult::Denied, "Invalid Token");
-        }
+        let req = req.into_inner();
+        require_token(req.token, AuthAction::Launch)?;
 
-        let mut state = STATE.write();
-        let res = state.launch();
+        let res = STATE.write().launch();
         match res {
-            Ok(_) => respond(OpResult::Success, "Launched successfully"),
-            Err(launch_error) => match launch_error {
-                LaunchError::Launch => respond(OpResult::Fail, "Failed to launch server"),
-                LaunchError::AlreadyRunning => respond(OpResult::Fail, "Server already running"),
-                LaunchError::Downloading => {
-                    respond(OpResult::Fail, "Download in progress! Can't launch")
-                }
-            },
+            Ok(_) if !req.wait => respond("Launched successfully"),
+            Ok(_) => respond(&wait_until_ready().await?),
+            Err(launch_error) => Err(launch_failure(launch_error, "")),
         }
     }
 
     /// Handle stopping
     async fn stop(&self, req: Request<StopRequest>) -> Result<Response<OpResponce>, Status> {
-        let key = req.into_inner().token;
-        use AuthAction;
-        if !verify_key(Key {
-            key,
-            action: AuthAction::Stop,
-        }) {
-            return respond(OpResult::Denied, "Invalid token");
+        let req = req.into_inner();
+        require_token(req.token, AuthAction::Stop)?;
+        if !req.force {
+            wait_for_empty_server().await?;
         }
-        let mut state = STATE.write();
-        let res = state.stop();
-        match res {
-            Err(stop_error) => match stop_error {
-                StopError::ProccesError => respond(
-                    OpResult::Fail,
+        match blocking(stop_server).await? {
+            Err(stop_error) => Err(match stop_error {
+                StopError::ProccesError => fail(
+                    ErrorCode::InternalError,
                     "Error occurred while stopping server procces",
                 ),
-                _ => respond(OpResult::Fail, "Server already idle"),
-            },
+                StopError::Downloading => fail(ErrorCode::Busy, "Download in progress! Can't stop"),
+                StopError::Idle => fail(ErrorCode::ServerIdle, "Server already idle"),
+            }),
             Ok(_) => {
-                return respond(OpResult::Success, "Server stopped successfully");
+                return respond("Server stopped successfully");
             }
         }
     }
-}
 
-///////////////////////////////////////////////////////////////////////////////////////////////////////////////
-// Server state management using the state machine pattern
-///////////////////////////////////////////////////////////////////////////////////////////////////////////////
+    /// Stop the server and launch it again, an idle server is just launched
+    async fn restart(&self, req: Request<RestartRequest>) -> Result<Response<OpResponce>, Status> {
+        let req = req.into_inner();
+        require_token(req.token, AuthAction::Restart)?;
+        if !req.force {
+            wait_for_empty_server().await?;
+        }
+        match blocking(stop_server).await? {
+            Ok(_) | Err(StopError::Idle) => {}
+            Err(StopError::Downloading) => {
+                return Err(fail(ErrorCode::Busy, "Download in progress! Can't restart"))
+            }
+            Err(StopError::ProccesError) => {
+                return Err(fail(
+                    ErrorCode::InternalError,
+                    "Error occurred while stopping server procces",
+                ))
+            }
+        }
+        let res = STATE.write().launch();
+        match res {
+            Ok(_) => respond("Restarted successfully"),
+            Err(launch_error) => Err(launch_failure(
+                launch_error,
+                "Stopped, but failed to launch again: ",
+            )),
+        }
+    }
 
-#[derive(Debug)]
-enum ServerState {
-    Idle,
-    Running { procces: Child },
-    BackingUp,
-}
+    /// Report what the server is doing and what the last health check of the game port found
+    async fn status(
+        &self,
+        req: Request<StatusRequest>,
+    ) -> Result<Response<StatusResponce>, Status> {
+        require_token(req.into_inner().token, AuthAction::Status)?;
+        let phase = server_phase();
+        let health = HEALTH.read().clone();
+        let mut responce = StatusResponce {
+            result: OpResult::Success.into(),
+            phase: phase.into(),
+            checked: health.checked.map(unix_time).unwrap_or_default(),
+            ..Default::default()
+        };
+        responce.comment = match (phase, &health.status) {
+            (ServerPhase::Idle, _) => "Server idle".to_string(),
+            (ServerPhase::BackingUp, _) => "Backing up".to_string(),
+            (ServerPhase::Starting, _) => "Starting, not accepting players yet".to_string(),
+            (ServerPhase::Ready, None) => "Running, but not answering pings".to_string(),
+            (ServerPhase::Ready, Some(status)) => format!(
+                "Ready, {} with {}/{} players, {}ms",
+                status.version,
+                status.online,
+                status.max,
+                status.latency.as_millis()
+            ),
+        };
+        if let Some(status) = health.status {
+            responce.responding = true;
+            responce.version = status.version;
+            responce.protocol = status.protocol;
+            responce.motd = status.motd;
+            responce.online = status.online;
+            responce.max = status.max;
+            responce.latency_ms = status.latency.as_millis() as u32;
+        }
+        if let Some(query) = health.query {
+            responce.players = query.players;
+            responce.map = query.map;
+            responce.plugins = query.plugins;
+        }
+        responce.eula_accepted = preflight::eula_accepted();
+        if !responce.eula_accepted {
+            responce
+                .comment
+                .push_str(", the minecraft EULA hasn't been accepted");
+        } else if let Some((name, time)) = preflight::eula_acceptance() {
+            responce.eula_accepted_by = name;
+            responce.eula_accepted_at = time;
+        }
+        Ok(Response::new(responce))
+    }
 
-impl ServerState {
-    fn backup(&mut self) -> Result<(), BackupError> {
-        use ServerState::*;
-        match self {
-            Idle => {
-                *self = BackingUp;
-                // Compress the file
-                match Command::new("tar")
-                    .arg("-czf")
-                    .arg(format!(
-                        "{}/{}.tar.gz",
-                        &CONFIG.backup_directory,
-                        common::ran_letters(32)
-                    ))
-                    .arg("world")
-                    .spawn()
-                {
-                    Ok(mut child) => {
-                        if let Err(_) = child.wait() {
-                            return Err(BackupError::Compression);
-                        }
-                    }
-                    Err(_) => {
-                        return Err(BackupError::Compression);
-                    }
+    /// Write eula.txt agreeing to the minecraft EULA, recording who did it
+    async fn accept_eula(
+        &self,
+        req: Request<AcceptEulaRequest>,
+    ) -> Result<Response<OpResponce>, Status> {
+        let token = req.into_inner().token;
+        require_token(token.clone(), AuthAction::AcceptEula)?;
+        if preflight::eula_accepted() {
+            return respond("The EULA was already accepted");
+        }
+        let name = match token_user(&token) {
+            Some(user) if !user.name.is_empty() => user.name,
+            _ => "the shared key".to_string(),
+        };
+        match preflight::accept_eula(&name, unix_time(SystemTime::now())) {
+            Ok(()) => {
+                info!("The minecraft EULA was accepted by {name}");
+                respond("EULA accepted, the server can be launched")
+            }
+            Err(error) => Err(fail(
+                ErrorCode::IoFailed,
+                format!("Couldn't write {}: {error}", preflight::EULA_FILE),
+            )),
+        }
+    }
+
+    /// Read mcsc_server.toml again, applying what can be applied without a restart
+    async fn reload_config(
+        &self,
+        req: Request<ReloadConfigRequest>,
+    ) -> Result<Response<ReloadConfigResponce>, Status> {
+        let token = req.into_inner().token;
+        require_token(token.clone(), AuthAction::ReloadConfig)?;
+        let reload = config_reload();
+        let name = match token_user(&token) {
+            Some(user) if !user.name.is_empty() => user.name,
+            _ => "the shared key".to_string(),
+        };
+        info!("Config reload by {name}: {}", reload.describe());
+        let problems: Vec<String> = reload.problems.iter().map(ToString::to_string).collect();
+        if !reload.applied {
+            // The problems are what the person reloading needs to see, so they're in the message too
+            return Err(fail_with(
+                ErrorCode::InvalidConfig,
+                format!("{}\n{}", reload.describe(), problems.join("\n")),
+                [("problems", problems.join("\n"))],
+            ));
+        }
+        Ok(Response::new(ReloadConfigResponce {
+            result: OpResult::Success.into(),
+            comment: reload.describe(),
+            changed: reload.changed,
+            needs_restart: reload.needs_restart,
+            problems,
+        }))
+    }
+
+    /// The resource samples kept in memory, oldest first
+    async fn metrics(
+        &self,
+        req: Request<MetricsRequest>,
+    ) -> Result<Response<MetricsResponce>, Status> {
+        require_token(req.into_inner().token, AuthAction::Metrics)?;
+        let samples: Vec<MetricsSample> = METRICS
+            .read()
+            .iter()
+            .map(|(phase, sample)| metrics_sample(*phase, sample))
+            .collect();
+        Ok(Response::new(MetricsResponce {
+            result: OpResult::Success.into(),
+            comment: format!("{} samples", samples.len()),
+            samples,
+            interval: config().metrics_interval,
+        }))
+    }
+
+    /// Recent console lines, then new ones as the server prints them
+    type ConsoleStream = ConsoleLineStream;
+    async fn console(
+        &self,
+        req: Request<ConsoleRequest>,
+    ) -> Result<Response<Self::ConsoleStream>, Status> {
+        let req = req.into_inner();
+        let (send_channel, receive_channel) = mpsc::channel(256);
+        require_token(req.token, AuthAction::Console)?;
+        // Subscribe before copying the history so no line falls in between
+        let mut lines = CONSOLE_LINES.subscribe();
+        let history: Vec<(SystemTime, String)> = {
+            let history = CONSOLE_HISTORY.read();
+            let skip = history.len().saturating_sub(req.history as usize);
+            history.iter().skip(skip).cloned().collect()
+        };
+        let forward = async move {
+            for line in history {
+                if send_channel.send(Ok(console_line(line))).await.is_err() {
+                    return;
+                }
+            }
+            loop {
+                let line = match lines.recv().await {
+                    Ok(line) => line,
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                if send_channel.send(Ok(console_line(line))).await.is_err() {
+                    // Client disconnected
+                    break;
                 }
-                // remove_oldest_backup("minecraft/backups");
+            }
+        };
+        tokio::spawn(forward.in_current_span());
+        let output_stream = ReceiverStream::new(receive_channel);
+        Ok(Response::new(Box::pin(output_stream) as Self::ConsoleStream))
+    }
+
+    /// The backups that can be downloaded, newest first
+    async fn backups(
+        &self,
+        req: Request<BackupsRequest>,
+    ) -> Result<Response<BackupsResponce>, Status> {
+        require_token(req.into_inner().token, AuthAction::Backups)?;
+        let files = match std::fs::read_dir(&config().backup_directory) {
+            Ok(files) => files,
+            Err(_) => {
+                return Ok(Response::new(BackupsResponce {
+                    result: OpResult::Success.into(),
+                    comment: "No backups".to_string(),
+                    ..Default::default()
+                }))
+            }
+        };
+        let mut files: Vec<(PathBuf, SystemTime)> = iter_paths_with_sys_time(files).collect();
+        files.sort_by_key(|(_, time)| std::cmp::Reverse(*time));
+        let backups: Vec<BackupFile> = files
+            .into_iter()
+            .map(|(path, time)| BackupFile {
+                name: path
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .to_string(),
+                size: std::fs::metadata(&path).map_or(0, |metadata| metadata.len()),
+                created: unix_time(time),
+            })
+            .collect();
+        Ok(Response::new(BackupsResponce {
+            result: OpResult::Success.into(),
+            comment: format!("{} backups", backups.len()),
+            backups,
+        }))
+    }
+
+    /// Stream resource samples as they're taken
+    type WatchMetricsStream = MetricsSampleStream;
+    async fn watch_metrics(
+        &self,
+        req: Request<WatchMetricsRequest>,
+    ) -> Result<Response<Self::WatchMetricsStream>, Status> {
+        let (send_channel, receive_channel) = mpsc::channel(128);
+        require_token(req.into_inner().token, AuthAction::WatchMetrics)?;
+        let mut samples = METRIC_SAMPLES.subscribe();
+        let forward = async move {
+            loop {
+                let (phase, sample) = match samples.recv().await {
+                    Ok(sample) => sample,
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                if send_channel
+                    .send(Ok(metrics_sample(phase, &sample)))
+                    .await
+                    .is_err()
                 {
-                    let mut num_backups = std::fs::read_dir(&CONFIG.backup_directory)
-                        .into_iter()
-                        .flatten()
-                        .count();
-                    while num_backups > 10 {
-                        num_backups -= 1;
-                        remove_oldest_backup(&CONFIG.backup_directory);
-                    }
+                    // Client disconnected
+                    break;
                 }
-                *self = Idle;
-                Ok(())
             }
-            Running { procces: _ } => Err(BackupError::ServerRunning),
-            BackingUp => Err(BackupError::OtherBackup),
+        };
+        tokio::spawn(forward.in_current_span());
+        let output_stream = ReceiverStream::new(receive_channel);
+        Ok(Response::new(
+            Box::pin(output_stream) as Self::WatchMetricsStream
+        ))
+    }
+
+    /// List the java runtimes the server can be launched with
+    async fn java_runtimes(
+        &self,
+        req: Request<JavaRuntimesRequest>,
+    ) -> Result<Response<JavaRuntimesResponce>, Status> {
+        let key = req.into_inner().token;
+        require_token(key, AuthAction::JavaRuntimes)?;
+        let runtimes: Vec<JavaRuntime> = java::discover(&config().java_directory)
+            .into_iter()
+            .map(|runtime| JavaRuntime {
+                major: runtime.major,
+                version: runtime.version,
+                path: runtime.home.display().to_string(),
+                source: runtime.source.to_string(),
+            })
+            .collect();
+        Ok(Response::new(JavaRuntimesResponce {
+            result: OpResult::Success.into(),
+            comment: format!("Found {} java runtimes", runtimes.len()),
+            runtimes,
+            required: config().java_version.unwrap_or(0),
+        }))
+    }
+
+    /// Register a java home or extract a jdk archive into the java directory
+    async fn register_java(
+        &self,
+        req: Request<RegisterJavaRequest>,
+    ) -> Result<Response<OpResponce>, Status> {
+        let req = req.into_inner();
+        require_token(req.token, AuthAction::RegisterJava)?;
+        match java::register(&config().java_directory, &req.path) {
+            Ok(runtime) => respond(&format!(
+                "Registered java {} ({}) at {}",
+                runtime.major,
+                runtime.version,
+                runtime.home.display()
+            )),
+            Err(register_error) => Err(match register_error {
+                java::RegisterError::NotFound => {
+                    fail(ErrorCode::NotFound, "No such file or directory")
+                }
+                java::RegisterError::NotJava => fail(
+                    ErrorCode::InvalidRequest,
+                    "No java runtime found at that path",
+                ),
+                java::RegisterError::UnsupportedArchive => fail(
+                    ErrorCode::InvalidRequest,
+                    "Unsupported archive, use a .tar.gz, .tgz, .tar.xz or .zip",
+                ),
+                java::RegisterError::Extraction => {
+                    fail(ErrorCode::IoFailed, "Failed to extract the archive")
+                }
+                java::RegisterError::Io => {
+                    fail(ErrorCode::IoFailed, "Unable to write to the java directory")
+                }
+            }),
         }
     }
 
-    fn check_stop(&mut self) {
-        if let Running { procces: c } = self {
-            let res = c.try_wait();
-            if let Ok(possible_exit_code) = res {
-                if let Some(_exit_code) = possible_exit_code {
-                    //Procces finished
-                    *self = Idle;
+    /// List the minecraft versions available from the version manifest
+    async fn versions(
+        &self,
+        req: Request<VersionsRequest>,
+    ) -> Result<Response<VersionsResponce>, Status> {
+        let req = req.into_inner();
+        let mut responce = VersionsResponce {
+            installed: versions::installed(&config().server_jar).unwrap_or_default(),
+            ..Default::default()
+        };
+        require_token(req.token, AuthAction::Versions)?;
+        let location = config().version_manifest.clone();
+        match blocking(move || versions::manifest(&location)).await? {
+            Ok(manifest) => {
+                responce.result = OpResult::Success.into();
+                responce.versions = manifest
+                    .versions
+                    .into_iter()
+                    .filter(|version| req.snapshots || version.kind == "release")
+                    .map(|version| MinecraftVersion {
+                        id: version.id,
+                        kind: version.kind,
+                        release_time: version.release_time,
+                    })
+                    .collect();
+                responce.comment = format!("Found {} versions", responce.versions.len());
+                responce.latest_release = manifest.latest.release;
+                responce.latest_snapshot = manifest.latest.snapshot;
+            }
+            Err(_) => {
+                return Err(fail_with(
+                    ErrorCode::UpstreamFailed,
+                    "Unable to load the version manifest",
+                    [("service", "mojang".to_string())],
+                ))
+            }
+        }
+        Ok(Response::new(responce))
+    }
+
+    /// Back up the world and switch the server jar to another minecraft version
+    async fn install_version(
+        &self,
+        req: Request<InstallVersionRequest>,
+    ) -> Result<Response<OpResponce>, Status> {
+        let req = req.into_inner();
+        require_token(req.token, AuthAction::InstallVersion)?;
+        // Don't download for nothing, install_version checks again once it has the lock
+        if server_running() {
+            return Err(install_failure(InstallError::ServerRunning));
+        }
+        let (manifest, jar) = (
+            config().version_manifest.clone(),
+            config().server_jar.clone(),
+        );
+        // Out of the state lock, the download can take a while
+        let downloaded = blocking(move || versions::download(&manifest, &req.version, &jar))
+            .await?
+            .map_err(|version_error| install_failure(InstallError::Version(version_error)))?;
+        match blocking(move || install_version(downloaded)).await? {
+            Ok(installed) => {
+                let mut comment = format!("Installed minecraft {}", installed.version);
+                if let Some(previous) = installed.previous {
+                    comment.push_str(&format!(", previous jar kept as {previous}"));
+                }
+                if let Some(java) = installed.java_version {
+                    comment.push_str(&format!(", requires java {java}"));
+                    if config().java_version.is_some_and(|pinned| pinned != java) {
+                        comment.push_str(" (update java_version in mcsc_server.toml)");
+                    }
                 }
+                respond(&comment)
             }
+            Err(install_error) => Err(install_failure(install_error)),
         }
     }
 
-    fn run_command(&mut self, cmd: &str) -> Result<(), CommandError> {
-        match self {
-            Running { procces } => {
-                let pstdin = procces.stdin.as_mut();
-                match pstdin {
-                    Some(buff) => match buff.write_all(&format!("\n{}\n", cmd).into_bytes()) {
-                        Err(_) => Err(CommandError::ProccesError),
-                        _ => Ok(()),
-                    },
-                    None => Err(CommandError::ProccesError),
+    /// Search modrinth for mods or plugins that fit the server's loader and version
+    async fn search_mods(
+        &self,
+        req: Request<SearchModsRequest>,
+    ) -> Result<Response<ModsResponce>, Status> {
+        let req = req.into_inner();
+        require_token(req.token, AuthAction::SearchMods)?;
+        let instance = mod_instance()?;
+        let (instance, hits) = blocking(move || {
+            let hits = modrinth::search(&instance, &req.query);
+            (instance, hits)
+        })
+        .await?;
+        match hits {
+            Ok(hits) => {
+                let mut responce = ModsResponce {
+                    result: OpResult::Success.into(),
+                    comment: format!("Found {} projects", hits.len()),
+                    loader: instance.loader,
+                    game_version: instance.game_version,
+                    ..Default::default()
+                };
+                responce.mods = hits
+                    .into_iter()
+                    .map(|hit| Mod {
+                        project_id: hit.project_id,
+                        slug: hit.slug,
+                        title: hit.title,
+                        description: hit.description,
+                        version: hit.latest_version,
+                        filename: String::new(),
+                    })
+                    .collect();
+                Ok(Response::new(responce))
+            }
+            Err(mod_error) => Err(mod_failure(mod_error)),
+        }
+    }
+
+    /// List the mods recorded in the lockfile
+    async fn installed_mods(
+        &self,
+        req: Request<InstalledModsRequest>,
+    ) -> Result<Response<ModsResponce>, Status> {
+        require_token(req.into_inner().token, AuthAction::InstalledMods)?;
+        match modrinth::installed() {
+            Ok(installed) => respond_mods(
+                format!("{} mods installed", installed.len()),
+                Some(installed),
+            ),
+            Err(mod_error) => Err(mod_failure(mod_error)),
+        }
+    }
+
+    /// Install a mod or plugin and its required dependencies
+    async fn install_mod(
+        &self,
+        req: Request<InstallModRequest>,
+    ) -> Result<Response<OpResponce>, Status> {
+        let req = req.into_inner();
+        require_token(req.token, AuthAction::InstallMod)?;
+        let instance = mod_instance()?;
+        match blocking(move || modrinth::install(&instance, &req.project)).await? {
+            Ok(installed) if installed.is_empty() => {
+                respond("Already installed, use UpdateMods to get the newest version")
+            }
+            Ok(installed) => respond(&format!(
+                "Installed {}{}",
+                describe_mods(&installed),
+                restart_note()
+            )),
+            Err(mod_error) => Err(mod_failure(mod_error)),
+        }
+    }
+
+    /// Update one or all installed mods to their newest compatible versions
+    async fn update_mods(
+        &self,
+        req: Request<UpdateModsRequest>,
+    ) -> Result<Response<OpResponce>, Status> {
+        let req = req.into_inner();
+        require_token(req.token, AuthAction::UpdateMods)?;
+        let instance = mod_instance()?;
+        match blocking(move || modrinth::update(&instance, &req.project)).await? {
+            Ok(updated) if updated.is_empty() => respond("Already up to date"),
+            Ok(updated) => respond(&format!(
+                "Updated {}{}",
+                describe_mods(&updated),
+                restart_note()
+            )),
+            Err(mod_error) => Err(mod_failure(mod_error)),
+        }
+    }
+
+    /// Remove an installed mod
+    async fn remove_mod(
+        &self,
+        req: Request<RemoveModRequest>,
+    ) -> Result<Response<OpResponce>, Status> {
+        let req = req.into_inner();
+        require_token(req.token, AuthAction::RemoveMod)?;
+        let instance = mod_instance()?;
+        match modrinth::remove(&instance, &req.project) {
+            Ok(removed) => respond(&format!("Removed {}{}", removed.title, restart_note())),
+            Err(mod_error) => Err(mod_failure(mod_error)),
+        }
+    }
+
+    /// Read server.properties, marking which keys the requesting user may change
+    async fn get_properties(
+        &self,
+        req: Request<GetPropertiesRequest>,
+    ) -> Result<Response<PropertiesResponce>, Status> {
+        let token = req.into_inner().token;
+        let user = token_user(&token);
+        require_token(token, AuthAction::GetProperties)?;
+        match properties::Properties::load() {
+            Ok(loaded) => {
+                respond_properties("Loaded server.properties".to_string(), &loaded, user, false)
+            }
+            Err(_) => Err(fail(
+                ErrorCode::IoFailed,
+                "Unable to read server.properties",
+            )),
+        }
+    }
+
+    /// Validate and write changes to server.properties
+    async fn set_properties(
+        &self,
+        req: Request<SetPropertiesRequest>,
+    ) -> Result<Response<PropertiesResponce>, Status> {
+        let req = req.into_inner();
+        let user = token_user(&req.token);
+        require_token(req.token, AuthAction::SetProperties)?;
+        let user = match user {
+            Some(user) => user,
+            None => return Err(fail(ErrorCode::UnknownUser, "Unknown user")),
+        };
+        let changes: BTreeMap<String, String> = req.properties.into_iter().collect();
+        let forbidden: Vec<&str> = changes
+            .keys()
+            .filter(|key| !user.may_edit_property(key))
+            .map(String::as_str)
+            .collect();
+        if !forbidden.is_empty() {
+            return Err(fail_with(
+                ErrorCode::NotPermitted,
+                format!("Not permitted to change {}", forbidden.join(", ")),
+                [("properties", forbidden.join(","))],
+            ));
+        }
+        if let Err(problems) = properties::Properties::validate(&changes) {
+            return Err(fail_with(
+                ErrorCode::InvalidRequest,
+                problems.join("; "),
+                [("problems", problems.join("\n"))],
+            ));
+        }
+
+        let mut state = STATE.write();
+        match state.set_properties(&changes) {
+            Ok((saved, restart_required)) => respond_properties(
+                if restart_required {
+                    "Saved, restart the server to apply".to_string()
+                } else {
+                    "Saved".to_string()
+                },
+                &saved,
+                Some(user),
+                restart_required,
+            ),
+            Err(properties_error) => Err(match properties_error {
+                PropertiesError::Downloading => fail(
+                    ErrorCode::Busy,
+                    "Backup in progress! Can't change properties",
+                ),
+                PropertiesError::Io => {
+                    fail(ErrorCode::IoFailed, "Unable to write server.properties")
                 }
+            }),
+        }
+    }
+
+    /// Read the whitelist, ops or a ban list
+    async fn list_entries(
+        &self,
+        req: Request<ListEntriesRequest>,
+    ) -> Result<Response<ListEntriesResponce>, Status> {
+        let req = req.into_inner();
+        let mut responce = ListEntriesResponce::default();
+        require_token(req.token, AuthAction::ListEntries)?;
+        let list = player_list(req.list)?;
+        match lists::entries(list) {
+            Ok(entries) => {
+                responce.result = OpResult::Success.into();
+                responce.comment = format!("{} entries in {}", entries.len(), list.file());
+                responce.entries = entries
+                    .into_iter()
+                    .map(|entry| ListEntry {
+                        name: entry.name,
+                        uuid: entry.uuid,
+                        ip: entry.ip,
+                        level: entry.level,
+                        reason: entry.reason,
+                        created: entry.created,
+                        source: entry.source,
+                        expires: entry.expires,
+                    })
+                    .collect();
             }
-            Idle => Err(CommandError::Idle),
-            BackingUp => Err(CommandError::Downloading),
+            Err(list_error) => return Err(list_failure(list, list_error)),
         }
+        Ok(Response::new(responce))
     }
 
-    /// Spawn a new java procces and store it in MINECRAFT_SERVER_STATE
-    fn launch(&mut self) -> Result<(), LaunchError> {
-        self.check_stop();
-        match self {
-            Idle => {
-                let child = match Command::new("sh")
-                    .stdin(Stdio::piped())
-                    // .stdout(Stdio::piped())
-                    .arg("launch.sh")
-                    .spawn()
-                {
-                    Ok(child) => child,
-                    Err(_c) => return Err(LaunchError::Launch),
+    /// Whitelist, op or ban a player, or ban an ip
+    async fn add_entry(
+        &self,
+        req: Request<AddEntryRequest>,
+    ) -> Result<Response<OpResponce>, Status> {
+        let req = req.into_inner();
+        require_token(req.token, AuthAction::AddEntry)?;
+        let list = player_list(req.list)?;
+        edit_list(lists::Edit {
+            list,
+            add: true,
+            name: req.name,
+            reason: req.reason,
+        })
+        .await
+    }
+
+    /// Take a player or ip off a list
+    async fn remove_entry(
+        &self,
+        req: Request<RemoveEntryRequest>,
+    ) -> Result<Response<OpResponce>, Status> {
+        let req = req.into_inner();
+        require_token(req.token, AuthAction::RemoveEntry)?;
+        let list = player_list(req.list)?;
+        edit_list(lists::Edit {
+            list,
+            add: false,
+            name: req.name,
+            reason: String::new(),
+        })
+        .await
+    }
+
+    /// Who is online and recent play sessions
+    async fn players(
+        &self,
+        req: Request<PlayersRequest>,
+    ) -> Result<Response<PlayersResponce>, Status> {
+        require_token(req.into_inner().token, AuthAction::Players)?;
+        let roster = ROSTER.read();
+        let now = SystemTime::now();
+        Ok(Response::new(PlayersResponce {
+            result: OpResult::Success.into(),
+            comment: describe_online(roster.count()),
+            online: roster
+                .online()
+                .map(|(name, joined)| OnlinePlayer {
+                    name: name.to_string(),
+                    joined: unix_time(joined),
+                    online_for: now.duration_since(joined).unwrap_or_default().as_secs(),
+                })
+                .collect(),
+            sessions: roster
+                .sessions()
+                .map(|session| PlayerSession {
+                    name: session.player.clone(),
+                    joined: unix_time(session.joined),
+                    left: unix_time(session.left),
+                    duration: session
+                        .left
+                        .duration_since(session.joined)
+                        .unwrap_or_default()
+                        .as_secs(),
+                })
+                .collect(),
+        }))
+    }
+
+    /// Stream joins, leaves, deaths, chat and advancements as they happen
+    type PlayerEventsStream = PlayerEventStream;
+    async fn player_events(
+        &self,
+        req: Request<PlayerEventsRequest>,
+    ) -> Result<Response<Self::PlayerEventsStream>, Status> {
+        let (send_channel, receive_channel) = mpsc::channel(128);
+        require_token(req.into_inner().token, AuthAction::PlayerEvents)?;
+        let mut events = PLAYER_EVENTS.subscribe();
+        let forward = async move {
+            loop {
+                let event = match events.recv().await {
+                    Ok(event) => event,
+                    // Missed some events, carry on with the newest
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
                 };
-                *self = Running { procces: child };
-                Ok(())
+                if send_channel.send(Ok(player_event(event))).await.is_err() {
+                    // Client disconnected
+                    break;
+                }
             }
-            BackingUp => Err(LaunchError::Downloading),
-            Running { procces: _ } => Err(LaunchError::AlreadyRunning),
+        };
+        tokio::spawn(forward.in_current_span());
+        let output_stream = ReceiverStream::new(receive_channel);
+        Ok(Response::new(
+            Box::pin(output_stream) as Self::PlayerEventsStream
+        ))
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////
+// Players
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+type PlayerEventStream = Pin<Box<dyn Stream<Item = Result<PlayerEvent, Status>> + Send>>;
+
+fn player_event(event: players::Event) -> PlayerEvent {
+    let mut message = PlayerEvent {
+        result: OpResult::Success.into(),
+        time: unix_time(SystemTime::now()),
+        ..Default::default()
+    };
+    let kind = match event {
+        players::Event::Join { player } => {
+            message.comment = format!("{player} joined");
+            message.player = player;
+            PlayerEventKind::Join
+        }
+        players::Event::Leave { player, session } => {
+            message.comment = format!("{player} left after {}", describe_duration(session));
+            message.player = player;
+            message.session = session.as_secs();
+            PlayerEventKind::Leave
+        }
+        players::Event::Death {
+            player,
+            message: text,
+        } => {
+            message.comment = text.clone();
+            message.player = player;
+            message.message = text;
+            PlayerEventKind::Death
+        }
+        players::Event::Chat {
+            player,
+            message: text,
+        } => {
+            message.comment = format!("<{player}> {text}");
+            message.player = player;
+            message.message = text;
+            PlayerEventKind::Chat
+        }
+        players::Event::Advancement {
+            player,
+            advancement,
+        } => {
+            message.comment = format!("{player} got [{advancement}]");
+            message.player = player;
+            message.message = advancement;
+            PlayerEventKind::Advancement
+        }
+    };
+    message.kind = kind.into();
+    message
+}
+
+fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// "1h 5m", "12m", "40s"
+fn describe_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    match (seconds / 3600, seconds / 60 % 60) {
+        (0, 0) => format!("{seconds}s"),
+        (0, minutes) => format!("{minutes}m"),
+        (hours, minutes) => format!("{hours}h {minutes}m"),
+    }
+}
+
+/// "1 player online", "3 players online"
+fn describe_online(count: usize) -> String {
+    match count {
+        1 => "1 player online".to_string(),
+        count => format!("{count} players online"),
+    }
+}
+
+/// Apply online_stop_policy before stopping, failing with PlayersOnline when it refuses
+async fn wait_for_empty_server() -> Result<(), Status> {
+    let timeout = Duration::from_secs(config().online_stop_timeout);
+    let started = std::time::Instant::now();
+    loop {
+        let online = players_online();
+        if online == 0 {
+            return Ok(());
+        }
+        match config().online_stop_policy {
+            StopPolicy::Allow => return Ok(()),
+            StopPolicy::Deny => {
+                return Err(fail_with(
+                    ErrorCode::PlayersOnline,
+                    format!("{}, set force to stop anyway", describe_online(online)),
+                    [("online", online.to_string())],
+                ))
+            }
+            StopPolicy::Wait if started.elapsed() >= timeout => {
+                return Err(fail_with(
+                    ErrorCode::PlayersOnline,
+                    format!(
+                        "Still {} after waiting {}, set force to stop anyway",
+                        describe_online(online),
+                        describe_duration(timeout)
+                    ),
+                    [("online", online.to_string())],
+                ))
+            }
+            StopPolicy::Wait => tokio::time::sleep(Duration::from_secs(1)).await,
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////
+// Player lists
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[allow(clippy::result_large_err)]
+fn player_list(list: i32) -> Result<lists::List, Status> {
+    let list = PlayerList::from_i32(list)
+        .ok_or_else(|| fail(ErrorCode::InvalidRequest, "Invalid list"))?;
+    Ok(match list {
+        PlayerList::Whitelist => lists::List::Whitelist,
+        PlayerList::Ops => lists::List::Ops,
+        PlayerList::BannedPlayers => lists::List::BannedPlayers,
+        PlayerList::BannedIps => lists::List::BannedIps,
+    })
+}
+
+/// Apply an edit and reply with how it went
+async fn edit_list(edit: lists::Edit) -> Result<Response<OpResponce>, Status> {
+    let list = edit.list;
+    edit.validate()
+        .map_err(|list_error| list_failure(list, list_error))?;
+    // A stopped server's list files are edited directly, an add needs the player's uuid for
+    // that, so it's looked up before taking the lock
+    let new_entry = if edit.add && server_phase() == ServerPhase::Idle {
+        let (edit, lookup) = (edit.clone(), list_lookup());
+        let new_entry = blocking(move || edit.new_entry(&lookup))
+            .await?
+            .map_err(|list_error| list_failure(list, list_error))?;
+        Some(new_entry)
+    } else {
+        None
+    };
+    let res = STATE.write().edit_list(&edit, new_entry);
+    match res {
+        Ok(true) => respond(&format!("Ran \"{}\" on the server", edit.command())),
+        Ok(false) => respond(&format!("Updated {}", list.file())),
+        Err(list_edit_error) => Err(match list_edit_error {
+            ListEditError::Downloading => {
+                fail(ErrorCode::Busy, "Backup in progress! Can't change lists")
+            }
+            ListEditError::ProccesError => {
+                fail(ErrorCode::InternalError, "Error running command on procces")
+            }
+            ListEditError::Stopped => fail(
+                ErrorCode::Busy,
+                "The server stopped while the change was made, try again",
+            ),
+            ListEditError::List(list_error) => list_failure(list, list_error),
+        }),
+    }
+}
+
+/// How to find player uuids for a stopped server, going by its server.properties
+fn list_lookup() -> lists::Lookup {
+    let loaded = properties::Properties::load().ok();
+    let property = |key| loaded.as_ref().and_then(|p| p.get(key));
+    lists::Lookup {
+        api: config().profile_api.clone(),
+        online_mode: property("online-mode") != Some("false"),
+        op_level: property("op-permission-level")
+            .and_then(|level| level.parse().ok())
+            .unwrap_or(4),
+    }
+}
+
+fn list_failure(list: lists::List, list_error: lists::ListError) -> Status {
+    match list_error {
+        lists::ListError::InvalidName => {
+            fail(ErrorCode::InvalidRequest, "Not a valid player name or ip")
+        }
+        lists::ListError::UnknownPlayer => {
+            fail(ErrorCode::NotFound, "No minecraft account with that name")
+        }
+        lists::ListError::AlreadyListed => fail(
+            ErrorCode::InvalidRequest,
+            format!("Already in {}", list.file()),
+        ),
+        lists::ListError::NotListed => fail(ErrorCode::NotFound, format!("Not in {}", list.file())),
+        lists::ListError::Corrupt => {
+            fail(ErrorCode::IoFailed, format!("{} is corrupt", list.file()))
+        }
+        lists::ListError::Io => fail(
+            ErrorCode::IoFailed,
+            format!("Unable to write {}", list.file()),
+        ),
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////
+// server.properties
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+/// Shorthand for Ok(Response::new(PropertiesResponce{..})), listing `loaded` with which keys
+/// `user` may change
+#[allow(clippy::result_large_err)]
+fn respond_properties(
+    comment: String,
+    loaded: &properties::Properties,
+    user: Option<User>,
+    restart_required: bool,
+) -> Result<Response<PropertiesResponce>, Status> {
+    info!(comment, "Replying");
+    let properties = loaded
+        .entries()
+        .map(|(key, value)| Property {
+            key: key.to_string(),
+            value: value.to_string(),
+            kind: properties::kind(key).name().to_string(),
+            editable: user.as_ref().is_some_and(|user| {
+                user.may(AuthAction::SetProperties) && user.may_edit_property(key)
+            }),
+        })
+        .collect();
+    Ok(Response::new(PropertiesResponce {
+        result: OpResult::Success.into(),
+        comment,
+        properties,
+        restart_required,
+    }))
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////
+// Mod management
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+/// Describe this server to modrinth, fails with NotConfigured if it isn't configured
+#[allow(clippy::result_large_err)]
+fn mod_instance() -> Result<modrinth::Instance, Status> {
+    let loader = match &config().loader {
+        Some(loader) => loader.to_lowercase(),
+        None => {
+            return Err(fail_with(
+                ErrorCode::NotConfigured,
+                "Set loader in mcsc_server.toml to manage mods",
+                [("setting", "loader".to_string())],
+            ))
+        }
+    };
+    let game_version = match config()
+        .game_version
+        .clone()
+        .or_else(|| versions::installed(&config().server_jar))
+    {
+        Some(version) => version,
+        None => {
+            return Err(fail_with(
+                ErrorCode::NotConfigured,
+                "Minecraft version unknown, set game_version in mcsc_server.toml",
+                [("setting", "game_version".to_string())],
+            ))
+        }
+    };
+    Ok(modrinth::Instance {
+        api: config().modrinth_api.trim_end_matches('/').to_string(),
+        directory: config()
+            .mods_directory
+            .clone()
+            .unwrap_or_else(|| modrinth::default_directory(&loader).to_string()),
+        loader,
+        game_version,
+    })
+}
+
+/// Shorthand for Ok(Response::new(ModsResponce{..}))
+#[allow(clippy::result_large_err)]
+fn respond_mods(
+    comment: String,
+    installed: Option<Vec<modrinth::LockedMod>>,
+) -> Result<Response<ModsResponce>, Status> {
+    info!(comment, "Replying");
+    Ok(Response::new(ModsResponce {
+        result: OpResult::Success.into(),
+        comment,
+        mods: installed
+            .unwrap_or_default()
+            .into_iter()
+            .map(|locked| Mod {
+                project_id: locked.project_id,
+                slug: locked.slug,
+                title: locked.title,
+                description: String::new(),
+                version: locked.version_number,
+                filename: locked.filename,
+            })
+            .collect(),
+        loader: config().loader.clone().unwrap_or_default(),
+        game_version: config()
+            .game_version
+            .clone()
+            .or_else(|| versions::installed(&config().server_jar))
+            .unwrap_or_default(),
+    }))
+}
+
+fn mod_failure(mod_error: modrinth::ModError) -> Status {
+    let modrinth = || [("service", "modrinth".to_string())];
+    match mod_error {
+        modrinth::ModError::Api(reason) => fail_with(
+            ErrorCode::UpstreamFailed,
+            format!("Modrinth request failed: {reason}"),
+            modrinth(),
+        ),
+        modrinth::ModError::InvalidProject => fail(
+            ErrorCode::InvalidRequest,
+            "Name projects by their slug or id, eg. sodium",
+        ),
+        modrinth::ModError::NotFound => fail(ErrorCode::NotFound, "No such project on modrinth"),
+        modrinth::ModError::NoCompatibleVersion => fail(
+            ErrorCode::NotFound,
+            "No version compatible with this server's loader and minecraft version",
+        ),
+        modrinth::ModError::Checksum(file) => fail_with(
+            ErrorCode::UpstreamFailed,
+            format!("{file} failed sha1 verification and wasn't installed"),
+            modrinth(),
+        ),
+        modrinth::ModError::NotInstalled => fail(ErrorCode::NotFound, "That mod isn't installed"),
+        modrinth::ModError::Lockfile => fail(ErrorCode::IoFailed, "mcsc-mods.lock is corrupt"),
+        modrinth::ModError::Io => {
+            fail(ErrorCode::IoFailed, "Unable to write to the mods directory")
+        }
+    }
+}
+
+/// "a 1.0, b 2.1"
+fn describe_mods(mods: &[modrinth::LockedMod]) -> String {
+    mods.iter()
+        .map(|locked| format!("{} {}", locked.title, locked.version_number))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// Mods are only loaded on startup
+fn restart_note() -> &'static str {
+    match *STATE.read() {
+        Starting { procces: _ } | Running { procces: _ } | Adopted { pid: _ } => {
+            ", restart the server to apply"
+        }
+        _ => "",
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////
+// Server state management using the state machine pattern
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug)]
+enum ServerState {
+    Idle,
+    /// Launched but not accepting players yet
+    Starting {
+        procces: Child,
+    },
+    /// Answering pings on the game port
+    Running {
+        procces: Child,
+    },
+    /// Running, but not launched by this mcsc-server so there's no procces handle. Found through
+    /// the pid file or rcon, commands go through rcon or the console pipe
+    Adopted {
+        pid: Option<u32>,
+    },
+    BackingUp,
+}
+
+impl ServerState {
+    /// Mark the server as backing up, so nothing launches it or touches the world until
+    /// finish_backup
+    fn start_backup(&mut self) -> Result<(), BackupError> {
+        self.check_stop();
+        match self {
+            Idle => {
+                *self = BackingUp;
+                Ok(())
+            }
+            Starting { procces: _ } | Running { procces: _ } | Adopted { pid: _ } => {
+                Err(BackupError::ServerRunning)
+            }
+            BackingUp => Err(BackupError::OtherBackup),
+        }
+    }
+
+    fn finish_backup(&mut self) {
+        if let BackingUp = self {
+            *self = Idle;
+        }
+    }
+
+    fn check_stop(&mut self) {
+        if let Starting { procces: c } | Running { procces: c } = self {
+            let res = c.try_wait();
+            if let Ok(Some(_exit_code)) = res {
+                //Procces finished
+                forget_pid();
+                *self = Idle;
+            }
+        }
+        if let Adopted { pid: Some(pid) } = self {
+            if !server_alive(*pid) {
+                forget_pid();
+                *self = Idle;
+            }
+        }
+    }
+
+    /// Run a console command, returning its output when it went through rcon
+    fn run_command(&mut self, cmd: &str) -> Result<String, CommandError> {
+        match self {
+            Starting { procces } | Running { procces } => {
+                if config().rcon {
+                    // Rcon only comes up once the server has started, use the console until then
+                    match rcon_command(cmd) {
+                        Err(rcon::RconError::Connect(_) | rcon::RconError::Disabled) => {}
+                        res => return res.map_err(CommandError::Rcon),
+                    }
+                }
+                if write_console(procces.stdin.as_mut(), &format!("\n{}\n", cmd)) {
+                    Ok(String::new())
+                } else {
+                    Err(CommandError::ProccesError)
+                }
+            }
+            Adopted { pid: _ } => {
+                if config().rcon {
+                    rcon_command(cmd).map_err(CommandError::Rcon)
+                } else if config().console_pipe && write_console(None, &format!("\n{}\n", cmd)) {
+                    Ok(String::new())
+                } else {
+                    Err(CommandError::NoConsole)
+                }
+            }
+            Idle => Err(CommandError::Idle),
+            BackingUp => Err(CommandError::Downloading),
+        }
+    }
+
+    /// Spawn a new java procces and store it in MINECRAFT_SERVER_STATE
+    fn launch(&mut self) -> Result<(), LaunchError> {
+        self.check_stop();
+        match self {
+            Idle => {
+                preflight_check()?;
+                let mut command = Command::new("sh");
+                // Point launch.sh at the pinned java runtime through JAVA_HOME, PATH and MCSC_JAVA
+                if let Some(major) = config().java_version {
+                    let runtime = match java::find(&config().java_directory, major) {
+                        Some(runtime) => runtime,
+                        None => return Err(LaunchError::JavaMissing(major)),
+                    };
+                    command
+                        .env("JAVA_HOME", &runtime.home)
+                        .env("PATH", runtime.path_var())
+                        .env("MCSC_JAVA", runtime.executable());
+                }
+                let stdin = match config().console_pipe.then(open_console_pipe) {
+                    Some(Ok(pipe)) => Stdio::from(pipe),
+                    Some(Err(error)) => {
+                        warn!("Couldn't open the console pipe, using stdin: {error}");
+                        Stdio::piped()
+                    }
+                    None => Stdio::piped(),
+                };
+                let mut child = match command
+                    .stdin(stdin)
+                    .stdout(Stdio::piped())
+                    // Its own process group, so it outlives mcsc-server and a SIGTERM reaches java
+                    // as well as launch.sh
+                    .process_group(0)
+                    .arg("launch.sh")
+                    .spawn()
+                {
+                    Ok(child) => child,
+                    Err(_c) => return Err(LaunchError::Launch),
+                };
+                if let Err(error) = std::fs::write(PID_FILE, child.id().to_string()) {
+                    warn!("Couldn't write {PID_FILE}, the server will be lost if mcsc-server restarts: {error}");
+                }
+                if let Some(stdout) = child.stdout.take() {
+                    watch_console(stdout);
+                }
+                *self = Starting { procces: child };
+                Ok(())
+            }
+            BackingUp => Err(LaunchError::Downloading),
+            Starting { procces: _ } | Running { procces: _ } | Adopted { pid: _ } => {
+                Err(LaunchError::AlreadyRunning)
+            }
+        }
+    }
+
+    /// Rewrite server.properties, minecraft only reads it on startup so changes made while it's
+    /// running are flagged as needing a restart
+    fn set_properties(
+        &mut self,
+        changes: &BTreeMap<String, String>,
+    ) -> Result<(properties::Properties, bool), PropertiesError> {
+        self.check_stop();
+        let restart_required = match self {
+            Idle => false,
+            Starting { procces: _ } | Running { procces: _ } | Adopted { pid: _ } => true,
+            BackingUp => return Err(PropertiesError::Downloading),
+        };
+        let mut loaded = properties::Properties::load().map_err(|_| PropertiesError::Io)?;
+        loaded.set(changes);
+        loaded.save().map_err(|_| PropertiesError::Io)?;
+        Ok((loaded, restart_required))
+    }
+
+    /// Run the edit as a command on a running server or edit the list file of a stopped one,
+    /// which takes the new entry of an add. Returns whether it was run as a command
+    fn edit_list(
+        &mut self,
+        edit: &lists::Edit,
+        new_entry: Option<serde_json::Value>,
+    ) -> Result<bool, ListEditError> {
+        self.check_stop();
+        edit.validate().map_err(ListEditError::List)?;
+        match self {
+            Starting { procces: _ } | Running { procces: _ } | Adopted { pid: _ } => {
+                match self.run_command(&edit.command()) {
+                    Ok(_) => Ok(true),
+                    Err(_) => Err(ListEditError::ProccesError),
+                }
+            }
+            // Stopped after the caller checked, so the entry wasn't looked up
+            Idle if edit.add && new_entry.is_none() => Err(ListEditError::Stopped),
+            Idle => {
+                edit.apply(new_entry).map_err(ListEditError::List)?;
+                Ok(false)
+            }
+            BackingUp => Err(ListEditError::Downloading),
+        }
+    }
+
+    /// The server answered a ping, so it's accepting players. Returns whether it was starting
+    fn mark_ready(&mut self) -> bool {
+        match std::mem::replace(self, Idle) {
+            Starting { procces } => {
+                *self = Running { procces };
+                true
+            }
+            other => {
+                *self = other;
+                false
+            }
+        }
+    }
+
+    /// Stop the running procces by entering stop into the stdin. An adopted server is left for
+    /// stop_server to stop, since waiting for it to go can take a while
+    fn stop(&mut self) -> Result<Stopping, StopError> {
+        self.check_stop();
+        match self {
+            Starting { procces: child } | Running { procces: child } => {
+                if !write_console(child.stdin.as_mut(), "\nstop\n") {
+                    return Err(StopError::ProccesError);
+                }
+                let _ = child.wait();
+                forget_pid();
+                *self = Idle;
+                Ok(Stopping::Stopped)
+            }
+            Adopted { pid } => Ok(Stopping::Adopted(*pid)),
+            BackingUp => Err(StopError::Downloading),
+            Idle => Err(StopError::Idle),
+        }
+    }
+}
+
+/// What's left to do after ServerState::stop
+enum Stopping {
+    Stopped,
+    /// A reattached server, with its pid if it's known, still has to be asked to stop
+    Adopted(Option<u32>),
+}
+
+/// Stop the server. An adopted server is waited for without holding STATE, which can take up to
+/// ADOPTED_STOP_TIMEOUT, so call this off the runtime
+fn stop_server() -> Result<(), StopError> {
+    let res = STATE.write().stop()?;
+    let Stopping::Adopted(pid) = res else {
+        return Ok(());
+    };
+    let stopped = stop_adopted(pid);
+    let mut state = STATE.write();
+    state.check_stop();
+    // Whoever else changed the state meanwhile already saw it go
+    if let Adopted { .. } = *state {
+        if !stopped {
+            return Err(StopError::ProccesError);
+        }
+        forget_pid();
+        *state = Idle;
+    }
+    Ok(())
+}
+
+/// Ask a reattached server to stop through rcon or the console pipe and wait for it to go,
+/// false if it's still running
+fn stop_adopted(pid: Option<u32>) -> bool {
+    let asked = (config().rcon
+        && matches!(
+            rcon_command("stop"),
+            // The server may hang up before replying
+            Ok(_) | Err(rcon::RconError::Io(_))
+        ))
+        || (config().console_pipe && write_console(None, "\nstop\n"));
+    match pid {
+        // Minecraft also saves and stops on SIGTERM
+        Some(pid) => (asked && wait_for_exit(pid)) || (terminate(pid) && wait_for_exit(pid)),
+        None => asked && wait_for_rcon_to_close(),
+    }
+}
+
+#[derive(Debug)]
+enum StopError {
+    Idle,
+    Downloading,
+    ProccesError,
+}
+
+#[derive(Debug)]
+enum LaunchError {
+    Launch,
+    AlreadyRunning,
+    Downloading,
+    /// The java major version pinned in the config isn't installed
+    JavaMissing(u32),
+    /// Another procces, with this pid, has the world open
+    WorldLocked(u32),
+    WorldCorrupt(String),
+    LowDiskSpace {
+        free_mb: u64,
+        required_mb: u64,
+    },
+    EulaNotAccepted,
+}
+
+fn launch_error_comment(launch_error: LaunchError) -> String {
+    match launch_error {
+        LaunchError::Launch => "Failed to launch server".to_string(),
+        LaunchError::AlreadyRunning => "Server already running".to_string(),
+        LaunchError::Downloading => "Download in progress! Can't launch".to_string(),
+        LaunchError::JavaMissing(major) => {
+            format!("Java {major} runtime not found, register one with RegisterJava")
+        }
+        LaunchError::WorldLocked(pid) => {
+            format!("World is in use by another procces (pid {pid}), is another server running?")
+        }
+        LaunchError::WorldCorrupt(problem) => format!("World can't be loaded: {problem}"),
+        LaunchError::LowDiskSpace {
+            free_mb,
+            required_mb,
+        } => format!("Only {free_mb} MB of disk space free, min_free_disk_mb is {required_mb}"),
+        LaunchError::EulaNotAccepted => {
+            "The minecraft EULA hasn't been accepted, an admin can accept it with AcceptEula"
+                .to_string()
+        }
+    }
+}
+
+/// Fail with the ErrorCode for a launch error, `context` goes before the comment
+fn launch_failure(launch_error: LaunchError, context: &str) -> Status {
+    let (code, details) = match &launch_error {
+        LaunchError::Launch => (
+            ErrorCode::LaunchFailed,
+            vec![("reason", "spawn".to_string())],
+        ),
+        LaunchError::AlreadyRunning => (ErrorCode::ServerRunning, vec![]),
+        LaunchError::Downloading => (ErrorCode::Busy, vec![]),
+        LaunchError::JavaMissing(major) => (
+            ErrorCode::LaunchFailed,
+            vec![
+                ("reason", "java_missing".to_string()),
+                ("java_version", major.to_string()),
+            ],
+        ),
+        LaunchError::WorldLocked(pid) => (
+            ErrorCode::LaunchFailed,
+            vec![
+                ("reason", "world_locked".to_string()),
+                ("pid", pid.to_string()),
+            ],
+        ),
+        LaunchError::WorldCorrupt(_) => (
+            ErrorCode::LaunchFailed,
+            vec![("reason", "world_corrupt".to_string())],
+        ),
+        LaunchError::LowDiskSpace {
+            free_mb,
+            required_mb,
+        } => (
+            ErrorCode::LaunchFailed,
+            vec![
+                ("reason", "low_disk_space".to_string()),
+                ("free_mb", free_mb.to_string()),
+                ("required_mb", required_mb.to_string()),
+            ],
+        ),
+        LaunchError::EulaNotAccepted => (ErrorCode::EulaNotAccepted, vec![]),
+    };
+    fail_with(
+        code,
+        format!("{context}{}", launch_error_comment(launch_error)),
+        details,
+    )
+}
+
+/// The world folder, level-name in server.properties
+fn world_name() -> String {
+    properties::Properties::load()
+        .ok()
+        .and_then(|loaded| loaded.get("level-name").map(str::to_string))
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "world".to_string())
+}
+
+/// Back up the world. STATE is only held to mark the server as backing up and idle again, the
+/// compression happens without it, so call this off the runtime
+fn backup_world() -> Result<(), BackupError> {
+    STATE.write().start_backup()?;
+    let archived = archive_world();
+    STATE.write().finish_backup();
+    archived
+}
+
+/// Compress the world into the backup directory and keep the newest 10 backups
+fn archive_world() -> Result<(), BackupError> {
+    let started = std::time::Instant::now();
+    let archive = format!(
+        "{}/{}.tar.gz",
+        &config().backup_directory,
+        common::ran_letters(32)
+    );
+    let status = Command::new("tar")
+        .arg("-czf")
+        .arg(&archive)
+        .arg(world_name())
+        .status();
+    if !status.is_ok_and(|status| status.success()) {
+        // Whatever tar got through isn't a backup
+        let _ = std::fs::remove_file(&archive);
+        return Err(BackupError::Compression);
+    }
+    {
+        let mut stats = STATS.write();
+        stats.backups += 1;
+        stats.backup_seconds += started.elapsed().as_secs_f64();
+        stats.last_backup_bytes = std::fs::metadata(&archive).map_or(0, |m| m.len());
+    }
+    let mut num_backups = std::fs::read_dir(&config().backup_directory)
+        .into_iter()
+        .flatten()
+        .count();
+    while num_backups > 10 {
+        num_backups -= 1;
+        remove_oldest_backup(&config().backup_directory);
+    }
+    Ok(())
+}
+
+/// Back up the world, then switch to a downloaded server jar. Like backup_world, call this off
+/// the runtime
+fn install_version(downloaded: versions::Downloaded) -> Result<versions::Installed, InstallError> {
+    STATE
+        .write()
+        .start_backup()
+        .map_err(|backup_error| match backup_error {
+            BackupError::ServerRunning => InstallError::ServerRunning,
+            _ => InstallError::Downloading,
+        })?;
+    let installed = (|| {
+        if Path::new(&world_name()).exists() {
+            archive_world().map_err(InstallError::Backup)?;
+        }
+        versions::swap(downloaded).map_err(InstallError::Version)
+    })();
+    STATE.write().finish_backup();
+    installed
+}
+
+/// Make sure the world and the machine are fit to launch the server on
+fn preflight_check() -> Result<(), LaunchError> {
+    preflight::check(&world_name(), config().min_free_disk_mb).map_err(|problem| match problem {
+        preflight::Problem::WorldLocked(pid) => LaunchError::WorldLocked(pid),
+        preflight::Problem::WorldCorrupt(problem) => LaunchError::WorldCorrupt(problem),
+        preflight::Problem::LowDiskSpace {
+            free_mb,
+            required_mb,
+        } => LaunchError::LowDiskSpace {
+            free_mb,
+            required_mb,
+        },
+        preflight::Problem::EulaNotAccepted => LaunchError::EulaNotAccepted,
+    })
+}
+
+#[derive(Debug)]
+enum BackupError {
+    ServerRunning,
+    OtherBackup,
+    Compression,
+}
+
+fn backup_failure(backup_error: BackupError) -> Status {
+    match backup_error {
+        BackupError::OtherBackup => fail(ErrorCode::Busy, "Another backup is in progress"),
+        BackupError::ServerRunning => fail(
+            ErrorCode::ServerRunning,
+            "Back up failed, server still running",
+        ),
+        BackupError::Compression => fail(
+            ErrorCode::IoFailed,
+            "Back up failed to compress the world folder",
+        ),
+    }
+}
+
+#[derive(Debug)]
+enum InstallError {
+    ServerRunning,
+    Downloading,
+    Backup(BackupError),
+    Version(versions::VersionError),
+}
+
+fn install_failure(install_error: InstallError) -> Status {
+    match install_error {
+        InstallError::ServerRunning => fail(
+            ErrorCode::ServerRunning,
+            "Server running, stop it before upgrading",
+        ),
+        InstallError::Downloading => fail(ErrorCode::Busy, "Download in progress! Can't upgrade"),
+        InstallError::Backup(backup_error) => match backup_error {
+            BackupError::Compression => fail(
+                ErrorCode::IoFailed,
+                "Pre-upgrade backup failed to compress the world folder, version not changed",
+            ),
+            _ => fail(
+                ErrorCode::Busy,
+                "Pre-upgrade backup failed, version not changed",
+            ),
+        },
+        InstallError::Version(version_error) => match version_error {
+            versions::VersionError::Fetch(reason) => fail(
+                ErrorCode::UpstreamFailed,
+                format!("Unable to download version data: {reason}"),
+            ),
+            versions::VersionError::Parse => {
+                fail(ErrorCode::UpstreamFailed, "Invalid version manifest")
+            }
+            versions::VersionError::UnknownVersion => {
+                fail(ErrorCode::NotFound, "No such version in the manifest")
+            }
+            versions::VersionError::NoServerJar => {
+                fail(ErrorCode::NotFound, "That version has no server jar")
+            }
+            versions::VersionError::Checksum => fail(
+                ErrorCode::UpstreamFailed,
+                "Downloaded jar failed sha1 verification, version not changed",
+            ),
+            versions::VersionError::Io => {
+                fail(ErrorCode::IoFailed, "Unable to write the server jar")
+            }
+        },
+    }
+}
+
+#[derive(Debug)]
+enum PropertiesError {
+    Downloading,
+    Io,
+}
+
+#[derive(Debug)]
+enum ListEditError {
+    Downloading,
+    ProccesError,
+    /// The server stopped while the edit was being prepared for a running one
+    Stopped,
+    List(lists::ListError),
+}
+
+#[derive(Debug)]
+enum CommandError {
+    Idle,
+    Downloading,
+    ProccesError,
+    Rcon(rcon::RconError),
+    /// Reattached to a server without rcon or a console pipe
+    NoConsole,
+}
+
+lazy_static! {
+    /// Exits if the config is unusable, so load it before doing anything else. Replaced as a whole
+    /// when the config is reloaded, read it through config()
+    static ref CONFIG: RwLock<std::sync::Arc<crate::Config>> =
+        RwLock::new(std::sync::Arc::new(crate::config_load()));
+    /// Contains the current procces of the minecraft server and it's stdin
+    static ref STATE: RwLock<ServerState> = RwLock::new(Idle);
+    /// Who is on the minecraft server, followed from its console output
+    static ref ROSTER: RwLock<players::Roster> = RwLock::new(players::Roster::default());
+    /// Feeds PlayerEvents streams
+    static ref PLAYER_EVENTS: broadcast::Sender<players::Event> = broadcast::channel(256).0;
+    /// What the last ping of the game port found
+    static ref HEALTH: RwLock<Health> = RwLock::new(Health::default());
+    /// Tick rate reported by the console since the last metrics sample
+    static ref TICKS: RwLock<metrics::TickWatch> = RwLock::new(metrics::TickWatch::default());
+    /// The last metrics_history samples, oldest first
+    static ref METRICS: RwLock<VecDeque<(ServerPhase, metrics::Sample)>> =
+        RwLock::new(VecDeque::new());
+    /// The last CONSOLE_HISTORY_LINES lines the server printed, oldest first
+    static ref CONSOLE_HISTORY: RwLock<VecDeque<(SystemTime, String)>> =
+        RwLock::new(VecDeque::new());
+    /// Feeds Console streams
+    static ref CONSOLE_LINES: broadcast::Sender<(SystemTime, String)> = broadcast::channel(256).0;
+    /// Feeds WatchMetrics streams
+    static ref METRIC_SAMPLES: broadcast::Sender<(ServerPhase, metrics::Sample)> =
+        broadcast::channel(16).0;
+}
+
+/// How many console lines Console can look back on
+const CONSOLE_HISTORY_LINES: usize = 1000;
+
+type ConsoleLineStream = Pin<Box<dyn Stream<Item = Result<ConsoleLine, Status>> + Send>>;
+
+fn console_line((time, line): (SystemTime, String)) -> ConsoleLine {
+    ConsoleLine {
+        result: OpResult::Success.into(),
+        comment: String::new(),
+        line,
+        time: unix_time(time),
+    }
+}
+
+/// Echo the minecraft console to our stdout and follow what players do
+fn watch_console(stdout: ChildStdout) {
+    std::thread::spawn(move || {
+        let mut reader = BufReader::new(stdout);
+        let mut buffer = Vec::new();
+        while let Ok(read) = reader.read_until(b'\n', &mut buffer) {
+            if read == 0 {
+                break;
+            }
+            let line = String::from_utf8_lossy(&buffer).trim_end().to_string();
+            buffer.clear();
+            info!(target: "minecraft", "{line}");
+            {
+                let mut history = CONSOLE_HISTORY.write();
+                history.push_back((SystemTime::now(), line.clone()));
+                if history.len() > CONSOLE_HISTORY_LINES {
+                    history.pop_front();
+                }
+            }
+            let _ = CONSOLE_LINES.send((SystemTime::now(), line.clone()));
+            TICKS.write().observe(&line);
+            if let Some(event) = ROSTER.write().observe(&line) {
+                let _ = PLAYER_EVENTS.send(event);
+            }
+        }
+        // The server is gone, and everyone with it
+        for event in ROSTER.write().clear() {
+            let _ = PLAYER_EVENTS.send(event);
+        }
+    });
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////
+// Sleeping
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+/// How often to check whether anyone is online for idle shutdowns
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Whether the minecraft process is running, noticing if it exited on its own
+fn server_running() -> bool {
+    matches!(server_phase(), ServerPhase::Starting | ServerPhase::Ready)
+}
+
+/// Stop the server once nobody has been online for idle_shutdown_minutes
+fn watch_idle() {
+    std::thread::spawn(move || {
+        let mut empty_since = None;
+        loop {
+            std::thread::sleep(IDLE_CHECK_INTERVAL);
+            let limit = match config().idle_shutdown_minutes {
+                Some(minutes) => Duration::from_secs(minutes * 60),
+                None => {
+                    empty_since = None;
+                    continue;
+                }
+            };
+            if server_phase() != ServerPhase::Ready || players_online() > 0 {
+                empty_since = None;
+                continue;
+            }
+            let since = *empty_since.get_or_insert_with(std::time::Instant::now);
+            if since.elapsed() < limit {
+                continue;
+            }
+            empty_since = None;
+            info!("Nobody online for {}, stopping", describe_duration(limit));
+            if let Err(stop_error) = stop_server() {
+                error!("Idle shutdown failed: {stop_error:?}");
+                continue;
+            }
+            if config().idle_backup {
+                match backup_world() {
+                    Ok(_) => info!("Backed up after idle shutdown"),
+                    Err(backup_error) => {
+                        error!("Backup after idle shutdown failed: {backup_error:?}")
+                    }
+                }
+            }
+        }
+    });
+}
+
+/// An address minecraft listens on, from server.properties. `any` stands in for an unset
+/// server-ip, which means every interface
+fn property_address(port_key: &str, default_port: &str, any: &str) -> String {
+    let properties = properties::Properties::load().ok();
+    let get = |key: &str| {
+        properties
+            .as_ref()
+            .and_then(|properties| properties.get(key))
+            .filter(|value| !value.is_empty())
+            .map(str::to_string)
+    };
+    format!(
+        "{}:{}",
+        get("server-ip").unwrap_or_else(|| any.to_string()),
+        get(port_key).unwrap_or_else(|| default_port.to_string())
+    )
+}
+
+/// While the server is stopped hold the game port, answering pings with sleeping_motd and
+/// launching when someone tries to join. The port is let go as soon as the server runs, minecraft
+/// takes a few seconds to start so it's free by the time it binds
+fn listen_while_sleeping() {
+    std::thread::spawn(|| loop {
+        if server_running() || !config().wake_on_connect {
+            std::thread::sleep(Duration::from_secs(1));
+            continue;
+        }
+        let address = property_address("server-port", "25565", "0.0.0.0");
+        let listener = match std::net::TcpListener::bind(&address) {
+            Ok(listener) => listener,
+            Err(error) => {
+                warn!("Couldn't listen on {address} while sleeping: {error}");
+                std::thread::sleep(Duration::from_secs(10));
+                continue;
+            }
+        };
+        if listener.set_nonblocking(true).is_err() {
+            continue;
+        }
+        info!("Sleeping, listening on {address}");
+        while !server_running() && config().wake_on_connect {
+            let stream = match listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(_) => {
+                    std::thread::sleep(Duration::from_millis(250));
+                    continue;
+                }
+            };
+            // Launched since the last check, don't answer the health check for minecraft
+            if server_running() {
+                break;
+            }
+            let kick = "Server is starting, try again in a minute";
+            if let Ok(protocol::Intent::Login(player)) =
+                protocol::serve_sleeping(stream, &config().sleeping_motd, kick)
+            {
+                info!(player, "Tried to join, waking up");
+                match STATE.write().launch() {
+                    Ok(_) => break,
+                    Err(launch_error) => {
+                        error!("Wake up failed: {}", launch_error_comment(launch_error))
+                    }
+                }
+            }
+        }
+        // Dropping the listener frees the port for minecraft
+    });
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////
+// Health checks
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+/// How often to ping the game port while the server starts, and once it's up
+const STARTING_PING_INTERVAL: Duration = Duration::from_secs(2);
+const READY_PING_INTERVAL: Duration = Duration::from_secs(15);
+const PING_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// What the last ping of the game port found
+#[derive(Debug, Clone, Default)]
+struct Health {
+    /// Server list ping, None when it wasn't answered
+    status: Option<protocol::Status>,
+    /// Query protocol, when enabled with health_query
+    query: Option<query::QueryStatus>,
+    checked: Option<SystemTime>,
+}
+
+/// Where the server is in its lifecycle, noticing if it exited on its own
+fn server_phase() -> ServerPhase {
+    let mut state = STATE.write();
+    state.check_stop();
+    match *state {
+        Idle => ServerPhase::Idle,
+        Starting { procces: _ } => ServerPhase::Starting,
+        Running { procces: _ } | Adopted { pid: _ } => ServerPhase::Ready,
+        BackingUp => ServerPhase::BackingUp,
+    }
+}
+
+/// Players online going by the console, or the last ping for adopted servers whose console we
+/// can't see
+fn players_online() -> usize {
+    let pinged = HEALTH
+        .read()
+        .status
+        .as_ref()
+        .map_or(0, |status| status.online as usize);
+    ROSTER.read().count().max(pinged)
+}
+
+/// Ping the game port while the server runs, it counts as ready once it answers
+fn watch_health() {
+    std::thread::spawn(|| loop {
+        let interval = match server_phase() {
+            ServerPhase::Starting => STARTING_PING_INTERVAL,
+            ServerPhase::Ready => READY_PING_INTERVAL,
+            _ => {
+                *HEALTH.write() = Health::default();
+                std::thread::sleep(STARTING_PING_INTERVAL);
+                continue;
+            }
+        };
+        let status = protocol::ping(
+            &property_address("server-port", "25565", "127.0.0.1"),
+            PING_TIMEOUT,
+        )
+        .ok();
+        let query = match status {
+            Some(_) if config().health_query => query::query(
+                &property_address("query.port", "25565", "127.0.0.1"),
+                PING_TIMEOUT,
+            )
+            .ok(),
+            _ => None,
+        };
+        if let Some(status) = &status {
+            if STATE.write().mark_ready() {
+                info!(
+                    "Server ready, {} with {}/{} players",
+                    status.version, status.online, status.max
+                );
+            }
+        } else if matches!(*STATE.read(), Adopted { pid: None }) {
+            // There's no procces to notice an adopted server exiting, but rcon refusing
+            // connections means it's gone
+            if let Err(rcon::RconError::Connect(_)) = rcon_command("list") {
+                let mut state = STATE.write();
+                if let Adopted { pid: None } = *state {
+                    info!("Adopted server stopped");
+                    *state = Idle;
+                }
+            }
+        }
+        *HEALTH.write() = Health {
+            status,
+            query,
+            checked: Some(SystemTime::now()),
+        };
+        std::thread::sleep(interval);
+    });
+}
+
+/// Wait for the health checks to find the server accepting players, the comment says how it went
+async fn wait_until_ready() -> Result<String, Status> {
+    let timeout = Duration::from_secs(config().ready_timeout);
+    let started = std::time::Instant::now();
+    loop {
+        match server_phase() {
+            ServerPhase::Ready => {
+                let took = describe_duration(started.elapsed());
+                return Ok(match &HEALTH.read().status {
+                    Some(status) => format!(
+                        "Ready after {took}, {} with {}/{} players",
+                        status.version, status.online, status.max
+                    ),
+                    None => format!("Ready after {took}"),
+                });
+            }
+            ServerPhase::Starting if started.elapsed() < timeout => {
+                tokio::time::sleep(Duration::from_millis(500)).await
+            }
+            ServerPhase::Starting => {
+                return Err(fail_with(
+                    ErrorCode::TimedOut,
+                    format!(
+                        "Launched, but still not accepting players after {}",
+                        describe_duration(timeout)
+                    ),
+                    [("seconds", timeout.as_secs().to_string())],
+                ))
+            }
+            _ => {
+                return Err(fail_with(
+                    ErrorCode::LaunchFailed,
+                    "Server exited while starting",
+                    [("reason", "exited".to_string())],
+                ))
+            }
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////
+// Metrics
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+type MetricsSampleStream = Pin<Box<dyn Stream<Item = Result<MetricsSample, Status>> + Send>>;
+
+fn metrics_sample(phase: ServerPhase, sample: &metrics::Sample) -> MetricsSample {
+    MetricsSample {
+        result: OpResult::Success.into(),
+        comment: String::new(),
+        time: unix_time(sample.time),
+        phase: phase.into(),
+        cpu_percent: sample.cpu_percent,
+        memory_bytes: sample.memory_bytes,
+        world_bytes: sample.world_bytes,
+        backup_bytes: sample.backup_bytes,
+        tps: sample.tps.unwrap_or_default(),
+        mspt: sample.mspt.unwrap_or_default(),
+    }
+}
+
+/// Sample the server's cpu, memory, disk use and tick rate every metrics_interval seconds
+fn watch_metrics() {
+    std::thread::spawn(|| {
+        let mut sampler = metrics::Sampler::new();
+        loop {
+            let interval = config().metrics_interval;
+            // Off until a reload turns it on
+            std::thread::sleep(Duration::from_secs(interval.max(1)));
+            if interval == 0 {
+                continue;
+            }
+            let phase = server_phase();
+            // Launched servers lead their own process group
+            let group = match &*STATE.read() {
+                Starting { procces } | Running { procces } => Some(procces.id()),
+                Adopted { pid } => *pid,
+                Idle | BackingUp => None,
+            };
+            let (cpu_percent, memory_bytes) = match group {
+                Some(group) => sampler.process_group(group),
+                None => {
+                    sampler.reset();
+                    (0.0, 0)
+                }
+            };
+            let (tps, mspt) = TICKS.write().take();
+            let sample = metrics::Sample {
+                time: SystemTime::now(),
+                cpu_percent,
+                memory_bytes,
+                world_bytes: metrics::disk_usage(Path::new(&world_name())),
+                backup_bytes: metrics::disk_usage(Path::new(&config().backup_directory)),
+                tps,
+                mspt,
+            };
+            {
+                let mut history = METRICS.write();
+                history.push_back((phase, sample.clone()));
+                while history.len() > config().metrics_history {
+                    history.pop_front();
+                }
+            }
+            let _ = METRIC_SAMPLES.send((phase, sample));
+            // Ask for the tick rate, the answer shows up in the console or comes back over rcon
+            // and goes in the next sample
+            if let (ServerPhase::Ready, Some(command)) = (phase, &config().tps_command) {
+                if let Ok(output) = STATE.write().run_command(command) {
+                    TICKS.write().observe(&output);
+                }
+            }
+        }
+    });
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////
+// Prometheus
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+/// Counters the prometheus endpoint reports, they start over when mcsc-server restarts
+#[derive(Debug, Default)]
+struct Stats {
+    /// Calls by method and grpc status
+    rpcs: BTreeMap<(String, String), u64>,
+    /// Refused requests by action and why
+    auth_failures: BTreeMap<(String, &'static str), u64>,
+    backups: u64,
+    backup_seconds: f64,
+    last_backup_bytes: u64,
+    download_bytes: u64,
+}
+
+lazy_static! {
+    static ref STATS: RwLock<Stats> = RwLock::new(Stats::default());
+}
+
+fn count_auth_failure(action: AuthAction, reason: &'static str) {
+    *STATS
+        .write()
+        .auth_failures
+        .entry((action.as_str_name().to_string(), reason))
+        .or_default() += 1;
+}
+
+/// Wraps the grpc service to count calls by method and status, and to log each one in a span with
+/// the method and peer. Auth refusals are also counted by reason in count_auth_failure
+#[derive(Debug, Clone)]
+struct CountRpcs<S> {
+    inner: S,
+}
+
+impl<S, B> tonic::codegen::Service<tonic::codegen::http::Request<B>> for CountRpcs<S>
+where
+    S: tonic::codegen::Service<
+            tonic::codegen::http::Request<B>,
+            Response = tonic::codegen::http::Response<tonic::body::BoxBody>,
+        > + Clone
+        + Send
+        + 'static,
+    S::Future: Send + 'static,
+    B: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = tonic::codegen::BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: tonic::codegen::http::Request<B>) -> Self::Future {
+        // "/actions.Controller/Launch"
+        let method = req
+            .uri()
+            .path()
+            .rsplit('/')
+            .next()
+            .unwrap_or_default()
+            .to_string();
+        let peer = req
+            .extensions()
+            .get::<tonic::transport::server::TcpConnectInfo>()
+            .and_then(|info| info.remote_addr());
+        // The user is filled in once their token checks out. At error level so the span is kept
+        // whatever log_level filters out, events are still filtered by their own level
+        let span = tracing::error_span!(
+            "rpc",
+            method,
+            peer = peer.map(|peer| peer.to_string()),
+            user = tracing::field::Empty,
+        );
+        let started = std::time::Instant::now();
+        // The clone may not be ready, keep the one poll_ready was called on
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let call = async move {
+            let response = inner.call(req).await?;
+            // Failed calls carry their status in the headers, successful ones in the trailers
+            let code = response
+                .headers()
+                .get("grpc-status")
+                .and_then(|status| status.to_str().ok())
+                .and_then(|status| status.parse().ok())
+                .map_or(tonic::Code::Ok, tonic::Code::from_i32);
+            debug!(
+                code = ?code,
+                millis = started.elapsed().as_millis() as u64,
+                "Finished"
+            );
+            *STATS
+                .write()
+                .rpcs
+                .entry((method, format!("{code:?}")))
+                .or_default() += 1;
+            Ok(response)
+        };
+        Box::pin(call.instrument(span))
+    }
+}
+
+/// Serve /metrics over http on prometheus_socket
+fn serve_prometheus() {
+    let socket = match config().prometheus_socket.clone() {
+        Some(socket) => socket,
+        None => return,
+    };
+    let listener = match std::net::TcpListener::bind(&socket) {
+        Ok(listener) => listener,
+        Err(error) => {
+            error!("Couldn't listen for prometheus on {socket}: {error}");
+            return;
+        }
+    };
+    info!("Serving prometheus metrics on http://{socket}/metrics");
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if let Err(error) = prometheus::serve(stream, render_prometheus) {
+                debug!("Prometheus scrape failed: {error}");
+            }
+        }
+    });
+}
+
+fn render_prometheus() -> String {
+    let mut out = prometheus::Exposition::default();
+    let phase = server_phase();
+    out.metric(
+        "mcsc_server_state",
+        "gauge",
+        "1 for the state the minecraft server is in",
+    );
+    for state in [
+        ServerPhase::Idle,
+        ServerPhase::Starting,
+        ServerPhase::Ready,
+        ServerPhase::BackingUp,
+    ] {
+        out.sample(
+            "mcsc_server_state",
+            &[("state", state.as_str_name())],
+            if state == phase { 1.0 } else { 0.0 },
+        );
+    }
+    let pid = match &*STATE.read() {
+        Starting { procces } | Running { procces } => Some(procces.id()),
+        Adopted { pid } => *pid,
+        Idle | BackingUp => None,
+    };
+    out.single(
+        "mcsc_server_uptime_seconds",
+        "gauge",
+        "How long the minecraft server has been running",
+        pid.and_then(metrics::process_uptime)
+            .map_or(0.0, |uptime| uptime.as_secs_f64()),
+    );
+    out.single(
+        "mcsc_players_online",
+        "gauge",
+        "Players on the minecraft server",
+        players_online() as f64,
+    );
+    if let Some((_, sample)) = METRICS.read().back() {
+        out.single(
+            "mcsc_server_cpu_percent",
+            "gauge",
+            "Cpu use of the minecraft server, 100 is one core",
+            sample.cpu_percent,
+        );
+        out.single(
+            "mcsc_server_memory_bytes",
+            "gauge",
+            "Resident memory of the minecraft server",
+            sample.memory_bytes as f64,
+        );
+        out.single(
+            "mcsc_world_bytes",
+            "gauge",
+            "Disk space the world takes",
+            sample.world_bytes as f64,
+        );
+        out.single(
+            "mcsc_backup_directory_bytes",
+            "gauge",
+            "Disk space the backups take",
+            sample.backup_bytes as f64,
+        );
+        if let Some(tps) = sample.tps {
+            out.single("mcsc_server_tps", "gauge", "Ticks per second", tps);
+        }
+        if let Some(mspt) = sample.mspt {
+            out.single("mcsc_server_mspt", "gauge", "Milliseconds per tick", mspt);
+        }
+    }
+    let stats = STATS.read();
+    out.metric(
+        "mcsc_rpc_requests_total",
+        "counter",
+        "Grpc calls by method and status",
+    );
+    for ((method, code), count) in &stats.rpcs {
+        out.sample(
+            "mcsc_rpc_requests_total",
+            &[("method", method), ("code", code)],
+            *count as f64,
+        );
+    }
+    out.metric(
+        "mcsc_auth_failures_total",
+        "counter",
+        "Refused requests by action and reason",
+    );
+    for ((action, reason), count) in &stats.auth_failures {
+        out.sample(
+            "mcsc_auth_failures_total",
+            &[("action", action), ("reason", reason)],
+            *count as f64,
+        );
+    }
+    out.metric(
+        "mcsc_backup_duration_seconds",
+        "summary",
+        "Time taken by backups",
+    );
+    out.sample(
+        "mcsc_backup_duration_seconds_sum",
+        &[],
+        stats.backup_seconds,
+    );
+    out.sample(
+        "mcsc_backup_duration_seconds_count",
+        &[],
+        stats.backups as f64,
+    );
+    out.single(
+        "mcsc_backup_size_bytes",
+        "gauge",
+        "Size of the last backup",
+        stats.last_backup_bytes as f64,
+    );
+    out.single(
+        "mcsc_download_bytes_total",
+        "counter",
+        "World download bytes served",
+        stats.download_bytes as f64,
+    );
+    out.finish()
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////
+// Web UI
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+/// Web logins are forgotten after this long without use
+const WEB_SESSION_TIMEOUT: Duration = Duration::from_secs(12 * 60 * 60);
+/// How many console lines the web console starts with
+const WEB_CONSOLE_HISTORY: u32 = 200;
+/// How often an idle web console is checked for a closed connection
+const WEB_KEEPALIVE: Duration = Duration::from_secs(15);
+/// Most web connections served at once, each one has a thread
+const WEB_CONNECTIONS: usize = 64;
+/// Failed logins from one address before it has to wait out WEB_LOGIN_LOCKOUT
+const WEB_LOGIN_ATTEMPTS: u32 = 5;
+const WEB_LOGIN_LOCKOUT: Duration = Duration::from_secs(60);
+
+/// Web connections being served right now
+static WEB_OPEN: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+lazy_static! {
+    /// Logged in browsers by session cookie, with the user's name and when they were last seen
+    static ref WEB_SESSIONS: RwLock<HashMap<String, (String, std::time::Instant)>> =
+        RwLock::new(HashMap::new());
+    /// Failed web logins by address, with when the first of them was
+    static ref WEB_LOGIN_FAILURES: RwLock<HashMap<std::net::IpAddr, (u32, std::time::Instant)>> =
+        RwLock::new(HashMap::new());
+}
+
+/// Counts a web connection as open until it's dropped
+struct WebConnection;
+
+impl WebConnection {
+    /// None when WEB_CONNECTIONS are already open
+    fn open() -> Option<Self> {
+        use std::sync::atomic::Ordering;
+        WEB_OPEN
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |open| {
+                (open < WEB_CONNECTIONS).then_some(open + 1)
+            })
+            .ok()
+            .map(|_| WebConnection)
+    }
+}
+
+impl Drop for WebConnection {
+    fn drop(&mut self) {
+        WEB_OPEN.fetch_sub(1, std::sync::atomic::Ordering::SeqCst);
+    }
+}
+
+/// Serve the web ui on web_socket. It's a json gateway to the same handlers the grpc service uses,
+/// logged in users get tokens for them like any other client
+fn serve_web() {
+    let socket = match config().web_socket.clone() {
+        Some(socket) => socket,
+        None => return,
+    };
+    let listener = match std::net::TcpListener::bind(&socket) {
+        Ok(listener) => listener,
+        Err(error) => {
+            error!("Couldn't listen for the web ui on {socket}: {error}");
+            return;
+        }
+    };
+    info!("Serving the web ui on http://{socket}");
+    // Each request gets a thread, the handlers are run on the main runtime from there
+    let runtime = tokio::runtime::Handle::current();
+    std::thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            let Some(connection) = WebConnection::open() else {
+                let _ = web::json(
+                    &mut stream,
+                    "503 Service Unavailable",
+                    &json!({ "result": "Fail", "comment": "Too many connections, try again" }),
+                );
+                continue;
+            };
+            let runtime = runtime.clone();
+            std::thread::spawn(move || {
+                let _ = web_request(stream, &runtime);
+                drop(connection);
+            });
+        }
+    });
+}
+
+#[allow(clippy::result_large_err)]
+fn web_request(
+    mut stream: std::net::TcpStream,
+    runtime: &tokio::runtime::Handle,
+) -> std::io::Result<()> {
+    let request = web::Request::read(&stream)?;
+    // The handlers run on this thread through block_on, so their logs land in this span too
+    let span = tracing::error_span!(
+        "web",
+        method = request.method,
+        path = request.path,
+        peer = stream.peer_addr().ok().map(|peer| peer.to_string()),
+        user = tracing::field::Empty,
+    )
+    .entered();
+    let service = ControllerService::default();
+    let path = request.path.as_str();
+    if request.method == "GET" && path == "/" {
+        return web::respond(
+            &mut stream,
+            "200 OK",
+            "text/html; charset=utf-8",
+            &[],
+            web::INDEX.as_bytes(),
+        );
+    }
+    if request.method == "POST" && path == "/api/login" {
+        return web_login(&mut stream, &request);
+    }
+    let (session, user) = match web_session(&request) {
+        Some(session) => session,
+        None => {
+            return web::json(
+                &mut stream,
+                "401 Unauthorized",
+                &json!({ "result": "Denied", "comment": "Log in first" }),
+            )
+        }
+    };
+    span.record("user", log_name(&user));
+    let body = request.json();
+    match (request.method.as_str(), path) {
+        ("GET", "/api/me") => web::json(
+            &mut stream,
+            "200 OK",
+            &json!({ "user": if user.name.is_empty() { "shared key" } else { &user.name } }),
+        ),
+        ("POST", "/api/logout") => {
+            WEB_SESSIONS.write().remove(&session);
+            web::respond(
+                &mut stream,
+                "200 OK",
+                "application/json",
+                &[("Set-Cookie", "mcsc_session=; Max-Age=0; Path=/")],
+                b"{}",
+            )
+        }
+        ("GET", "/api/status") => web_call(&mut stream, &user, AuthAction::Status, |token| {
+            let status = runtime
+                .block_on(service.status(Request::new(StatusRequest { token })))?
+                .into_inner();
+            Ok(json!({
+                "result": op_result_name(status.result),
+                "comment": status.comment,
+                "phase": ServerPhase::from_i32(status.phase).unwrap_or_default().as_str_name(),
+                "online": status.online,
+                "max": status.max,
+                "players": status.players,
+                "version": status.version,
+                "motd": status.motd,
+                "eula_accepted": status.eula_accepted,
+            }))
+        }),
+        ("POST", "/api/launch") => web_call(&mut stream, &user, AuthAction::Launch, |token| {
+            let wait = body["wait"].as_bool().unwrap_or_default();
+            op_json(runtime.block_on(service.launch(Request::new(LaunchRequest { token, wait }))))
+        }),
+        ("POST", "/api/stop") => web_call(&mut stream, &user, AuthAction::Stop, |token| {
+            let force = body["force"].as_bool().unwrap_or_default();
+            op_json(runtime.block_on(service.stop(Request::new(StopRequest { token, force }))))
+        }),
+        ("POST", "/api/backup") => web_call(&mut stream, &user, AuthAction::Backup, |token| {
+            op_json(runtime.block_on(service.backup(Request::new(BackupRequest { token }))))
+        }),
+        ("POST", "/api/command") => web_call(&mut stream, &user, AuthAction::Command, |token| {
+            let command = body["command"].as_str().unwrap_or_default().to_string();
+            op_json(
+                runtime.block_on(service.command(Request::new(CommandRequest { token, command }))),
+            )
+        }),
+        ("GET", "/api/backups") => web_call(&mut stream, &user, AuthAction::Backups, |token| {
+            let backups = runtime
+                .block_on(service.backups(Request::new(BackupsRequest { token })))?
+                .into_inner();
+            Ok(json!({
+                "result": op_result_name(backups.result),
+                "comment": backups.comment,
+                "backups": backups.backups.iter().map(|backup| json!({
+                    "name": backup.name,
+                    "size": backup.size,
+                    "created": backup.created,
+                })).collect::<Vec<_>>(),
+            }))
+        }),
+        ("GET", "/api/download") => {
+            let name = request.query("name").unwrap_or_default();
+            web_download(&mut stream, runtime, &user, name)
+        }
+        ("GET", "/api/console") => web_console(&mut stream, runtime, &user),
+        _ => web::json(
+            &mut stream,
+            "404 Not Found",
+            &json!({ "result": "Fail", "comment": "Not found" }),
+        ),
+    }
+}
+
+/// Check a user's key and hand out a session cookie. Addresses that keep getting it wrong are
+/// turned away for a while
+fn web_login(stream: &mut std::net::TcpStream, request: &web::Request) -> std::io::Result<()> {
+    let peer = stream.peer_addr()?.ip();
+    if let Some((failures, since)) = WEB_LOGIN_FAILURES.read().get(&peer) {
+        if *failures >= WEB_LOGIN_ATTEMPTS && since.elapsed() < WEB_LOGIN_LOCKOUT {
+            warn!("Turning away a login, {failures} wrong keys recently");
+            return web::json(
+                stream,
+                "429 Too Many Requests",
+                &json!({ "result": "Denied", "comment": "Too many wrong keys, wait a minute" }),
+            );
+        }
+    }
+    let body = request.json();
+    let name = body["user"].as_str().unwrap_or_default();
+    let key = body["key"].as_str().unwrap_or_default();
+    match find_user(name) {
+        Some(user) if !key.is_empty() && keys_match(&user.key, key) => {
+            WEB_LOGIN_FAILURES.write().remove(&peer);
+            let session = common::ran_letters(48);
+            WEB_SESSIONS
+                .write()
+                .insert(session.clone(), (user.name, std::time::Instant::now()));
+            web::respond(
+                stream,
+                "200 OK",
+                "application/json",
+                &[(
+                    "Set-Cookie",
+                    &format!("mcsc_session={session}; HttpOnly; SameSite=Strict; Path=/"),
+                )],
+                b"{}",
+            )
+        }
+        _ => {
+            let mut failures = WEB_LOGIN_FAILURES.write();
+            failures.retain(|_, (_, since)| since.elapsed() < WEB_LOGIN_LOCKOUT);
+            failures
+                .entry(peer)
+                .or_insert((0, std::time::Instant::now()))
+                .0 += 1;
+            drop(failures);
+            web::json(
+                stream,
+                "401 Unauthorized",
+                &json!({ "result": "Denied", "comment": "Wrong user or key" }),
+            )
+        }
+    }
+}
+
+/// Compare keys in time that doesn't depend on where they differ, so it can't be used to guess
+/// them a character at a time
+fn keys_match(expected: &str, given: &str) -> bool {
+    expected.len() == given.len()
+        && expected
+            .bytes()
+            .zip(given.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+/// The session cookie and its user, None when it's missing or has expired
+fn web_session(request: &web::Request) -> Option<(String, User)> {
+    let session = request.cookie("mcsc_session")?;
+    let mut sessions = WEB_SESSIONS.write();
+    sessions.retain(|_, (_, seen)| seen.elapsed() < WEB_SESSION_TIMEOUT);
+    let (name, seen) = sessions.get_mut(session)?;
+    *seen = std::time::Instant::now();
+    // The user may have been taken out of the config since
+    Some((session.to_string(), find_user(name)?))
+}
+
+/// Run a handler with a token for the user, replying with the json it made
+fn web_call(
+    stream: &mut std::net::TcpStream,
+    user: &User,
+    action: AuthAction,
+    call: impl FnOnce(Vec<u8>) -> Result<serde_json::Value, Status>,
+) -> std::io::Result<()> {
+    if !user.may(action) {
+        return web_fail(stream, &not_permitted(action));
+    }
+    match call(authorize_key(action, user)) {
+        Ok(value) => web::json(stream, "200 OK", &value),
+        Err(status) => web_fail(stream, &status),
+    }
+}
+
+/// Reply with the error a failed rpc carries, under the closest http status
+fn web_fail(stream: &mut std::net::TcpStream, status: &Status) -> std::io::Result<()> {
+    let error: actions::Error = prost::Message::decode(status.details()).unwrap_or_default();
+    let http_status = match status.code() {
+        tonic::Code::Unauthenticated => "401 Unauthorized",
+        tonic::Code::PermissionDenied => "403 Forbidden",
+        tonic::Code::NotFound => "404 Not Found",
+        tonic::Code::InvalidArgument => "400 Bad Request",
+        tonic::Code::FailedPrecondition | tonic::Code::Aborted => "409 Conflict",
+        tonic::Code::Unavailable => "502 Bad Gateway",
+        tonic::Code::DeadlineExceeded => "504 Gateway Timeout",
+        _ => "500 Internal Server Error",
+    };
+    let result = match status.code() {
+        tonic::Code::Unauthenticated | tonic::Code::PermissionDenied => OpResult::Denied,
+        _ => OpResult::Fail,
+    };
+    let code = ErrorCode::from_i32(error.code).unwrap_or(ErrorCode::InternalError);
+    web::json(
+        stream,
+        http_status,
+        &json!({
+            "result": result.as_str_name(),
+            "comment": status.message(),
+            "error": code.as_str_name(),
+            "details": error.details,
+        }),
+    )
+}
+
+fn op_result_name(result: i32) -> &'static str {
+    OpResult::from_i32(result)
+        .unwrap_or(OpResult::Fail)
+        .as_str_name()
+}
+
+#[allow(clippy::result_large_err)]
+fn op_json(responce: Result<Response<OpResponce>, Status>) -> Result<serde_json::Value, Status> {
+    let responce = responce?.into_inner();
+    Ok(json!({
+        "result": op_result_name(responce.result),
+        "comment": responce.comment,
+    }))
+}
+
+/// Send a backup as a file download
+fn web_download(
+    stream: &mut std::net::TcpStream,
+    runtime: &tokio::runtime::Handle,
+    user: &User,
+    name: String,
+) -> std::io::Result<()> {
+    if !user.may(AuthAction::Download) {
+        return web_fail(stream, &not_permitted(AuthAction::Download));
+    }
+    let token = authorize_key(AuthAction::Download, user);
+    let filename = if name.is_empty() {
+        "world-backup.tar.gz".to_string()
+    } else {
+        name.clone()
+    };
+    let service = ControllerService::default();
+    let mut parts =
+        match runtime.block_on(service.download(Request::new(DownloadRequest { token, name }))) {
+            Ok(parts) => parts.into_inner(),
+            Err(status) => return web_fail(stream, &status),
+        };
+    let mut started = false;
+    loop {
+        let part = match runtime.block_on(parts.next()) {
+            Some(Ok(part)) => part,
+            Some(Err(status)) if !started => return web_fail(stream, &status),
+            // A failed part cuts the download short so it doesn't look complete
+            _ => break,
+        };
+        if !started {
+            started = true;
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\n\
+                 Content-Type: application/gzip\r\n\
+                 Content-Length: {}\r\n\
+                 Content-Disposition: attachment; filename=\"{filename}\"\r\n\
+                 Connection: close\r\n\r\n",
+                part.size
+            )?;
+        }
+        stream.write_all(&part.data)?;
+    }
+    if !started {
+        return web::json(
+            stream,
+            "500 Internal Server Error",
+            &json!({ "result": "Fail", "comment": "Download failed" }),
+        );
+    }
+    Ok(())
+}
+
+/// Follow the console as server-sent events
+fn web_console(
+    stream: &mut std::net::TcpStream,
+    runtime: &tokio::runtime::Handle,
+    user: &User,
+) -> std::io::Result<()> {
+    if !user.may(AuthAction::Console) {
+        return web_fail(stream, &not_permitted(AuthAction::Console));
+    }
+    let token = authorize_key(AuthAction::Console, user);
+    let service = ControllerService::default();
+    let mut lines = match runtime.block_on(service.console(Request::new(ConsoleRequest {
+        token,
+        history: WEB_CONSOLE_HISTORY,
+    }))) {
+        Ok(lines) => lines.into_inner(),
+        Err(status) => return web_fail(stream, &status),
+    };
+    web::start_stream(stream, "text/event-stream", &[])?;
+    loop {
+        match runtime.block_on(async { tokio::time::timeout(WEB_KEEPALIVE, lines.next()).await }) {
+            Ok(Some(Ok(line))) => write!(stream, "data: {}\n\n", line.line)?,
+            Ok(_) => return Ok(()),
+            // Writing something is the only way to notice the browser went away
+            Err(_) => write!(stream, ": keepalive\n\n")?,
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////
+// Rcon
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+const RCON_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long an adopted server gets to shut down after being told to stop
+const ADOPTED_STOP_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Where rcon listens and its password, None when it isn't enabled in server.properties
+fn rcon_settings() -> Option<(String, String)> {
+    let loaded = properties::Properties::load().ok()?;
+    if loaded.get("enable-rcon") != Some("true") {
+        return None;
+    }
+    let password = loaded.get("rcon.password").filter(|p| !p.is_empty())?;
+    Some((
+        property_address("rcon.port", "25575", "127.0.0.1"),
+        password.to_string(),
+    ))
+}
+
+/// Run a command over a fresh rcon connection
+fn rcon_command(command: &str) -> Result<String, rcon::RconError> {
+    let (address, password) = rcon_settings().ok_or(rcon::RconError::Disabled)?;
+    rcon::Rcon::connect(&address, &password, RCON_TIMEOUT)?.command(command.trim())
+}
+
+/// Wait for an adopted server to stop listening for rcon, false if it's still up after
+/// ADOPTED_STOP_TIMEOUT
+fn wait_for_rcon_to_close() -> bool {
+    let started = std::time::Instant::now();
+    while started.elapsed() < ADOPTED_STOP_TIMEOUT {
+        match rcon_settings() {
+            Some((address, _)) if std::net::TcpStream::connect(&address).is_ok() => {
+                std::thread::sleep(Duration::from_millis(500))
+            }
+            _ => return true,
+        }
+    }
+    false
+}
+
+fn rcon_failure(rcon_error: rcon::RconError) -> Status {
+    let code = match rcon_error {
+        rcon::RconError::Disabled => ErrorCode::NotConfigured,
+        rcon::RconError::TooLong => ErrorCode::InvalidRequest,
+        _ => ErrorCode::UpstreamFailed,
+    };
+    fail_with(
+        code,
+        rcon_error_comment(rcon_error),
+        [("service", "rcon".to_string())],
+    )
+}
+
+fn rcon_error_comment(rcon_error: rcon::RconError) -> String {
+    match rcon_error {
+        rcon::RconError::Disabled => {
+            "Rcon isn't enabled, set enable-rcon and rcon.password in server.properties".to_string()
         }
+        rcon::RconError::Connect(error) => format!("Couldn't connect to rcon: {error}"),
+        rcon::RconError::Auth => "Rcon refused the password from server.properties".to_string(),
+        rcon::RconError::TooLong => "Command too long for rcon".to_string(),
+        rcon::RconError::Io(error) => format!("Rcon connection failed: {error}"),
     }
+}
 
-    /// Stop the running procces by entering stop into the stdin
-    fn stop(&mut self) -> Result<(), StopError> {
-        self.check_stop();
-        match self {
-            Running { procces: child } => {
-                let child_input = child.stdin.as_mut();
-                match child_input {
-                    Some(buff) => {
-                        match buff.write_all(b"\nstop\n") {
-                            Err(_) => return Err(StopError::ProccesError),
-                            _ => {}
-                        };
-                        match child.wait() {
-                            Ok(_) => {}
-                            Err(_) => {}
-                        }
-                        *self = Idle;
-                        Ok(())
-                    }
-                    None => Err(StopError::ProccesError),
-                }
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////
+// Reattaching
+///////////////////////////////////////////////////////////////////////////////////////////////////////////////
+
+/// Holds the pid of the server while it runs, relative to minecraft dir
+const PID_FILE: &str = "mcsc-server.pid";
+/// Named pipe the server reads its console from with console_pipe, relative to minecraft dir
+const CONSOLE_PIPE: &str = "mcsc-console.pipe";
+
+/// Find a server left running by an earlier mcsc-server, through the pid file or rcon, so it can
+/// be stopped and commanded instead of launched a second time
+fn reattach() {
+    let pid = std::fs::read_to_string(PID_FILE)
+        .ok()
+        .and_then(|pid| pid.trim().parse().ok())
+        .filter(|pid| server_alive(*pid));
+    if pid.is_none() {
+        forget_pid();
+    }
+    let rcon = config().rcon
+        && match rcon_command("list") {
+            Ok(_) => true,
+            Err(rcon::RconError::Connect(_)) | Err(rcon::RconError::Disabled) => false,
+            Err(rcon_error) => {
+                warn!(
+                    "Couldn't reach the running server: {}",
+                    rcon_error_comment(rcon_error)
+                );
+                false
             }
-            BackingUp => Err(StopError::Downloading),
-            Idle => Err(StopError::Idle),
+        };
+    if pid.is_none() && !rcon {
+        return;
+    }
+    *STATE.write() = Adopted { pid };
+    let console = if rcon {
+        "rcon"
+    } else if config().console_pipe && Path::new(CONSOLE_PIPE).exists() {
+        "the console pipe"
+    } else {
+        "nothing, it can only be stopped"
+    };
+    match pid {
+        Some(pid) => info!("Reattached to server procces {pid}, commands go through {console}"),
+        None => {
+            info!("Adopted the server that's already running, commands go through {console}")
         }
     }
 }
 
-#[derive(Debug)]
-enum StopError {
-    Idle,
-    Downloading,
-    ProccesError,
+fn forget_pid() {
+    let _ = std::fs::remove_file(PID_FILE);
 }
 
-#[derive(Debug)]
-enum LaunchError {
-    Launch,
-    AlreadyRunning,
-    Downloading,
+/// Whether the server with this pid is still running. Where /proc shows it, the procces also has
+/// to be running in the minecraft directory in case the pid was reused
+fn server_alive(pid: u32) -> bool {
+    let procces = PathBuf::from(format!("/proc/{pid}"));
+    if Path::new("/proc").exists() {
+        return match std::fs::read_link(procces.join("cwd")) {
+            Ok(cwd) => std::env::current_dir().is_ok_and(|dir| dir == cwd),
+            Err(_) => procces.exists(),
+        };
+    }
+    Command::new("kill")
+        .arg("-0")
+        .arg(pid.to_string())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
 }
 
-#[derive(Debug)]
-enum BackupError {
-    ServerRunning,
-    OtherBackup,
-    Compression,
+/// SIGTERM the server's process group, or just the procces if it was launched without one
+fn terminate(pid: u32) -> bool {
+    let kill = |target: String| {
+        Command::new("kill")
+            .arg("-TERM")
+            .arg("--")
+            .arg(target)
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success())
+    };
+    kill(format!("-{pid}")) || kill(pid.to_string())
 }
 
-#[derive(Debug)]
-enum CommandError {
-    Idle,
-    Downloading,
-    ProccesError,
+/// Wait for a reattached server to exit, false if it's still running after ADOPTED_STOP_TIMEOUT
+fn wait_for_exit(pid: u32) -> bool {
+    let started = std::time::Instant::now();
+    while started.elapsed() < ADOPTED_STOP_TIMEOUT {
+        if !server_alive(pid) {
+            return true;
+        }
+        std::thread::sleep(Duration::from_millis(500));
+    }
+    false
 }
 
-lazy_static! {
-    static ref CONFIG: crate::Config = crate::config_load();
-    /// Contains the current procces of the minecraft server and it's stdin
-    static ref STATE: RwLock<ServerState> = RwLock::new(Idle);
+/// Open the console pipe for the server's stdin, creating it if needed. It's opened for writing
+/// too so the server never reads the end of its input when mcsc-server goes away
+fn open_console_pipe() -> std::io::Result<File> {
+    if !Path::new(CONSOLE_PIPE).exists() {
+        let status = Command::new("mkfifo").arg(CONSOLE_PIPE).status()?;
+        if !status.success() {
+            return Err(std::io::Error::other("mkfifo failed"));
+        }
+    }
+    std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(CONSOLE_PIPE)
+}
+
+/// Write to the server's console, through its stdin or the console pipe when it has none
+fn write_console(stdin: Option<&mut ChildStdin>, text: &str) -> bool {
+    let result = match stdin {
+        Some(stdin) => stdin.write_all(text.as_bytes()),
+        // Opened for reading too so this doesn't block when nothing reads the other end
+        None => std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(CONSOLE_PIPE)
+            .and_then(|mut pipe| pipe.write_all(text.as_bytes())),
+    };
+    result.is_ok()
 }
 
 ///////////////////////////////////////////////////////////////////////////////////////////////////////////////
@@ -428,9 +3341,6 @@ lazy_static! {
 ///////////////////////////////////////////////////////////////////////////////////////////////////////////////
 
 lazy_static! {
-    static ref CRYPT: MagicCrypt256 = new_magic_crypt!(CONFIG.key.clone(), 256);
-    static ref SOCKET: String = CONFIG.socket.clone();
-    static ref KEY: String = CONFIG.key.clone();
     static ref KEYS: RwLock<RollingSet<Key>> = RwLock::new(RollingSet::new(2048));
 }
 const KEY_BYTES: usize = 256;
@@ -441,8 +3351,62 @@ struct Key {
     key: Vec<u8>,
 }
 
-fn encrypt(data: Vec<u8>) -> Vec<u8> {
-    CRYPT.encrypt_bytes_to_bytes(&data)
+/// Someone allowed to use the service, authenticated by their own key
+#[derive(serde_derive::Deserialize, serde_derive::Serialize, Debug, Clone)]
+struct User {
+    name: String,
+    key: String,
+    /// Admins may do everything
+    #[serde(default)]
+    admin: bool,
+    /// Actions this user may request, eg. ["Launch", "Stop"], every action when unset. AcceptEula
+    /// and ReloadConfig are left to admins
+    actions: Option<Vec<String>>,
+    /// server.properties keys this user may change, "*" for all
+    #[serde(default)]
+    properties: Vec<String>,
+}
+
+impl User {
+    fn may(&self, action: AuthAction) -> bool {
+        self.admin
+            || !matches!(action, AuthAction::AcceptEula | AuthAction::ReloadConfig)
+                && self.actions.as_ref().is_none_or(|actions| {
+                    actions
+                        .iter()
+                        .any(|allowed| allowed.eq_ignore_ascii_case(action.as_str_name()))
+                })
+    }
+
+    fn may_edit_property(&self, key: &str) -> bool {
+        self.admin
+            || self
+                .properties
+                .iter()
+                .any(|allowed| allowed == "*" || allowed == key)
+    }
+}
+
+/// Look up a user by name, no name means the shared key from the config which has full access
+fn find_user(name: &str) -> Option<User> {
+    if name.is_empty() {
+        return Some(User {
+            name: String::new(),
+            key: config().key.clone(),
+            admin: true,
+            actions: None,
+            properties: Vec::new(),
+        });
+    }
+    config()
+        .users
+        .iter()
+        .find(|user| user.name == name)
+        .cloned()
+}
+
+fn encrypt(data: Vec<u8>, secret: &str) -> Vec<u8> {
+    new_magic_crypt!(secret, 256).encrypt_bytes_to_bytes(&data)
 }
 
 /// Generate some some random bytes for authentication
@@ -452,10 +3416,12 @@ fn gen_bytes(key_bytes: usize) -> Vec<u8> {
     bytes
 }
 
-/// Create a new key to give to our client, and store it so it can be verified later
-fn authorize_key(action: AuthAction) -> Vec<u8> {
+/// Create a new key to give to our client, and store it so it can be verified later. The user's
+/// name follows the random bytes so handlers can tell who a token belongs to
+fn authorize_key(action: AuthAction, user: &User) -> Vec<u8> {
     let mut set = KEYS.write();
-    let bytes = gen_bytes(KEY_BYTES);
+    let mut bytes = gen_bytes(KEY_BYTES);
+    bytes.extend_from_slice(user.name.as_bytes());
     set.insert(Key {
         key: bytes.clone(),
         action,
@@ -465,21 +3431,38 @@ fn authorize_key(action: AuthAction) -> Vec<u8> {
 
 /// Check that a key has been authored by us
 fn verify_key(key: Key) -> bool {
-    let mut set = KEYS.write();
-    let res = set.remove(&key);
-    res
+    let action = key.action;
+    let verified = KEYS.write().remove(&key);
+    if !verified {
+        count_auth_failure(action, "invalid_token");
+    }
+    verified
+}
+
+/// The user a token was issued to, only meaningful once the token has been verified
+fn token_user(token: &[u8]) -> Option<User> {
+    let name = std::str::from_utf8(token.get(KEY_BYTES..)?).ok()?;
+    find_user(name)
 }
 
 ///////////////////////////////////////////////////////////////////////////////////////////////////////////////
 // Backup stuff
 ///////////////////////////////////////////////////////////////////////////////////////////////////////////////
 
+/// A backup by its file name, None unless it's a file in backup_directory
+fn backup_path(name: &str) -> Option<PathBuf> {
+    if name.contains('/') || name.starts_with('.') {
+        return None;
+    }
+    let path = Path::new(&config().backup_directory).join(name);
+    path.is_file().then_some(path)
+}
+
 fn latest_file(dir: &str) -> Option<PathBuf> {
     let files = match std::fs::read_dir(dir) {
         Ok(files) => files,
         Err(_) => return None,
-    }
-    .into_iter();
+    };
 
     match iter_paths_with_sys_time(files).max_by_key(|t| t.1) {
         Some(tuple) => Some(tuple.0),
@@ -490,34 +3473,26 @@ fn latest_file(dir: &str) -> Option<PathBuf> {
 fn iter_paths_with_sys_time(
     files: std::fs::ReadDir,
 ) -> impl Iterator<Item = (PathBuf, SystemTime)> + 'static {
-    files
-        .flatten()
-        .map(|f| f.path())
-        .map(|p| {
-            let time = match std::fs::metadata(&p) {
-                Ok(metadata) => match metadata.modified() {
-                    Ok(time) => time,
-                    Err(_) => return None,
-                },
+    files.flatten().map(|f| f.path()).filter_map(|p| {
+        let time = match std::fs::metadata(&p) {
+            Ok(metadata) => match metadata.modified() {
+                Ok(time) => time,
                 Err(_) => return None,
-            };
-            Some((p, time))
-        })
-        .flatten()
+            },
+            Err(_) => return None,
+        };
+        Some((p, time))
+    })
 }
 
 fn remove_oldest_backup(dir: &str) {
     let files = match std::fs::read_dir(dir) {
         Ok(files) => files,
         Err(_) => return,
-    }
-    .into_iter();
+    };
 
-    match iter_paths_with_sys_time(files).min_by_key(|t| t.1) {
-        Some(tuple) => {
-            let _ = std::fs::remove_file(tuple.0);
-        }
-        _ => {}
+    if let Some(tuple) = iter_paths_with_sys_time(files).min_by_key(|t| t.1) {
+        let _ = std::fs::remove_file(tuple.0);
     };
 }
 
@@ -552,7 +3527,7 @@ impl WorldDownloadIterator {
 type WDLStream = Pin<Box<dyn Stream<Item = Result<WorldDownload, Status>> + Send>>;
 
 impl Iterator for WorldDownloadIterator {
-    type Item = WorldDownload;
+    type Item = Result<WorldDownload, Status>;
 
     fn next(&mut self) -> Option<Self::Item> {
         if self.error {
@@ -562,24 +3537,19 @@ impl Iterator for WorldDownloadIterator {
             Ok(buff) => buff.to_vec(),
             Err(_) => {
                 self.error = true;
-                return Some(WorldDownload {
-                    result: OpResult::Fail.into(),
-                    size: self.size as u64,
-                    comment: format!("Download failed"),
-                    data: vec![],
-                });
+                return Some(Err(fail(ErrorCode::IoFailed, "Reading the backup failed")));
             }
         };
         self.file_reader.consume(bytes.len());
         self.read += bytes.len();
         let progress = (self.read as f64 / self.size as f64 * 100.) as u64;
         if !bytes.is_empty() {
-            Some(WorldDownload {
+            Some(Ok(WorldDownload {
                 result: OpResult::Success.into(),
                 size: self.size as u64,
                 comment: format!("Download progress: {progress}%"),
                 data: bytes,
-            })
+            }))
         } else {
             None
         }
@@ -590,25 +3560,828 @@ impl Iterator for WorldDownloadIterator {
 // Config
 ///////////////////////////////////////////////////////////////////////////////////////////////////////////////
 
+/// Where the config is looked for without --config
+const CONFIG_FILE: &str = "mcsc_server.toml";
+/// Environment variables starting with this override config fields, eg. MCSC_SERVER_SOCKET
+const CONFIG_ENV_PREFIX: &str = "MCSC_SERVER_";
+
+/// The config file picked on the command line, absolute as the working directory changes
+static CONFIG_PATH: std::sync::OnceLock<PathBuf> = std::sync::OnceLock::new();
+
 /// Contains config info
-#[derive(serde_derive::Deserialize, Debug)]
+#[derive(serde_derive::Deserialize, serde_derive::Serialize, Debug)]
 struct Config {
-    /// Working directory of minecraft server
+    /// Working directory of minecraft server, relative to the config file
+    #[serde(default = "default_minecraft_directory")]
     minecraft_directory: String,
     /// Where to store backups relative to minecraft dir
+    #[serde(default = "default_backup_directory")]
     backup_directory: String,
     /// Clients need to have this to authenticate their actions
     key: String,
     /// Service runs from this socket
+    #[serde(default = "default_socket")]
     socket: String,
+    /// Java major version the minecraft server needs, eg. 8, 17 or 21. When unset launch.sh uses
+    /// whatever java it finds
+    java_version: Option<u32>,
+    /// Where managed java runtimes are extracted to, relative to minecraft dir
+    #[serde(default = "default_java_directory")]
+    java_directory: String,
+    /// Where to list minecraft versions from, a url, file:// url or path to a local mirror
+    #[serde(default = "default_version_manifest")]
+    version_manifest: String,
+    /// The jar launch.sh runs, replaced when switching versions
+    #[serde(default = "default_server_jar")]
+    server_jar: String,
+    /// Mod loader or plugin platform, eg. fabric, forge, quilt, neoforge, paper or spigot
+    loader: Option<String>,
+    /// Minecraft version mods must support, defaults to the version last installed by mcsc
+    game_version: Option<String>,
+    /// Where mod jars go, defaults to plugins for bukkit style servers and mods otherwise
+    mods_directory: Option<String>,
+    /// Base url of the modrinth api, can point at a local stand-in for testing
+    #[serde(default = "default_modrinth_api")]
+    modrinth_api: String,
+    /// Mojang api used to find player uuids when editing lists while the server is stopped
+    #[serde(default = "default_profile_api")]
+    profile_api: String,
+    /// What Stop and Restart do while players are online, unless the request is forced
+    #[serde(default)]
+    online_stop_policy: StopPolicy,
+    /// Seconds the wait policy waits for the server to empty before giving up
+    #[serde(default = "default_online_stop_timeout")]
+    online_stop_timeout: u64,
+    /// Stop the server after this many minutes without anyone online, never when unset
+    idle_shutdown_minutes: Option<u64>,
+    /// Back up the world after an idle shutdown
+    #[serde(default)]
+    idle_backup: bool,
+    /// While stopped, answer server list pings on the game port and launch when someone joins
+    #[serde(default)]
+    wake_on_connect: bool,
+    /// Shown in the server list while the server is asleep
+    #[serde(default = "default_sleeping_motd")]
+    sleeping_motd: String,
+    /// Also use the query protocol for player names and plugins, needs enable-query in
+    /// server.properties
+    #[serde(default)]
+    health_query: bool,
+    /// Seconds a Launch that waits gives the server to start accepting players
+    #[serde(default = "default_ready_timeout")]
+    ready_timeout: u64,
+    /// Run commands over rcon, which returns their output and lets mcsc take over a server that's
+    /// already running. Uses enable-rcon, rcon.port and rcon.password from server.properties
+    #[serde(default)]
+    rcon: bool,
+    /// Give the server its console input through a named pipe, so commands still reach it after
+    /// mcsc-server restarts
+    #[serde(default)]
+    console_pipe: bool,
+    /// Refuse to launch with less disk space free than this, 0 turns the check off
+    #[serde(default = "default_min_free_disk_mb")]
+    min_free_disk_mb: u64,
+    /// Seconds between resource samples, 0 turns sampling off
+    #[serde(default = "default_metrics_interval")]
+    metrics_interval: u64,
+    /// How many samples Metrics can look back on
+    #[serde(default = "default_metrics_history")]
+    metrics_history: usize,
+    /// Where to serve the web ui over http, eg. "127.0.0.1:8080", off when unset. Put it behind a
+    /// proxy with https when it's reachable from outside
+    web_socket: Option<String>,
+    /// Where to serve prometheus metrics over http, eg. "127.0.0.1:9225", off when unset
+    prometheus_socket: Option<String>,
+    /// Console command that reports the tick rate, run with every sample. "tick query" on vanilla
+    /// 1.20.3 and later, "tps" or "mspt" on paper
+    tps_command: Option<String>,
+    /// Which messages to log, a level like "info" or "debug", or directives for parts of mcsc like
+    /// "info,mcsc_server::rcon=debug,minecraft=warn". The server's own output is logged as minecraft
+    #[serde(default = "default_log_level")]
+    log_level: String,
+    /// text or json
+    #[serde(default)]
+    log_format: logging::LogFormat,
+    /// Log to this file instead of stdout, relative to the config file
+    log_file: Option<String>,
+    /// When to start a new log file: hourly, daily or never
+    #[serde(default)]
+    log_rotation: logging::LogRotation,
+    /// How many log files to keep as they're rotated, 0 keeps all of them
+    #[serde(default = "default_log_files_kept")]
+    log_files_kept: usize,
+    /// People with their own keys and permissions, in addition to the shared key
+    #[serde(default)]
+    users: Vec<User>,
+}
+
+/// How to handle a stop request while players are online
+#[derive(serde_derive::Deserialize, serde_derive::Serialize, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum StopPolicy {
+    /// Stop straight away
+    #[default]
+    Allow,
+    /// Refuse the request
+    Deny,
+    /// Hold the request until everyone leaves, refusing it after online_stop_timeout
+    Wait,
+}
+
+fn default_minecraft_directory() -> String {
+    "minecraft".to_string()
+}
+
+fn default_backup_directory() -> String {
+    "backups".to_string()
+}
+
+fn default_socket() -> String {
+    "0.0.0.0:7878".to_string()
+}
+
+fn default_java_directory() -> String {
+    "runtimes".to_string()
+}
+
+fn default_version_manifest() -> String {
+    "https://piston-meta.mojang.com/mc/game/version_manifest_v2.json".to_string()
+}
+
+fn default_server_jar() -> String {
+    "server.jar".to_string()
+}
+
+fn default_profile_api() -> String {
+    "https://api.mojang.com/users/profiles/minecraft".to_string()
+}
+
+fn default_modrinth_api() -> String {
+    "https://api.modrinth.com/v2".to_string()
+}
+
+fn default_online_stop_timeout() -> u64 {
+    300
+}
+
+fn default_ready_timeout() -> u64 {
+    300
+}
+
+fn default_min_free_disk_mb() -> u64 {
+    1024
+}
+
+fn default_metrics_interval() -> u64 {
+    15
+}
+
+fn default_metrics_history() -> usize {
+    240
+}
+
+fn default_sleeping_motd() -> String {
+    "Sleeping, join to wake the server up".to_string()
+}
+
+fn default_log_level() -> String {
+    "info".to_string()
+}
+
+fn default_log_files_kept() -> usize {
+    14
+}
+
+fn config() -> std::sync::Arc<Config> {
+    CONFIG.read().clone()
+}
+
+fn config_path() -> &'static Path {
+    CONFIG_PATH.get_or_init(|| PathBuf::from(CONFIG_FILE))
+}
+
+/// Relative paths in the config start here
+fn config_directory() -> PathBuf {
+    config_path()
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_default()
 }
 
 /// Load the config file and parse it into a convenient data structure
 ///
-/// Panics if the config file couldn't be loaded or parsed
+/// Exits after listing what's wrong if the config file couldn't be loaded or isn't valid
 ///
 fn config_load() -> Config {
-    let bytes = std::fs::read("mcsc_server.toml").expect("Unable to load config file");
-    let config = std::str::from_utf8(&bytes).expect("Config file encoding error");
-    toml::from_str(&config).expect("Unable to parse config, (syntax error)")
+    let (config, problems) = config_read(config_path());
+    for problem in &problems {
+        eprintln!("{problem}");
+    }
+    match config {
+        Some(config) => config,
+        None => {
+            eprintln!("Unable to load the config, fix the errors above");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Fields only read at startup, changes to them wait for mcsc-server to restart
+const RESTART_FIELDS: &[&str] = &[
+    "socket",
+    "minecraft_directory",
+    "web_socket",
+    "prometheus_socket",
+    "log_format",
+    "log_file",
+    "log_rotation",
+    "log_files_kept",
+];
+
+/// What reloading the config did
+#[derive(Debug, Default)]
+struct ConfigReload {
+    /// Whether the new config is in use, it isn't when it has errors
+    applied: bool,
+    /// Fields whose new values are in use
+    changed: Vec<String>,
+    /// Fields that changed but keep their old values until a restart
+    needs_restart: Vec<String>,
+    problems: Vec<ConfigProblem>,
+}
+
+impl ConfigReload {
+    fn describe(&self) -> String {
+        if !self.applied {
+            let errors = self.problems.iter().filter(|problem| problem.fatal).count();
+            return match errors {
+                1 => "Not reloaded, the config has an error".to_string(),
+                errors => format!("Not reloaded, the config has {errors} errors"),
+            };
+        }
+        let mut comment = match self.changed.as_slice() {
+            [] => "Reloaded, nothing changed".to_string(),
+            changed => format!("Reloaded, changed {}", changed.join(", ")),
+        };
+        if !self.needs_restart.is_empty() {
+            comment.push_str(&format!(
+                ". Restart mcsc-server to apply {}",
+                self.needs_restart.join(", ")
+            ));
+        }
+        comment
+    }
+}
+
+/// Read the config file again and switch to it, fields in RESTART_FIELDS keep their old values.
+/// Nothing changes when the new config has errors
+fn config_reload() -> ConfigReload {
+    let (new, problems) = config_read(config_path());
+    let Some(mut new) = new else {
+        return ConfigReload {
+            problems,
+            ..Default::default()
+        };
+    };
+    let mut config = CONFIG.write();
+    // Fields are compared as toml, without that there's no telling what changed
+    let (old, mut fields) = match (
+        toml::Value::try_from(&**config),
+        toml::Value::try_from(&new),
+    ) {
+        (Ok(toml::Value::Table(old)), Ok(toml::Value::Table(fields))) => (old, fields),
+        (Err(error), _) | (_, Err(error)) => {
+            return config_reload_failed(problems, format!("Couldn't compare it: {error}"))
+        }
+        _ => return config_reload_failed(problems, "Couldn't compare it".to_string()),
+    };
+    let mut needs_restart = Vec::new();
+    for field in RESTART_FIELDS {
+        if old.get(*field) != fields.get(*field) {
+            needs_restart.push(field.to_string());
+            match old.get(*field) {
+                Some(value) => fields.insert(field.to_string(), value.clone()),
+                None => fields.remove(*field),
+            };
+        }
+    }
+    new = match <Config as serde::Deserialize>::deserialize(toml::Value::Table(fields.clone())) {
+        Ok(kept) => kept,
+        Err(error) => {
+            let message = format!("Couldn't keep the settings that need a restart: {error}");
+            return config_reload_failed(problems, message);
+        }
+    };
+    let mut changed: Vec<String> = old.keys().chain(fields.keys()).cloned().collect();
+    changed.sort();
+    changed.dedup();
+    changed.retain(|name| old.get(name) != fields.get(name));
+    if changed.iter().any(|field| field == "log_level") {
+        // Already validated with the rest of the config
+        let _ = logging::set_level(&new.log_level);
+    }
+    *config = std::sync::Arc::new(new);
+    ConfigReload {
+        applied: true,
+        changed,
+        needs_restart,
+        problems,
+    }
+}
+
+/// A reload that didn't happen, because of a problem with the reload rather than the config
+fn config_reload_failed(mut problems: Vec<ConfigProblem>, message: String) -> ConfigReload {
+    problems.push(ConfigProblem {
+        fatal: true,
+        location: config_path().display().to_string(),
+        message,
+    });
+    ConfigReload {
+        problems,
+        ..Default::default()
+    }
+}
+
+async fn reload_on_hangup() {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(error) => {
+            warn!("Couldn't listen for SIGHUP, the config can still be reloaded with ReloadConfig: {error}");
+            return;
+        }
+    };
+    while hangups.recv().await.is_some() {
+        let reload = config_reload();
+        for problem in &reload.problems {
+            warn!("{problem}");
+        }
+        info!("SIGHUP: {}", reload.describe());
+    }
+}
+
+/// List every problem with the config, the exit code for check-config
+fn check_config(path: &Path) -> i32 {
+    let (config, problems) = config_read(path);
+    for problem in &problems {
+        println!("{problem}");
+    }
+    let errors = problems.iter().filter(|problem| problem.fatal).count();
+    let describe = |count: usize, what: &str| match count {
+        1 => format!("1 {what}"),
+        count => format!("{count} {what}s"),
+    };
+    match (config, problems.len()) {
+        (Some(_), 0) => {
+            println!("{} is valid", path.display());
+            0
+        }
+        (Some(_), warnings) => {
+            let warnings = describe(warnings, "warning");
+            println!("{} is usable, with {warnings}", path.display());
+            0
+        }
+        (None, _) => {
+            println!("{} has {}", path.display(), describe(errors, "error"));
+            1
+        }
+    }
+}
+
+/// Write the example configs with the answers and a fresh key filled in, the exit code for init
+fn init(path: &Path, args: InitArgs) -> i32 {
+    match init_files(path, args) {
+        Ok(code) => code,
+        Err(error) => {
+            eprintln!("{error}");
+            1
+        }
+    }
+}
+
+fn init_files(path: &Path, args: InitArgs) -> Result<i32, String> {
+    let directory = path.parent().map(Path::to_path_buf).unwrap_or_default();
+    let client_path = directory.join(&args.client_config);
+    if !args.force {
+        for path in [path, client_path.as_path()] {
+            if path.exists() {
+                return Err(format!(
+                    "{} already exists, pass --force to replace it",
+                    path.display()
+                ));
+            }
+        }
+    }
+    let interactive = !args.yes && std::io::IsTerminal::is_terminal(&std::io::stdin());
+    let ask = |question: &str, given: Option<String>, default: String| -> Result<String, String> {
+        match given {
+            Some(answer) => Ok(answer),
+            None if !interactive => Ok(default),
+            None => {
+                print!("{question} [{default}]: ");
+                std::io::stdout()
+                    .flush()
+                    .map_err(|error| error.to_string())?;
+                let mut answer = String::new();
+                std::io::stdin()
+                    .read_line(&mut answer)
+                    .map_err(|error| error.to_string())?;
+                Ok(Some(answer.trim().to_string())
+                    .filter(|answer| !answer.is_empty())
+                    .unwrap_or(default))
+            }
+        }
+    };
+    let minecraft_directory = ask(
+        "Minecraft server directory",
+        args.minecraft_directory,
+        default_minecraft_directory(),
+    )?;
+    let backup_directory = ask(
+        "Backup directory, inside the minecraft directory",
+        args.backup_directory,
+        default_backup_directory(),
+    )?;
+    let socket = ask("Socket to serve on", args.socket, default_socket())?;
+    let bound = socket
+        .parse::<std::net::SocketAddr>()
+        .map_err(|error| format!("{socket} isn't a socket like 0.0.0.0:7878: {error}"))?;
+    // Serving on every interface, so the hostname is the best guess at how to get here
+    let default_address = if bound.ip().is_unspecified() {
+        let hostname = std::fs::read_to_string("/proc/sys/kernel/hostname")
+            .map(|name| name.trim().to_string())
+            .ok()
+            .filter(|name| !name.is_empty())
+            .unwrap_or_else(|| "localhost".to_string());
+        format!("{hostname}:{}", bound.port())
+    } else {
+        bound.to_string()
+    };
+    let address = ask(
+        "Address clients reach the server at",
+        args.address,
+        default_address,
+    )?;
+    let key = common::ran_letters(48);
+
+    let server = [
+        ("socket", &socket),
+        ("minecraft_directory", &minecraft_directory),
+        ("backup_directory", &backup_directory),
+        ("key", &key),
+    ]
+    .iter()
+    .fold(
+        include_str!("../mcsc_server.toml").to_string(),
+        |text, (field, value)| init_setting(&text, field, value),
+    );
+    let ip = format!("http://{address}");
+    let client = [("ip", &ip), ("key", &key)].iter().fold(
+        include_str!("../mcsc_client.toml").to_string(),
+        |text, (field, value)| init_setting(&text, field, value),
+    );
+    let backups = directory.join(&minecraft_directory).join(&backup_directory);
+    std::fs::create_dir_all(&backups)
+        .map_err(|error| format!("Couldn't create {}: {error}", backups.display()))?;
+    for (path, text) in [(path, server), (client_path.as_path(), client)] {
+        write_secret(path, &text)
+            .map_err(|error| format!("Couldn't write {}: {error}", path.display()))?;
+        println!("Wrote {}", path.display());
+    }
+    let code = check_config(path);
+    println!(
+        "Put the minecraft server and its launch.sh in {}, then friends can connect with:",
+        directory.join(&minecraft_directory).display()
+    );
+    println!(
+        "  mcsc-cli import {}",
+        common::connection_string(&address, &key, "")
+    );
+    println!(
+        "That holds the shared key, which can do everything. Add [[users]] to give friends less"
+    );
+    Ok(code)
+}
+
+/// Replace the value of a `field = value # comment` line, keeping the comment
+fn init_setting(text: &str, field: &str, value: &str) -> String {
+    let prefix = format!("{field} = ");
+    text.lines()
+        .map(|line| match line.strip_prefix(&prefix) {
+            Some(rest) => {
+                let value = toml::Value::String(value.to_string());
+                // The comment starts after the old value, which may have a # of its own
+                let comment = rest.match_indices(" #").map(|(at, _)| at).find(|at| {
+                    toml::from_str::<toml::Table>(&format!("value = {}", &rest[..*at])).is_ok()
+                });
+                match comment {
+                    Some(comment) => format!("{prefix}{value}{}\n", &rest[comment..]),
+                    None => format!("{prefix}{value}\n"),
+                }
+            }
+            None => format!("{line}\n"),
+        })
+        .collect()
+}
+
+/// Configs hold keys, so only their owner may read them
+fn write_secret(path: &Path, text: &str) -> std::io::Result<()> {
+    use std::os::unix::fs::OpenOptionsExt;
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    file.write_all(text.as_bytes())
+}
+
+/// Something wrong with the config, the server won't start when it's fatal
+#[derive(Debug)]
+struct ConfigProblem {
+    fatal: bool,
+    /// Where it is, eg. "mcsc_server.toml:4" or "$MCSC_SERVER_SOCKET"
+    location: String,
+    message: String,
+}
+
+impl std::fmt::Display for ConfigProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let severity = if self.fatal { "error" } else { "warning" };
+        write!(f, "{severity}: {}: {}", self.location, self.message)
+    }
+}
+
+/// Read the config at `path` with the environment's overrides, along with everything wrong with
+/// it. There's only a config when nothing fatal is
+fn config_read(path: &Path) -> (Option<Config>, Vec<ConfigProblem>) {
+    let fatal = |location: String, message: String| ConfigProblem {
+        fatal: true,
+        location,
+        message,
+    };
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(error) => {
+            let message = format!("Couldn't read it: {error}");
+            return (None, vec![fatal(path.display().to_string(), message)]);
+        }
+    };
+    let mut table: toml::Table = match toml::from_str(&text) {
+        Ok(table) => table,
+        Err(error) => {
+            let location = match error.span() {
+                Some(span) => format!("{}:{}", path.display(), line_at(&text, span.start)),
+                None => path.display().to_string(),
+            };
+            let message = error.message().trim().replace('\n', ", ");
+            return (None, vec![fatal(location, message)]);
+        }
+    };
+
+    // Environment variables win over the file
+    let mut overridden = HashMap::new();
+    for (variable, value) in std::env::vars() {
+        let Some(field) = variable.strip_prefix(CONFIG_ENV_PREFIX) else {
+            continue;
+        };
+        if field == "CONFIG" {
+            continue;
+        }
+        let field = field.to_lowercase();
+        // Typed when it parses as a toml value of the right type, eg. 30 or true, a string otherwise
+        let parsed = toml::from_str::<toml::Table>(&format!("value = {value}"))
+            .ok()
+            .and_then(|mut parsed| parsed.remove("value"))
+            .filter(|parsed| config_field_error(&field, parsed).is_none());
+        table.insert(field.clone(), parsed.unwrap_or(toml::Value::String(value)));
+        overridden.insert(field, variable);
+    }
+    let locate = |field: &str, nth: usize| match overridden.get(field) {
+        Some(variable) => format!("${variable}"),
+        None => match config_line(&text, field, nth) {
+            Some(line) => format!("{}:{line}", path.display()),
+            None => path.display().to_string(),
+        },
+    };
+
+    // Check each field on its own so every bad one is reported, not just the first. They're left
+    // out after, so the rest can still be checked
+    let mut problems = Vec::new();
+    // Where each user that's left was in [[users]], for locating the problems found with them later
+    let mut kept_users = Vec::new();
+    let fields: Vec<String> = table.keys().cloned().collect();
+    for field in fields {
+        if let Some(toml::Value::Array(users)) = table.get_mut("users").filter(|_| field == "users")
+        {
+            let mut index = 0;
+            users.retain(|user| {
+                let error = <User as serde::Deserialize>::deserialize(user.clone()).err();
+                match &error {
+                    Some(error) => {
+                        let message = format!("users: {}", error.message());
+                        problems.push(fatal(locate("users", index), message));
+                    }
+                    None => kept_users.push(index),
+                }
+                index += 1;
+                error.is_none()
+            });
+        } else if let Some(error) = config_field_error(&field, &table[&field]) {
+            problems.push(fatal(locate(&field, 0), format!("{field}: {error}")));
+            table.remove(&field);
+        }
+    }
+    let mut unknown = Vec::new();
+    let config: Config = match serde_ignored::deserialize(toml::Value::Table(table), |field| {
+        unknown.push(field.to_string())
+    }) {
+        Ok(config) => config,
+        Err(error) => {
+            let error: toml::de::Error = error;
+            problems.push(fatal(locate("", 0), error.message().to_string()));
+            return (None, problems);
+        }
+    };
+    for field in unknown {
+        let top = field.split('.').next().unwrap_or_default();
+        problems.push(ConfigProblem {
+            fatal: false,
+            location: locate(top, 0),
+            message: format!("{field} isn't a setting, it's ignored"),
+        });
+    }
+    for (field, nth, fatal, message) in config_problems(&config) {
+        let nth = match field {
+            "users" => kept_users.get(nth).copied().unwrap_or(nth),
+            _ => nth,
+        };
+        problems.push(ConfigProblem {
+            fatal,
+            location: locate(field, nth),
+            message: format!("{field}: {message}"),
+        });
+    }
+    let usable = !problems.iter().any(|problem| problem.fatal);
+    (usable.then_some(config), problems)
+}
+
+/// Why a single field doesn't parse, if it doesn't
+fn config_field_error(field: &str, value: &toml::Value) -> Option<String> {
+    // The key is the only field without a default
+    let mut table = toml::Table::from_iter([("key".to_string(), "".into())]);
+    table.insert(field.to_string(), value.clone());
+    <Config as serde::Deserialize>::deserialize(toml::Value::Table(table))
+        .err()
+        .map(|error| error.message().to_string())
+}
+
+/// Line of the config where a top level field is set, the nth [[users]] table for users
+fn config_line(text: &str, field: &str, nth: usize) -> Option<usize> {
+    let lines = text.lines().enumerate();
+    if field == "users" {
+        return lines
+            .filter(|(_, line)| line.trim_start().starts_with("[[users]]"))
+            .nth(nth)
+            .map(|(index, _)| index + 1);
+    }
+    // Top level fields come before the first table
+    lines
+        .take_while(|(_, line)| !line.trim_start().starts_with('['))
+        .find(|(_, line)| {
+            line.trim_start()
+                .strip_prefix(field)
+                .is_some_and(|rest| rest.trim_start().starts_with('='))
+        })
+        .map(|(index, _)| index + 1)
+}
+
+fn line_at(text: &str, offset: usize) -> usize {
+    text[..offset.min(text.len())].matches('\n').count() + 1
+}
+
+/// Problems with values that parse fine, as the field, which of its entries, whether it's fatal
+/// and what's wrong
+fn config_problems(config: &Config) -> Vec<(&'static str, usize, bool, String)> {
+    let mut problems = Vec::new();
+    if config.key.is_empty() {
+        problems.push(("key", 0, true, "The shared key can't be empty".to_string()));
+    }
+    let sockets = [
+        ("socket", Some(&config.socket)),
+        ("web_socket", config.web_socket.as_ref()),
+        ("prometheus_socket", config.prometheus_socket.as_ref()),
+    ];
+    for (field, socket) in sockets {
+        if let Some(socket) = socket {
+            if socket.parse::<std::net::SocketAddr>().is_err() {
+                let message = format!("{socket} isn't an ip and port, eg. 0.0.0.0:7878");
+                problems.push((field, 0, true, message));
+            }
+        }
+    }
+    let minecraft_directory = config_directory().join(&config.minecraft_directory);
+    if !minecraft_directory.is_dir() {
+        let message = format!("{} isn't a directory", minecraft_directory.display());
+        problems.push(("minecraft_directory", 0, true, message));
+    } else if !minecraft_directory.join(&config.backup_directory).is_dir() {
+        let message = format!(
+            "{} doesn't exist in the minecraft directory, backups will fail until it's created",
+            config.backup_directory
+        );
+        problems.push(("backup_directory", 0, false, message));
+    }
+    if config.idle_shutdown_minutes == Some(0) {
+        let message = "Should be at least 1, leave it out to never stop".to_string();
+        problems.push(("idle_shutdown_minutes", 0, true, message));
+    }
+    if let Err(error) = logging::parse_level(&config.log_level) {
+        problems.push(("log_level", 0, true, error));
+    }
+    if config.metrics_interval > 0 && config.metrics_history == 0 {
+        let message = "Should be at least 1 while metrics_interval is on".to_string();
+        problems.push(("metrics_history", 0, true, message));
+    }
+    let actions: Vec<AuthAction> = (0..64).filter_map(AuthAction::from_i32).collect();
+    for (index, user) in config.users.iter().enumerate() {
+        if user.name.is_empty() {
+            problems.push(("users", index, true, "A user needs a name".to_string()));
+        } else if config.users[..index]
+            .iter()
+            .any(|other| other.name == user.name)
+        {
+            let message = format!("There's already a user called {}", user.name);
+            problems.push(("users", index, true, message));
+        }
+        if user.key.is_empty() {
+            let message = format!("{} needs a key", user.name);
+            problems.push(("users", index, true, message));
+        } else if user.key == config.key {
+            let message = format!("{} has the shared key, which anyone may use", user.name);
+            problems.push(("users", index, false, message));
+        }
+        for action in user.actions.iter().flatten() {
+            if !actions
+                .iter()
+                .any(|known| known.as_str_name().eq_ignore_ascii_case(action))
+            {
+                let message = format!("{action} isn't an action, eg. Launch, Stop or Backup");
+                problems.push(("users", index, true, message));
+            }
+        }
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn init_setting_replaces_values() {
+        let text = "socket = \"0.0.0.0:7878\"\nkey = \"\"\n";
+        assert_eq!(
+            init_setting(text, "key", "secret"),
+            "socket = \"0.0.0.0:7878\"\nkey = \"secret\"\n"
+        );
+    }
+
+    #[test]
+    fn init_setting_keeps_comments() {
+        let text = "key = \"\" # Everyone with it can do anything\n# key = \"old\"\n";
+        assert_eq!(
+            init_setting(text, "key", "secret"),
+            "key = \"secret\" # Everyone with it can do anything\n# key = \"old\"\n"
+        );
+        let text = "key = \"a # b\" # The key\n";
+        assert_eq!(init_setting(text, "key", "c"), "key = \"c\" # The key\n");
+    }
+
+    #[test]
+    fn init_setting_quotes_values() {
+        assert_eq!(
+            init_setting("key = \"\"\n", "key", "say \"hi\""),
+            "key = \"say \\\"hi\\\"\"\n"
+        );
+        // Only whole field names
+        assert_eq!(init_setting("keys = 1\n", "key", "secret"), "keys = 1\n");
+    }
+
+    #[test]
+    fn init_fills_in_the_example_configs() {
+        let server = [
+            ("socket", "127.0.0.1:7999"),
+            ("minecraft_directory", "mc"),
+            ("key", "secret # not a comment"),
+        ]
+        .iter()
+        .fold(
+            include_str!("../mcsc_server.toml").to_string(),
+            |text, (field, value)| init_setting(&text, field, value),
+        );
+        let config: Config = toml::from_str(&server).unwrap();
+        assert_eq!(config.socket, "127.0.0.1:7999");
+        assert_eq!(config.minecraft_directory, "mc");
+        assert_eq!(config.key, "secret # not a comment");
+    }
 }
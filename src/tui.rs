@@ -0,0 +1,499 @@
+use crate::actions::{
+    controller_client::ControllerClient, AuthAction, BackupRequest, CommandRequest, ConsoleRequest,
+    LaunchRequest, ServerPhase, StatusRequest, StatusResponce, StopRequest,
+};
+use crate::client::{self, Config};
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout},
+    style::{Color, Style, Stylize},
+    text::{Line, Span},
+    widgets::{Block, Paragraph},
+    Frame,
+};
+use std::time::Duration;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+use tonic::transport::Channel;
+
+/// How often to ask the server how it's doing
+const STATUS_INTERVAL: Duration = Duration::from_secs(5);
+/// How many console lines to keep for scrolling back
+const CONSOLE_LINES: usize = 5000;
+/// How many lines of console history to ask for when starting
+const CONSOLE_HISTORY: u32 = 200;
+
+/// Vanilla commands, for tab completion of the first word
+const MINECRAFT_COMMANDS: &[&str] = &[
+    "advancement",
+    "attribute",
+    "ban",
+    "ban-ip",
+    "banlist",
+    "bossbar",
+    "clear",
+    "clone",
+    "damage",
+    "data",
+    "datapack",
+    "debug",
+    "defaultgamemode",
+    "deop",
+    "difficulty",
+    "effect",
+    "enchant",
+    "execute",
+    "experience",
+    "fill",
+    "fillbiome",
+    "forceload",
+    "function",
+    "gamemode",
+    "gamerule",
+    "give",
+    "help",
+    "item",
+    "jfr",
+    "kick",
+    "kill",
+    "list",
+    "locate",
+    "loot",
+    "me",
+    "msg",
+    "op",
+    "pardon",
+    "pardon-ip",
+    "particle",
+    "perf",
+    "place",
+    "playsound",
+    "random",
+    "recipe",
+    "reload",
+    "return",
+    "ride",
+    "save-all",
+    "save-off",
+    "save-on",
+    "say",
+    "schedule",
+    "scoreboard",
+    "seed",
+    "setblock",
+    "setidletimeout",
+    "setworldspawn",
+    "spawnpoint",
+    "spectate",
+    "spreadplayers",
+    "stop",
+    "stopsound",
+    "summon",
+    "tag",
+    "team",
+    "teammsg",
+    "teleport",
+    "tell",
+    "tellraw",
+    "tick",
+    "time",
+    "title",
+    "tm",
+    "tp",
+    "transfer",
+    "trigger",
+    "w",
+    "weather",
+    "whitelist",
+    "worldborder",
+    "xp",
+];
+
+enum Update {
+    Terminal(Event),
+    Status(Result<StatusResponce, String>),
+    ConsoleLine(String),
+    /// How an operation like Launch went
+    Message(String),
+}
+
+/// Operations replied to with an OpResponce
+enum Op {
+    Launch,
+    Stop { force: bool },
+    Backup,
+    Command(String),
+}
+
+#[derive(Default)]
+struct Tui {
+    status: Option<Result<StatusResponce, String>>,
+    console: Vec<String>,
+    /// How many lines up from the bottom the console is scrolled
+    scroll: usize,
+    /// Height of the console pane at the last draw, for paging
+    console_height: usize,
+    input: String,
+    history: Vec<String>,
+    /// Position in `history` while going through it with the arrow keys
+    history_index: Option<usize>,
+    message: String,
+    /// Someone is online and F3 was pressed once already
+    confirm_stop: bool,
+    quit: bool,
+}
+
+/// Full screen view of the console, runs until the user quits
+pub async fn run(client: ControllerClient<Channel>, config: &Config) -> Result<(), tonic::Status> {
+    let (send, mut updates) = unbounded_channel();
+
+    // Terminal input blocks, so it gets its own thread
+    let keys = send.clone();
+    std::thread::spawn(move || {
+        while let Ok(event) = event::read() {
+            if keys.send(Update::Terminal(event)).is_err() {
+                break;
+            }
+        }
+    });
+    let console = tokio::spawn(follow_console(client.clone(), config.clone(), send.clone()));
+    let status = tokio::spawn(poll_status(client.clone(), config.clone(), send.clone()));
+
+    let mut terminal = ratatui::init();
+    let mut tui = Tui::default();
+    let result = async {
+        while !tui.quit {
+            terminal.draw(|frame| tui.draw(frame))?;
+            let Some(update) = updates.recv().await else {
+                break;
+            };
+            tui.update(update, &client, config, &send);
+            // Catch up on everything that's waiting before drawing again
+            while let Ok(update) = updates.try_recv() {
+                tui.update(update, &client, config, &send);
+            }
+        }
+        Ok::<_, std::io::Error>(())
+    }
+    .await;
+    ratatui::restore();
+    console.abort();
+    status.abort();
+    result.map_err(|error| tonic::Status::internal(format!("Terminal error: {error}")))
+}
+
+impl Tui {
+    fn update(
+        &mut self,
+        update: Update,
+        client: &ControllerClient<Channel>,
+        config: &Config,
+        send: &UnboundedSender<Update>,
+    ) {
+        match update {
+            Update::Terminal(Event::Key(key)) if key.kind == KeyEventKind::Press => {
+                self.key(key, client, config, send)
+            }
+            Update::Terminal(_) => {}
+            Update::Status(status) => self.status = Some(status),
+            Update::ConsoleLine(line) => {
+                self.console.push(line);
+                if self.console.len() > CONSOLE_LINES {
+                    self.console.remove(0);
+                }
+                // Keep the same lines on screen while scrolled back
+                if self.scroll > 0 {
+                    self.scroll = (self.scroll + 1).min(self.console.len());
+                }
+            }
+            Update::Message(message) => self.message = message,
+        }
+    }
+
+    fn key(
+        &mut self,
+        key: KeyEvent,
+        client: &ControllerClient<Channel>,
+        config: &Config,
+        send: &UnboundedSender<Update>,
+    ) {
+        let confirm_stop = std::mem::take(&mut self.confirm_stop);
+        let page = (self.console_height / 2).max(1);
+        match key.code {
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => self.quit = true,
+            KeyCode::F(10) | KeyCode::Esc => self.quit = true,
+            KeyCode::F(2) => {
+                self.message = "Launching...".to_string();
+                spawn_op(client, config, send, Op::Launch);
+            }
+            KeyCode::F(3) => {
+                let online = match &self.status {
+                    Some(Ok(status)) => status.online,
+                    _ => 0,
+                };
+                if online > 0 && !confirm_stop {
+                    self.message = format!(
+                        "{online} online, press F3 again to stop anyway, anything else to cancel"
+                    );
+                    self.confirm_stop = true;
+                } else {
+                    self.message = "Stopping...".to_string();
+                    let force = confirm_stop;
+                    spawn_op(client, config, send, Op::Stop { force });
+                }
+            }
+            KeyCode::F(4) => {
+                self.message = "Backing up...".to_string();
+                spawn_op(client, config, send, Op::Backup);
+            }
+            KeyCode::PageUp => self.scroll = (self.scroll + page).min(self.console.len()),
+            KeyCode::PageDown => self.scroll = self.scroll.saturating_sub(page),
+            KeyCode::End => self.scroll = 0,
+            KeyCode::Up => {
+                let index = match self.history_index {
+                    Some(index) => index.saturating_sub(1),
+                    None => self.history.len().saturating_sub(1),
+                };
+                if let Some(command) = self.history.get(index) {
+                    self.input = command.clone();
+                    self.history_index = Some(index);
+                }
+            }
+            KeyCode::Down => match self.history_index {
+                Some(index) if index + 1 < self.history.len() => {
+                    self.input = self.history[index + 1].clone();
+                    self.history_index = Some(index + 1);
+                }
+                Some(_) => {
+                    self.input.clear();
+                    self.history_index = None;
+                }
+                None => {}
+            },
+            KeyCode::Tab => {
+                let players = match &self.status {
+                    Some(Ok(status)) => status.players.clone(),
+                    _ => Vec::new(),
+                };
+                let (input, candidates) = complete(&self.input, &players);
+                self.input = input;
+                self.message = candidates.join(" ");
+            }
+            KeyCode::Backspace => {
+                self.input.pop();
+            }
+            KeyCode::Enter => {
+                let command = std::mem::take(&mut self.input).trim().to_string();
+                self.history_index = None;
+                if command.is_empty() {
+                    return;
+                }
+                if self.history.last() != Some(&command) {
+                    self.history.push(command.clone());
+                }
+                self.scroll = 0;
+                spawn_op(client, config, send, Op::Command(command));
+            }
+            KeyCode::Char(character) => self.input.push(character),
+            _ => {}
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [status_area, console_area, input_area, help_area] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Min(3),
+            Constraint::Length(3),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        let status = match &self.status {
+            Some(Ok(status)) => {
+                let phase = ServerPhase::from_i32(status.phase).unwrap_or_default();
+                let colour = match phase {
+                    ServerPhase::Ready => Color::Green,
+                    ServerPhase::Idle => Color::DarkGray,
+                    _ => Color::Yellow,
+                };
+                let mut spans = vec![
+                    Span::styled(
+                        format!(" {} ", phase.as_str_name()),
+                        Style::new().bg(colour),
+                    ),
+                    Span::raw(format!(" {}/{} players ", status.online, status.max)),
+                ];
+                if !status.version.is_empty() {
+                    spans.push(Span::raw(format!("| {} ", status.version)));
+                }
+                spans.push(Span::raw(format!("| {}", self.message)));
+                Line::from(spans)
+            }
+            Some(Err(error)) => Line::from(vec![
+                Span::styled(" Unreachable ", Style::new().bg(Color::Red)),
+                Span::raw(format!(" {error} | {}", self.message)),
+            ]),
+            None => Line::from(format!(" ... | {}", self.message)),
+        };
+        frame.render_widget(Paragraph::new(status), status_area);
+
+        self.console_height = console_area.height.saturating_sub(2) as usize;
+        let end = self.console.len() - self.scroll.min(self.console.len());
+        let start = end.saturating_sub(self.console_height);
+        let lines: Vec<Line> = self.console[start..end]
+            .iter()
+            .map(|line| Line::raw(line.as_str()))
+            .collect();
+        let title = if self.scroll > 0 {
+            format!(" Console, {} lines back ", self.scroll)
+        } else {
+            " Console ".to_string()
+        };
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title(title)),
+            console_area,
+        );
+
+        frame.render_widget(
+            Paragraph::new(self.input.as_str()).block(Block::bordered().title(" Command ")),
+            input_area,
+        );
+        frame.set_cursor_position((
+            input_area.x + 1 + self.input.chars().count() as u16,
+            input_area.y + 1,
+        ));
+
+        frame.render_widget(
+            Paragraph::new(
+                " F2 launch  F3 stop  F4 backup  PgUp/PgDn scroll  Tab complete  F10 quit",
+            )
+            .dim(),
+            help_area,
+        );
+    }
+}
+
+/// Complete the last word of `input`, commands for the first word and online players after it.
+/// Gives the new input and the candidates when there's more than one
+fn complete(input: &str, players: &[String]) -> (String, Vec<String>) {
+    let (before, word) = match input.rsplit_once(' ') {
+        Some((before, word)) => (format!("{before} "), word),
+        None => (String::new(), input),
+    };
+    let candidates: Vec<String> = if before.is_empty() {
+        MINECRAFT_COMMANDS
+            .iter()
+            .filter(|command| command.starts_with(word))
+            .map(|command| command.to_string())
+            .collect()
+    } else {
+        players
+            .iter()
+            .filter(|player| player.to_lowercase().starts_with(&word.to_lowercase()))
+            .cloned()
+            .collect()
+    };
+    match candidates.as_slice() {
+        [] => (input.to_string(), Vec::new()),
+        [only] => (format!("{before}{only} "), Vec::new()),
+        [first, rest @ ..] => {
+            // As far as the candidates agree
+            let mut common = first.len();
+            for candidate in rest {
+                common = first
+                    .chars()
+                    .zip(candidate.chars())
+                    .take_while(|(a, b)| a == b)
+                    .map(|(a, _)| a.len_utf8())
+                    .sum::<usize>()
+                    .min(common);
+            }
+            let completed = if common > word.len() {
+                &first[..common]
+            } else {
+                word
+            };
+            (format!("{before}{completed}"), candidates)
+        }
+    }
+}
+
+/// Run an operation in the background, its comment comes back as a message
+fn spawn_op(
+    client: &ControllerClient<Channel>,
+    config: &Config,
+    send: &UnboundedSender<Update>,
+    op: Op,
+) {
+    let mut client = client.clone();
+    let config = config.clone();
+    let send = send.clone();
+    tokio::spawn(async move {
+        let result: Result<String, tonic::Status> = async {
+            let action = match op {
+                Op::Launch => AuthAction::Launch,
+                Op::Stop { .. } => AuthAction::Stop,
+                Op::Backup => AuthAction::Backup,
+                Op::Command(_) => AuthAction::Command,
+            };
+            let token = client::auth(&mut client, action, &config).await?;
+            let responce = match op {
+                Op::Launch => client.launch(LaunchRequest { token, wait: true }).await?,
+                Op::Stop { force } => client.stop(StopRequest { token, force }).await?,
+                Op::Backup => client.backup(BackupRequest { token }).await?,
+                Op::Command(command) => client.command(CommandRequest { token, command }).await?,
+            };
+            Ok(responce.into_inner().comment)
+        }
+        .await;
+        let _ = send.send(Update::Message(
+            result.unwrap_or_else(|status| status.message().to_string()),
+        ));
+    });
+}
+
+async fn poll_status(
+    mut client: ControllerClient<Channel>,
+    config: Config,
+    send: UnboundedSender<Update>,
+) {
+    loop {
+        let status = async {
+            let token = client::auth(&mut client, AuthAction::Status, &config).await?;
+            Ok::<_, tonic::Status>(client.status(StatusRequest { token }).await?.into_inner())
+        }
+        .await
+        .map_err(|status| status.message().to_string());
+        if send.send(Update::Status(status)).is_err() {
+            return;
+        }
+        tokio::time::sleep(STATUS_INTERVAL).await;
+    }
+}
+
+async fn follow_console(
+    mut client: ControllerClient<Channel>,
+    config: Config,
+    send: UnboundedSender<Update>,
+) {
+    let result = async {
+        let token = client::auth(&mut client, AuthAction::Console, &config).await?;
+        let mut lines = client
+            .console(ConsoleRequest {
+                token,
+                history: CONSOLE_HISTORY,
+            })
+            .await?
+            .into_inner();
+        while let Some(line) = lines.message().await? {
+            if send.send(Update::ConsoleLine(line.line)).is_err() {
+                break;
+            }
+        }
+        Ok::<_, tonic::Status>("The console stream ended".to_string())
+    }
+    .await;
+    let _ = send.send(Update::ConsoleLine(format!(
+        "[{}]",
+        result.unwrap_or_else(|status| status.message().to_string())
+    )));
+}
@@ -0,0 +1,349 @@
+use serde_derive::Deserialize;
+use sha1::{Digest, Sha1};
+use std::{
+    fs,
+    io::Read,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// Records which version the server jar is, next to the jar
+const INSTALLED_FILE: &str = ".mcsc-version";
+
+/// Mojang's version_manifest_v2.json
+#[derive(Deserialize, Debug)]
+pub struct Manifest {
+    pub latest: Latest,
+    pub versions: Vec<ManifestVersion>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Latest {
+    pub release: String,
+    pub snapshot: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ManifestVersion {
+    pub id: String,
+    /// "release", "snapshot", "old_beta" or "old_alpha"
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// Location of the version json, may be relative to the manifest for local mirrors
+    pub url: String,
+    #[serde(rename = "releaseTime")]
+    pub release_time: String,
+}
+
+/// The parts of a version json we need
+#[derive(Deserialize, Debug)]
+struct VersionInfo {
+    downloads: Downloads,
+    #[serde(rename = "javaVersion")]
+    java_version: Option<JavaVersion>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Downloads {
+    server: Option<Download>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Download {
+    sha1: String,
+    url: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct JavaVersion {
+    #[serde(rename = "majorVersion")]
+    major_version: u32,
+}
+
+/// Numbers downloads so two at once don't write the same file
+static DOWNLOADS: AtomicU64 = AtomicU64::new(0);
+
+/// A verified server jar waiting next to the current one, removed when dropped without being
+/// swapped in
+#[derive(Debug)]
+pub struct Downloaded {
+    version: String,
+    java_version: Option<u32>,
+    /// The jar it replaces
+    jar: String,
+    path: String,
+}
+
+impl Drop for Downloaded {
+    fn drop(&mut self) {
+        // Already gone once it's been swapped in
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// A freshly installed server jar
+#[derive(Debug)]
+pub struct Installed {
+    pub version: String,
+    /// Java major version required by this minecraft version, if the manifest says
+    pub java_version: Option<u32>,
+    /// Where the jar that was replaced got moved to
+    pub previous: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum VersionError {
+    /// Couldn't read the manifest, version json or jar from the given location
+    Fetch(String),
+    /// The manifest or version json wasn't valid
+    Parse,
+    /// The manifest doesn't list the version
+    UnknownVersion,
+    /// The version has no server jar, eg. very old versions
+    NoServerJar,
+    /// The downloaded jar doesn't match the sha1 from the manifest
+    Checksum,
+    /// Failed to write the jar
+    Io,
+}
+
+/// Read and parse the version manifest
+pub fn manifest(location: &str) -> Result<Manifest, VersionError> {
+    let bytes = fetch(location)?;
+    serde_json::from_slice(&bytes).map_err(|_| VersionError::Parse)
+}
+
+/// The version of `jar` recorded by the last install, if any
+pub fn installed(jar: &str) -> Option<String> {
+    fs::read_to_string(installed_file(jar))
+        .ok()
+        .map(|version| version.trim().to_string())
+        .filter(|version| !version.is_empty())
+}
+
+/// Download the server jar of `version` next to `jar`, checking it against the sha1 from the
+/// manifest. "latest" and "latest-snapshot" select the newest release or snapshot. Blocks on the
+/// network, swap puts it in place after
+pub fn download(location: &str, version: &str, jar: &str) -> Result<Downloaded, VersionError> {
+    let manifest = manifest(location)?;
+    let id = match version {
+        "latest" | "" => manifest.latest.release.as_str(),
+        "latest-snapshot" => manifest.latest.snapshot.as_str(),
+        id => id,
+    };
+    let entry = manifest
+        .versions
+        .iter()
+        .find(|entry| entry.id == id)
+        .ok_or(VersionError::UnknownVersion)?;
+
+    let version_url = resolve(location, &entry.url);
+    let info: VersionInfo =
+        serde_json::from_slice(&fetch(&version_url)?).map_err(|_| VersionError::Parse)?;
+    let server = info.downloads.server.ok_or(VersionError::NoServerJar)?;
+    let bytes = fetch(&resolve(&version_url, &server.url))?;
+    if sha1_hex(&bytes) != server.sha1.to_lowercase() {
+        return Err(VersionError::Checksum);
+    }
+
+    let downloaded = Downloaded {
+        version: entry.id.clone(),
+        java_version: info.java_version.map(|java| java.major_version),
+        jar: jar.to_string(),
+        path: format!(
+            "{jar}.{}.download",
+            DOWNLOADS.fetch_add(1, Ordering::Relaxed)
+        ),
+    };
+    fs::write(&downloaded.path, &bytes).map_err(|_| VersionError::Io)?;
+    Ok(downloaded)
+}
+
+/// Switch to a downloaded jar, keeping the old one around so the switch can be undone by hand
+pub fn swap(downloaded: Downloaded) -> Result<Installed, VersionError> {
+    let jar = downloaded.jar.as_str();
+    let previous = if Path::new(jar).exists() {
+        let old = format!(
+            "{jar}.{}",
+            installed(jar).unwrap_or_else(|| "previous".to_string())
+        );
+        fs::rename(jar, &old).map_err(|_| VersionError::Io)?;
+        Some(old)
+    } else {
+        None
+    };
+    fs::rename(&downloaded.path, jar).map_err(|_| VersionError::Io)?;
+    fs::write(installed_file(jar), format!("{}\n", downloaded.version))
+        .map_err(|_| VersionError::Io)?;
+
+    Ok(Installed {
+        version: downloaded.version.clone(),
+        java_version: downloaded.java_version,
+        previous,
+    })
+}
+
+fn installed_file(jar: &str) -> PathBuf {
+    Path::new(jar).with_file_name(INSTALLED_FILE)
+}
+
+/// Read a http(s) url, a file:// url or a plain path
+pub fn fetch(location: &str) -> Result<Vec<u8>, VersionError> {
+    if location.starts_with("http://") || location.starts_with("https://") {
+        let response = ureq::get(location)
+            .call()
+            .map_err(|error| VersionError::Fetch(error.to_string()))?;
+        let mut bytes = Vec::new();
+        response
+            .into_reader()
+            .read_to_end(&mut bytes)
+            .map_err(|error| VersionError::Fetch(error.to_string()))?;
+        Ok(bytes)
+    } else {
+        let path = location.strip_prefix("file://").unwrap_or(location);
+        fs::read(path).map_err(|error| VersionError::Fetch(format!("{path}: {error}")))
+    }
+}
+
+/// Resolve `url` against the location it was listed in, so mirrors can use relative paths
+pub fn resolve(base: &str, url: &str) -> String {
+    if url.contains("://") || url.starts_with('/') {
+        return url.to_string();
+    }
+    match base.rfind('/') {
+        Some(end) => format!("{}/{}", &base[..end], url),
+        None => url.to_string(),
+    }
+}
+
+pub fn sha1_hex(bytes: &[u8]) -> String {
+    Sha1::digest(bytes)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A mirror with a manifest listing 1.20 and 1.21, whose server jar is `jar`. 1.21's version
+    /// json claims `sha1` for it
+    fn mirror(name: &str, jar: &[u8], sha1: &str) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("mcsc-versions-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(directory.join("mirror/versions")).unwrap();
+        fs::create_dir_all(directory.join("minecraft")).unwrap();
+        let manifest = serde_json::json!({
+            "latest": { "release": "1.21", "snapshot": "1.21" },
+            "versions": [
+                { "id": "1.21", "type": "release", "url": "versions/1.21.json", "releaseTime": "" },
+                { "id": "1.20", "type": "release", "url": "versions/1.20.json", "releaseTime": "" },
+            ],
+        });
+        fs::write(directory.join("mirror/manifest.json"), manifest.to_string()).unwrap();
+        let version = serde_json::json!({
+            "downloads": { "server": { "sha1": sha1, "url": "server-1.21.jar" } },
+            "javaVersion": { "majorVersion": 21 },
+        });
+        fs::write(
+            directory.join("mirror/versions/1.21.json"),
+            version.to_string(),
+        )
+        .unwrap();
+        // Too old to have a server jar
+        fs::write(
+            directory.join("mirror/versions/1.20.json"),
+            r#"{"downloads":{}}"#,
+        )
+        .unwrap();
+        fs::write(directory.join("mirror/versions/server-1.21.jar"), jar).unwrap();
+        directory
+    }
+
+    fn location(directory: &Path) -> String {
+        format!(
+            "file://{}",
+            directory.join("mirror/manifest.json").display()
+        )
+    }
+
+    fn jar(directory: &Path) -> String {
+        directory.join("minecraft/server.jar").display().to_string()
+    }
+
+    #[test]
+    fn installs_verified_jars_and_keeps_the_previous_one() {
+        let directory = mirror("install", b"new jar", &sha1_hex(b"new jar"));
+        let jar = jar(&directory);
+        fs::write(&jar, "old jar").unwrap();
+        fs::write(installed_file(&jar), "1.19\n").unwrap();
+
+        let downloaded = download(&location(&directory), "latest", &jar).unwrap();
+        // Nothing changes until the swap
+        assert_eq!(fs::read(&jar).unwrap(), b"old jar");
+        let swapped = swap(downloaded).unwrap();
+        assert_eq!(swapped.version, "1.21");
+        assert_eq!(swapped.java_version, Some(21));
+        assert_eq!(swapped.previous, Some(format!("{jar}.1.19")));
+        assert_eq!(fs::read(&jar).unwrap(), b"new jar");
+        assert_eq!(fs::read(format!("{jar}.1.19")).unwrap(), b"old jar");
+        assert_eq!(installed(&jar).as_deref(), Some("1.21"));
+        let _ = fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn rejects_jars_with_the_wrong_hash() {
+        let directory = mirror("checksum", b"tampered jar", &sha1_hex(b"new jar"));
+        let jar = jar(&directory);
+        fs::write(&jar, "old jar").unwrap();
+
+        let error = download(&location(&directory), "1.21", &jar).unwrap_err();
+        assert!(matches!(error, VersionError::Checksum));
+        assert_eq!(fs::read(&jar).unwrap(), b"old jar");
+        assert_eq!(installed(&jar), None);
+        // Only the old jar, no download left behind
+        assert_eq!(
+            fs::read_dir(directory.join("minecraft")).unwrap().count(),
+            1
+        );
+        let _ = fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn dropped_downloads_are_removed() {
+        let directory = mirror("dropped", b"new jar", &sha1_hex(b"new jar"));
+        let jar = jar(&directory);
+        let downloaded = download(&location(&directory), "1.21", &jar).unwrap();
+        assert!(Path::new(&downloaded.path).is_file());
+        drop(downloaded);
+        assert_eq!(
+            fs::read_dir(directory.join("minecraft")).unwrap().count(),
+            0
+        );
+        let _ = fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn unknown_and_jarless_versions() {
+        let directory = mirror("unknown", b"new jar", &sha1_hex(b"new jar"));
+        let jar = jar(&directory);
+        let error = download(&location(&directory), "1.2", &jar).unwrap_err();
+        assert!(matches!(error, VersionError::UnknownVersion));
+        let error = download(&location(&directory), "1.20", &jar).unwrap_err();
+        assert!(matches!(error, VersionError::NoServerJar));
+        let error = manifest("file:///nowhere/manifest.json").unwrap_err();
+        assert!(matches!(error, VersionError::Fetch(_)));
+        let _ = fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn resolves_relative_urls() {
+        let base = "https://example.com/mc/manifest.json";
+        assert_eq!(resolve(base, "v/1.json"), "https://example.com/mc/v/1.json");
+        assert_eq!(resolve(base, "/abs/1.json"), "/abs/1.json");
+        assert_eq!(resolve(base, "http://other/1.json"), "http://other/1.json");
+        assert_eq!(resolve("manifest.json", "v/1.json"), "v/1.json");
+    }
+}
@@ -0,0 +1,220 @@
+use crate::common::percent_decode;
+use serde_json::Value;
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::TcpStream,
+    time::Duration,
+};
+
+/// The whole dashboard, it talks to the json api below /api
+pub const INDEX: &str = include_str!("web/index.html");
+
+/// Logins and commands are small, anything bigger isn't from the dashboard
+const MAX_BODY: usize = 64 * 1024;
+/// Most the request line and headers together may take up
+const MAX_HEAD: u64 = 16 * 1024;
+
+/// An http request, just enough of one for the dashboard
+#[derive(Debug)]
+pub struct Request {
+    pub method: String,
+    /// Without the query string
+    pub path: String,
+    query: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    pub fn read(stream: &TcpStream) -> io::Result<Self> {
+        stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+        let mut reader = BufReader::new(stream);
+        let mut head = (&mut reader).take(MAX_HEAD);
+        let mut read_line = || {
+            let mut line = String::new();
+            head.read_line(&mut line)?;
+            if head.limit() == 0 && !line.ends_with('\n') {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "request head too big",
+                ));
+            }
+            Ok(line)
+        };
+        let request_line = read_line()?;
+        let mut parts = request_line.split_whitespace();
+        let method = parts.next().unwrap_or_default().to_string();
+        let target = parts.next().unwrap_or_default();
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let mut headers = Vec::new();
+        loop {
+            let line = read_line()?;
+            if line.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                headers.push((name.trim().to_lowercase(), value.trim().to_string()));
+            }
+        }
+        let mut request = Request {
+            method,
+            path: path.to_string(),
+            query: query.to_string(),
+            headers,
+            body: Vec::new(),
+        };
+        let length: usize = request
+            .header("content-length")
+            .and_then(|length| length.parse().ok())
+            .unwrap_or_default();
+        if length > MAX_BODY {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "request body too big",
+            ));
+        }
+        request.body = vec![0; length];
+        reader.read_exact(&mut request.body)?;
+        Ok(request)
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn cookie(&self, name: &str) -> Option<&str> {
+        self.header("cookie")?.split(';').find_map(|cookie| {
+            let (key, value) = cookie.split_once('=')?;
+            (key.trim() == name).then(|| value.trim())
+        })
+    }
+
+    /// A query string parameter, percent decoded. None when it's missing or badly escaped
+    pub fn query(&self, name: &str) -> Option<String> {
+        self.query.split('&').find_map(|pair| {
+            let (key, value) = pair.split_once('=')?;
+            // A + is a space in query strings, an escaped one is %2B
+            (key == name).then(|| percent_decode(&value.replace('+', " ")))
+        })?
+    }
+
+    /// The body as json, null when it isn't any
+    pub fn json(&self) -> Value {
+        serde_json::from_slice(&self.body).unwrap_or_default()
+    }
+}
+
+/// Send a whole response and close the connection
+pub fn respond(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+) -> io::Result<()> {
+    let mut head = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        body.len()
+    );
+    for (name, value) in headers {
+        head.push_str(&format!("{name}: {value}\r\n"));
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(body)
+}
+
+pub fn json(stream: &mut TcpStream, status: &str, value: &Value) -> io::Result<()> {
+    respond(
+        stream,
+        status,
+        "application/json",
+        &[],
+        value.to_string().as_bytes(),
+    )
+}
+
+/// Send the headers of a response whose body follows bit by bit until the connection closes
+pub fn start_stream(
+    stream: &mut TcpStream,
+    content_type: &str,
+    headers: &[(&str, &str)],
+) -> io::Result<()> {
+    let mut head = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: {content_type}\r\nCache-Control: no-cache\r\nConnection: close\r\n"
+    );
+    for (name, value) in headers {
+        head.push_str(&format!("{name}: {value}\r\n"));
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    /// Send `raw` to a fresh connection and read it back as a request
+    fn read(raw: Vec<u8>) -> io::Result<Request> {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let client = std::thread::spawn(move || {
+            let mut stream = TcpStream::connect(address).unwrap();
+            // The server may hang up before reading everything
+            let _ = stream.write_all(&raw);
+            stream
+        });
+        let (stream, _) = listener.accept().unwrap();
+        let request = Request::read(&stream);
+        drop(client.join());
+        request
+    }
+
+    #[test]
+    fn reads_requests() {
+        let raw = "POST /api/login?name=a+b%2Bc%21 HTTP/1.1\r\nCookie: x=1; mcsc_session=abc\r\n\
+                   Content-Length: 12\r\n\r\n{\"user\":\"a\"}";
+        let request = read(raw.as_bytes().to_vec()).unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/api/login");
+        assert_eq!(request.query("name").as_deref(), Some("a b+c!"));
+        assert_eq!(request.query("other"), None);
+        assert_eq!(request.cookie("mcsc_session"), Some("abc"));
+        assert_eq!(request.json()["user"], "a");
+    }
+
+    #[test]
+    fn bad_escapes_are_missing_parameters() {
+        let request = read(b"GET /api/download?name=%+1&b=%4 HTTP/1.1\r\n\r\n".to_vec()).unwrap();
+        assert_eq!(request.query("name"), None);
+        assert_eq!(request.query("b"), None);
+    }
+
+    #[test]
+    fn heads_are_bounded() {
+        let mut raw = b"GET / HTTP/1.1\r\n".to_vec();
+        while raw.len() <= MAX_HEAD as usize {
+            raw.extend_from_slice(b"X-Padding: aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa\r\n");
+        }
+        raw.extend_from_slice(b"\r\n");
+        let error = read(raw).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        // One endless line
+        let error = read(vec![b'a'; MAX_HEAD as usize * 2]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn bodies_are_bounded() {
+        let raw = format!(
+            "POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+            MAX_BODY + 1
+        );
+        let error = read(raw.into_bytes()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}